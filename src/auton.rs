@@ -0,0 +1,125 @@
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::{
+    brain::{Brain, PortError},
+    path::Path,
+};
+
+// how often the selected route file is checked for changes
+const FILE_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+// builds a route, claiming any motors it uses from the brain
+pub type RouteFn = fn(&mut Brain) -> Result<Path, PortError>;
+
+#[derive(Clone, Copy)]
+enum RouteSource {
+    Code(RouteFn),
+    // a route file loaded with Path::load
+    File(&'static str),
+}
+
+// The routes a robot can run keyed by the auton program selected on
+// the brain. Programs that aren't registered fall back to the first
+// route registered.
+#[derive(Default)]
+pub struct AutonSelector {
+    routes: Vec<(u8, &'static str, RouteSource)>,
+    // program the current route was built for
+    selected: Option<u8>,
+    // when the selected route file was last changed
+    modified: Option<SystemTime>,
+    last_poll: Option<Instant>,
+}
+
+impl AutonSelector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn register(self, program: u8, name: &'static str, route: RouteFn) -> Self {
+        self.add(program, name, RouteSource::Code(route))
+    }
+    // a route read from a file which is reloaded when the file changes
+    pub fn register_file(self, program: u8, name: &'static str, path: &'static str) -> Self {
+        self.add(program, name, RouteSource::File(path))
+    }
+    fn add(mut self, program: u8, name: &'static str, source: RouteSource) -> Self {
+        if let Some((_, other, _)) = self.routes.iter().find(|(p, _, _)| *p == program) {
+            log::warn!("Auton program {program} ({name}) replaces {other}.");
+            self.routes.retain(|(p, _, _)| *p != program);
+        }
+        self.routes.push((program, name, source));
+        self
+    }
+    // Builds the route for the selected program if the selection (or
+    // the selected route's file) has changed since the last call,
+    // otherwise None. This should only be called while disabled.
+    pub fn select(&mut self, brain: &mut Brain) -> Option<Path> {
+        let program = brain.auton_program();
+        if self.selected == Some(program) && !self.file_changed() {
+            return None;
+        }
+        let reselect = self.selected != Some(program);
+        self.selected = Some(program);
+
+        let Some((_, name, source)) = self.route(program) else {
+            log::error!("No auton routes registered.");
+            return None;
+        };
+        if reselect {
+            if !self.routes.iter().any(|(p, _, _)| *p == program) {
+                log::warn!("No route registered for auton program {program}.");
+            }
+            log::info!("Selected auton route {name} (program {program}).");
+        }
+        match source {
+            RouteSource::Code(route) => match route(brain) {
+                Ok(route) => Some(route),
+                Err(e) => {
+                    log::error!("Failed to build auton route {name}: {e}");
+                    None
+                }
+            },
+            RouteSource::File(path) => {
+                self.modified = modified(path);
+                self.last_poll = Some(Instant::now());
+                match Path::load(path) {
+                    Ok(route) => {
+                        log::info!("Loaded auton route {name} from {path}.");
+                        Some(route)
+                    }
+                    Err(e) => {
+                        log::error!("Failed to load auton route {name} from {path}: {e}");
+                        None
+                    }
+                }
+            }
+        }
+    }
+    // name of the route last built by select
+    pub fn selected_name(&self) -> Option<&'static str> {
+        self.route(self.selected?).map(|(_, name, _)| name)
+    }
+    fn route(&self, program: u8) -> Option<(u8, &'static str, RouteSource)> {
+        let route = self.routes.iter().find(|(p, _, _)| *p == program);
+        route.or(self.routes.first()).copied()
+    }
+    fn file_changed(&mut self) -> bool {
+        let Some((_, _, RouteSource::File(path))) = self.selected.and_then(|p| self.route(p))
+        else {
+            return false;
+        };
+        if self
+            .last_poll
+            .is_some_and(|t| t.elapsed() < FILE_POLL_INTERVAL)
+        {
+            return false;
+        }
+        self.last_poll = Some(Instant::now());
+        let modified = modified(path);
+        modified.is_some() && modified != self.modified
+    }
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
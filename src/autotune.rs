@@ -0,0 +1,111 @@
+use std::time::{Duration, Instant};
+
+use crate::pid::PidGains;
+
+// half periods of oscillation measured before the gains are worked out,
+// the first few are skipped while the oscillation builds up
+const HALF_PERIODS: usize = 10;
+const SKIPPED_HALF_PERIODS: usize = 2;
+// give up if the oscillation hasn't settled by then
+const TUNE_TIMEOUT: Duration = Duration::from_secs(20);
+
+// Relay auto-tuning (Åström–Hägglund). Instead of a controller a relay
+// drives the chassis, switching between +amplitude and -amplitude as
+// the heading crosses the setpoint, which makes the heading oscillate
+// at the ultimate period of the system. The ultimate gain follows from
+// the size of the oscillation and Ziegler–Nichols turns both into gains.
+pub struct RelayTune {
+    setpoint: f64,
+    // relay output, the same units as the controller output
+    amplitude: f64,
+    // error (rad) the relay waits for past the setpoint before
+    // switching so sensor noise doesn't switch it early
+    hysteresis: f64,
+    output: f64,
+    start: Instant,
+    last_switch: Option<Instant>,
+    // largest error since the last switch
+    extreme: f64,
+    half_periods: Vec<f64>,
+    extremes: Vec<f64>,
+    gains: Option<PidGains>,
+    finished: bool,
+}
+
+impl RelayTune {
+    pub fn new(setpoint: f64, amplitude: f64, hysteresis: f64) -> Self {
+        log::info!("Relay tuning started around {setpoint}rad.");
+        Self {
+            setpoint,
+            amplitude,
+            hysteresis,
+            output: amplitude,
+            start: Instant::now(),
+            last_switch: None,
+            extreme: 0.0,
+            half_periods: Vec::with_capacity(HALF_PERIODS),
+            extremes: Vec::with_capacity(HALF_PERIODS),
+            gains: None,
+            finished: false,
+        }
+    }
+    // the output to drive with, None once finished (see gains)
+    pub fn poll(&mut self, pv: f64) -> Option<f64> {
+        if self.finished {
+            return None;
+        }
+        if self.start.elapsed() > TUNE_TIMEOUT {
+            log::warn!("Relay tuning timed out, the heading didn't oscillate.");
+            self.finished = true;
+            return None;
+        }
+        let error = self.setpoint - pv;
+        self.extreme = self.extreme.max(error.abs());
+
+        let switch = (self.output < 0.0 && error > self.hysteresis)
+            || (self.output > 0.0 && error < -self.hysteresis);
+        if switch {
+            self.output = -self.output;
+            let now = Instant::now();
+            if let Some(last) = self.last_switch {
+                self.half_periods
+                    .push(now.duration_since(last).as_secs_f64());
+                self.extremes.push(self.extreme);
+            }
+            self.last_switch = Some(now);
+            self.extreme = 0.0;
+            if self.half_periods.len() == HALF_PERIODS {
+                self.finish();
+                return None;
+            }
+        }
+        Some(self.output)
+    }
+    fn finish(&mut self) {
+        self.finished = true;
+        let n = (HALF_PERIODS - SKIPPED_HALF_PERIODS) as f64;
+        let period = 2.0
+            * self.half_periods[SKIPPED_HALF_PERIODS..]
+                .iter()
+                .sum::<f64>()
+            / n;
+        let amplitude = self.extremes[SKIPPED_HALF_PERIODS..].iter().sum::<f64>() / n;
+        // the hysteresis shifts the switching so it is removed from the amplitude
+        let amplitude = (amplitude.powi(2) - self.hysteresis.powi(2))
+            .max(0.0)
+            .sqrt();
+        if amplitude <= 0.0 || period <= 0.0 {
+            log::warn!("Relay tuning measured no oscillation.");
+            return;
+        }
+        let ku = 4.0 * self.amplitude / (std::f64::consts::PI * amplitude);
+        // classic Ziegler–Nichols with ki and kd independent of kp
+        let kp = 0.6 * ku;
+        let gains = PidGains::new(kp, 2.0 * kp / period, kp * period / 8.0);
+        log::info!("Relay tuning found Ku {ku} and Tu {period}s, suggesting {gains:?}");
+        self.gains = Some(gains);
+    }
+    pub fn gains(&self) -> Option<PidGains> {
+        self.gains
+    }
+}
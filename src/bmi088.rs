@@ -1,8 +1,8 @@
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use rppal::i2c::I2c;
 
-pub const ROBOT_A_IMU_BIAS: f64 = 0.0004146448; //0.0002138361;
+use crate::imu::HeadingSensor;
 
 const ANGULAR_CODE: u8 = 0x01;
 const ANGULAR_SCALE: f64 = match ANGULAR_CODE {
@@ -15,54 +15,244 @@ const ANGULAR_SCALE: f64 = match ANGULAR_CODE {
 } * (std::f64::consts::PI / 180.0)
     / 2u16.pow(15) as f64;
 
+// failed gyro reads in a row before the gyro is considered disconnected
+const MAX_FAILED_READS: u32 = 20;
+// time between attempts to restart a disconnected gyro
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+// the temperature sensor only updates every 1.28s
+const TEMPERATURE_INTERVAL: Duration = Duration::from_millis(1280);
+// temperature the temperature correction is zero at, anything works
+// as the bias absorbs the correction at the time it is measured
+const REFERENCE_TEMPERATURE: f64 = 23.0;
+
+// the accelerometer is a separate i2c device whose address follows the
+// gyro's (0x18/0x19 for 0x68/0x69) with SDO1 and SDO2 tied together
+const ACCEL_ADDR_OFFSET: u16 = 0x50;
+const ACCEL_CHIP_ID: u8 = 0x1E;
+const ACCEL_RANGE_CODE: u8 = 0x01;
+// m/s^2 per bit
+const ACCEL_SCALE: f64 = match ACCEL_RANGE_CODE {
+    0x00 => 3.0,
+    0x01 => 6.0,
+    0x02 => 12.0,
+    0x03 => 24.0,
+    _ => panic!("Invalid ACCEL_RANGE_CODE"),
+} * 9.80665
+    / 2u16.pow(15) as f64;
+
 pub struct Bmi088 {
     pub i2c: I2c,
+    gyro_addr: u16,
+    // None if the accelerometer didn't respond
+    accel_addr: Option<u16>,
+    // address the i2c bus is currently talking to
+    selected: u16,
+    // x (forwards) and y (left) acceleration in m/s^2
+    acceleration: [f64; 2],
     last_read: Instant,
     last_angular_vel_z: f64,
+    // rad/s about y (left), no bias is applied as it is only used to
+    // catch the robot tipping
+    pitch_rate: f64,
     heading: f64,
     bias: f64,
+    // multiplier correcting the sensitivity of the gyro
+    scale: f64,
+    // gyro reads failed in a row
+    failed_reads: u32,
+    last_connect: Instant,
+    // degrees C, read from the accelerometer
+    temperature: Option<f64>,
+    last_temperature_read: Option<Instant>,
+    temperature_slope: f64,
 }
 
 impl Bmi088 {
-    pub fn new(bias: f64, addr: u16) -> Self {
-        let mut i2c = I2c::new().unwrap();
+    // the bias starts at zero and is measured at startup (see GyroBias).
+    // Only fails without an i2c bus, a gyro that doesn't respond is
+    // retried in the background (see is_healthy)
+    pub fn new(addr: u16) -> rppal::i2c::Result<Self> {
+        let mut i2c = I2c::new()?;
         log::info!("IMU clock speed: {:?}", i2c.clock_speed());
 
-        // gyroscope address
-        i2c.set_slave_address(addr).unwrap();
-        i2c.write(&[0x0F, ANGULAR_CODE]).unwrap();
-        // set filtering (test if this performs the best)
-        i2c.write(&[0x10, 0x02]).unwrap();
-        // read vel_z
-        let mut buf = [0u8; 2];
-        i2c.write_read(&[0x6u8], &mut buf).unwrap();
-        let last_angular_vel_z = i16::from_le_bytes(buf) as f64 * ANGULAR_SCALE;
+        let accel_addr = addr - ACCEL_ADDR_OFFSET;
+        let accel_addr = match Self::init_accel(&mut i2c, accel_addr) {
+            Ok(ACCEL_CHIP_ID) => Some(accel_addr),
+            Ok(id) => {
+                log::warn!("Unexpected accelerometer chip id {id:#x}, ignoring the accelerometer.");
+                None
+            }
+            Err(e) => {
+                log::warn!("Failed to start the accelerometer: {e}");
+                None
+            }
+        };
 
-        Self {
+        let mut imu = Self {
             i2c,
+            gyro_addr: addr,
+            accel_addr,
+            // not a device address so the first read selects its device
+            selected: 0,
+            acceleration: [0.0; 2],
             last_read: Instant::now(),
-            last_angular_vel_z,
+            last_angular_vel_z: 0.0,
+            pitch_rate: 0.0,
             heading: 0.0,
-            bias,
+            bias: 0.0,
+            scale: 1.0,
+            failed_reads: 0,
+            last_connect: Instant::now(),
+            temperature: None,
+            last_temperature_read: None,
+            temperature_slope: 0.0,
+        };
+        if let Err(e) = imu.init_gyro() {
+            log::error!("Failed to start the gyro: {e}");
+            imu.failed_reads = MAX_FAILED_READS;
         }
+        Ok(imu)
     }
-    fn read_vel_z(&mut self) -> f64 {
+    fn init_gyro(&mut self) -> rppal::i2c::Result<()> {
+        self.last_connect = Instant::now();
+        let gyro_addr = self.gyro_addr;
+        self.select(gyro_addr)?;
+        self.i2c.write(&[0x0F, ANGULAR_CODE])?;
+        // set filtering (test if this performs the best)
+        self.i2c.write(&[0x10, 0x02])?;
+        // read vel_z
+        let mut buf = [0u8; 2];
+        self.i2c.write_read(&[0x6u8], &mut buf)?;
+        self.last_angular_vel_z =
+            (i16::from_le_bytes(buf) as f64 * ANGULAR_SCALE + self.bias) * self.scale;
+        self.last_read = Instant::now();
+        self.failed_reads = 0;
+        Ok(())
+    }
+    // powers on the accelerometer returning its chip id
+    fn init_accel(i2c: &mut I2c, addr: u16) -> rppal::i2c::Result<u8> {
+        i2c.set_slave_address(addr)?;
+        // the first read switches the accelerometer to i2c mode
+        let mut id = [0u8];
+        i2c.write_read(&[0x00], &mut id)?;
+        // active mode then enable, each needs time to take effect
+        i2c.write(&[0x7C, 0x00])?;
+        std::thread::sleep(Duration::from_millis(5));
+        i2c.write(&[0x7D, 0x04])?;
+        std::thread::sleep(Duration::from_millis(5));
+        i2c.write(&[0x41, ACCEL_RANGE_CODE])?;
+        i2c.write_read(&[0x00], &mut id)?;
+        Ok(id[0])
+    }
+    fn select(&mut self, addr: u16) -> rppal::i2c::Result<()> {
+        if self.selected != addr {
+            self.i2c.set_slave_address(addr)?;
+            self.selected = addr;
+        }
+        Ok(())
+    }
+    fn read_accel(&mut self) {
+        let Some(addr) = self.accel_addr else {
+            return;
+        };
+        let mut buf = [0u8; 4];
+        let read = self
+            .select(addr)
+            .and_then(|_| self.i2c.write_read(&[0x12u8], &mut buf));
+        match read {
+            Ok(()) => {
+                self.acceleration = [
+                    i16::from_le_bytes([buf[0], buf[1]]) as f64 * ACCEL_SCALE,
+                    i16::from_le_bytes([buf[2], buf[3]]) as f64 * ACCEL_SCALE,
+                ];
+            }
+            Err(e) => log::warn!("accelerometer read failed: {e}"),
+        }
+    }
+    fn read_temperature(&mut self) {
+        let Some(addr) = self.accel_addr else {
+            return;
+        };
+        if self
+            .last_temperature_read
+            .is_some_and(|t| t.elapsed() < TEMPERATURE_INTERVAL)
+        {
+            return;
+        }
+        self.last_temperature_read = Some(Instant::now());
         let mut buf = [0u8; 2];
-        match self.i2c.write_read(&[0x6u8], &mut buf) {
-            Ok(()) => i16::from_le_bytes(buf) as f64 * ANGULAR_SCALE + self.bias,
+        let read = self
+            .select(addr)
+            .and_then(|_| self.i2c.write_read(&[0x22u8], &mut buf));
+        match read {
+            Ok(()) => {
+                // 11 bit two's complement in 0.125 degree steps from 23 degrees
+                let mut raw = ((buf[0] as i16) << 3) | (buf[1] as i16 >> 5);
+                if raw > 1023 {
+                    raw -= 2048;
+                }
+                self.temperature = Some(raw as f64 * 0.125 + 23.0);
+            }
+            Err(e) => log::warn!("IMU temperature read failed: {e}"),
+        }
+    }
+    // bias change since the reference temperature
+    fn temperature_correction(&self) -> f64 {
+        self.temperature.map_or(0.0, |t| {
+            -self.temperature_slope * (t - REFERENCE_TEMPERATURE)
+        })
+    }
+    // reads every axis of the gyro returning the z rate and keeping the pitch rate
+    fn read_vel_z(&mut self) -> f64 {
+        let mut buf = [0u8; 6];
+        let gyro_addr = self.gyro_addr;
+        match self
+            .select(gyro_addr)
+            .and_then(|_| self.i2c.write_read(&[0x2u8], &mut buf))
+        {
+            Ok(()) => {
+                self.failed_reads = 0;
+                self.pitch_rate = i16::from_le_bytes([buf[2], buf[3]]) as f64 * ANGULAR_SCALE;
+                let rate = i16::from_le_bytes([buf[4], buf[5]]) as f64 * ANGULAR_SCALE;
+                (rate + self.bias + self.temperature_correction()) * self.scale
+            }
             Err(e) => {
-                log::warn!("imu read failed: {e}");
+                self.failed_reads += 1;
+                if self.failed_reads == MAX_FAILED_READS {
+                    log::error!("The gyro stopped responding: {e}");
+                } else if self.failed_reads < MAX_FAILED_READS {
+                    log::warn!("imu read failed: {e}");
+                }
                 self.last_angular_vel_z
             }
         }
     }
-    pub fn heading(&self) -> f64 {
+}
+
+impl HeadingSensor for Bmi088 {
+    fn heading(&self) -> f64 {
         self.heading
     }
-    pub fn angular_velocity(&self) -> f64 {
+    fn angular_velocity(&self) -> f64 {
         self.last_angular_vel_z
     }
-    pub fn calc_heading(&mut self) -> f64 {
+    fn is_healthy(&self) -> bool {
+        self.failed_reads < MAX_FAILED_READS
+    }
+    fn calc_heading(&mut self) -> f64 {
+        // the heading is left to whoever is tracking it while disconnected
+        if !self.is_healthy() {
+            if self.last_connect.elapsed() > RECONNECT_INTERVAL {
+                match self.init_gyro() {
+                    Ok(()) => log::info!("Reconnected to the gyro."),
+                    Err(e) => log::debug!("Failed to reconnect to the gyro: {e}"),
+                }
+            }
+            return self.heading;
+        }
+        self.read_accel();
+        self.read_temperature();
         let new_angular_vel_z = self.read_vel_z();
         let now = Instant::now();
         let dt = now.duration_since(self.last_read).as_secs_f64();
@@ -71,8 +261,38 @@ impl Bmi088 {
         self.last_read = now;
         self.heading
     }
-    pub fn reset(&mut self) {
+    fn reset(&mut self) {
         self.last_read = Instant::now();
         self.heading = 0.0;
     }
+    fn set_heading(&mut self, heading: f64) {
+        self.heading = heading;
+    }
+    fn bias(&self) -> f64 {
+        self.bias
+    }
+    fn set_bias(&mut self, bias: f64) {
+        self.bias = bias;
+    }
+    fn scale(&self) -> f64 {
+        self.scale
+    }
+    fn set_scale(&mut self, scale: f64) {
+        self.scale = scale;
+    }
+    fn temperature(&self) -> Option<f64> {
+        self.temperature
+    }
+    fn temperature_slope(&self) -> f64 {
+        self.temperature_slope
+    }
+    fn set_temperature_slope(&mut self, slope: f64) {
+        self.temperature_slope = slope;
+    }
+    fn pitch_rate(&self) -> Option<f64> {
+        self.is_healthy().then_some(self.pitch_rate)
+    }
+    fn acceleration(&self) -> Option<[f64; 2]> {
+        self.accel_addr.map(|_| self.acceleration)
+    }
 }
@@ -1,18 +1,34 @@
-use std::time::Instant;
+use std::{
+    cell::RefCell,
+    fmt::Write,
+    sync::mpsc,
+    time::{Duration, Instant},
+};
 
-use client::coprocessor::serial::{find_v5_port, Serial, SerialSpawner};
 use protocol::{
     device::{CompetitionState, ControllerButtons, Gearbox},
     ControlPkt, StatusPkt,
 };
 
 use crate::{
+    capture::{self, CaptureLink},
+    config::RobotConfig,
     controller::Controller,
-    motor::{self, Motor},
+    link::{self, BenchLink, Link, MockLink},
+    motor::{self, AngularVelocity, Motor},
     robot::RobotState,
     triports::Triport,
 };
 
+// change in output scale per loop while over (and back under) a current limit
+const CURRENT_LIMIT_STEP: f64 = 0.02;
+const CURRENT_RECOVERY_STEP: f64 = 0.005;
+// a motor over its limit still gets this fraction of its output
+const MIN_CURRENT_SCALE: f64 = 0.2;
+// how long a motor must stay (dis)connected before an event is sent
+// so a loose cable doesn't send one every packet
+const CONNECTION_DEBOUNCE: Duration = Duration::from_millis(100);
+
 // this is not designed to ever be mutated
 #[derive(Debug, Clone)]
 pub struct Packet {
@@ -47,39 +63,170 @@ impl From<(Instant, StatusPkt)> for Packet {
     }
 }
 
+// set to run in bench mode regardless of the config
+pub const BENCH_ENV: &str = "LEMON_BENCH";
+
+// How the connection to the brain is made at startup
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StartupMode {
+    // block until the brain is connected and sending status packets
+    #[default]
+    Competition,
+    // start disabled straight away and keep looking for the brain
+    // in the background, for running the robot on a desk
+    Bench,
+}
+
+impl StartupMode {
+    // bench mode if BENCH_ENV is set or the config key "startup.bench" is non zero
+    pub fn load(config: &RobotConfig) -> Self {
+        if std::env::var_os(BENCH_ENV).is_some()
+            || config.get("startup.bench").unwrap_or(0.0) != 0.0
+        {
+            Self::Bench
+        } else {
+            Self::Competition
+        }
+    }
+}
+
+// statistics on the connection to the brain (see Brain::take_link_stats)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LinkStats {
+    pub packets: u64,
+    // longest time between status packets
+    pub max_gap: std::time::Duration,
+    pub disconnects: u32,
+}
+
+// a motor was connected or disconnected (see Brain::subscribe_motor_events)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MotorEvent {
+    pub port: u8,
+    pub connected: bool,
+}
+
+// debounced connection state of a motor port
+#[derive(Debug, Clone, Copy, Default)]
+struct Connection {
+    connected: bool,
+    // when the reported state started differing from connected
+    changing_since: Option<Instant>,
+}
+
+impl Connection {
+    // the new state once a change has lasted CONNECTION_DEBOUNCE
+    fn update(&mut self, connected: bool) -> Option<bool> {
+        if connected == self.connected {
+            self.changing_since = None;
+            return None;
+        }
+        let since = *self.changing_since.get_or_insert_with(Instant::now);
+        if since.elapsed() < CONNECTION_DEBOUNCE {
+            return None;
+        }
+        self.connected = connected;
+        self.changing_since = None;
+        Some(connected)
+    }
+}
+
+// why a motor couldn't be taken or released (see Brain::take_motor)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortError {
+    InvalidPort(u8),
+    Owned { port: u8, owner: &'static str },
+    NotOwner { port: u8, owner: &'static str },
+}
+
+impl std::fmt::Display for PortError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::InvalidPort(port) => write!(f, "there is no motor port {port}"),
+            Self::Owned { port, owner } => write!(f, "motor port {port} is owned by {owner}"),
+            Self::NotOwner { port, owner } => {
+                write!(f, "motor port {port} is not owned by {owner}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for PortError {}
+
+// what a port has been claimed for, used for the wiring report
+#[derive(Debug, Clone, Copy, Default)]
+struct PortClaim {
+    owner: Option<&'static str>,
+    reversed: Option<bool>,
+}
+
 pub struct Brain {
-    serial: Serial,
+    link: Box<dyn Link>,
     pkt_buffer: [Packet; 2],
     last_update: Instant,
     motors: [Motor; 20],
+    // loops for motors with position targets
+    position_loops: [Option<motor::PositionLoop>; 20],
+    // fraction of the output kept to stay under each motor's current limit
+    current_scales: [f64; 20],
+    thermal_levels: [motor::ThermalLevel; 20],
+    connections: [Connection; 20],
+    motor_subscribers: RefCell<Vec<mpsc::Sender<MotorEvent>>>,
     triports: std::sync::Arc<std::sync::atomic::AtomicU8>,
+    motor_claims: RefCell<[Option<PortClaim>; 20]>,
+    triport_claims: RefCell<[Option<PortClaim>; 8]>,
+    link_stats: LinkStats,
 }
 
 impl Brain {
-    pub fn init() -> (Self, Controller) {
+    pub fn init(mode: StartupMode) -> (Self, Controller) {
+        if let Ok(path) = std::env::var(capture::REPLAY_ENV) {
+            match capture::load_status(&path) {
+                Ok(status) => {
+                    log::info!("Replaying {} status packets from {path}.", status.len());
+                    return Self::with_link(Box::new(MockLink::new(status).0));
+                }
+                Err(e) => log::error!("Failed to load capture {path}: {e}"),
+            }
+        }
+
+        if mode == StartupMode::Bench {
+            log::info!("Starting in bench mode.");
+            return Self::with_link(Box::new(BenchLink::new()));
+        }
+
         // try establish connection
-        let serial_port = loop {
+        let serial = loop {
             std::thread::yield_now();
-            let Ok(port) = find_v5_port() else {
-                continue;
-            };
-            let Ok(sp) = SerialSpawner::open(&port.0.port_name) else {
-                continue;
-            };
-            break sp;
+            if let Some(serial) = link::try_connect() {
+                break serial;
+            }
         };
-        let serial = serial_port.spawn_threaded(None);
 
+        if let Ok(path) = std::env::var(capture::CAPTURE_ENV) {
+            match std::fs::File::create(&path) {
+                Ok(file) => {
+                    log::info!("Capturing serial traffic to {path}.");
+                    return Self::with_link(Box::new(CaptureLink::new(serial, file)));
+                }
+                Err(e) => log::error!("Failed to create capture {path}: {e}"),
+            }
+        }
+
+        Self::with_link(Box::new(serial))
+    }
+    // runs the brain over any link, used to drive the robot from a MockLink
+    pub fn with_link(mut link: Box<dyn Link>) -> (Self, Controller) {
         let first = loop {
             std::thread::yield_now();
-            let Some(pkt) = serial.take_status_pkt() else {
+            let Some(pkt) = link.take_status_pkt() else {
                 continue;
             };
             break pkt;
         };
         let second = loop {
             std::thread::yield_now();
-            let Some(pkt) = serial.take_status_pkt() else {
+            let Some(pkt) = link.take_status_pkt() else {
                 continue;
             };
             break pkt;
@@ -89,7 +236,7 @@ impl Brain {
 
         (
             Self {
-                serial,
+                link,
                 pkt_buffer: pkt_buffer.clone(),
                 last_update: Instant::now(),
                 motors: (1..=20)
@@ -97,7 +244,15 @@ impl Brain {
                     .collect::<Vec<_>>()
                     .try_into()
                     .unwrap(),
+                position_loops: [None; 20],
+                current_scales: [1.0; 20],
+                thermal_levels: [motor::ThermalLevel::Normal; 20],
+                connections: [Connection::default(); 20],
+                motor_subscribers: RefCell::new(Vec::new()),
                 triports: std::sync::Arc::new(std::sync::atomic::AtomicU8::new(0)),
+                motor_claims: RefCell::new([None; 20]),
+                triport_claims: RefCell::new([None; 8]),
+                link_stats: LinkStats::default(),
             },
             pkt_buffer.into(),
         )
@@ -109,13 +264,15 @@ impl Brain {
         controller: &mut Controller,
         robot_state: &RobotState,
     ) -> RobotState {
-        if let Some(data_pkt) = self.serial.take_status_pkt() {
+        if let Some(data_pkt) = self.link.take_status_pkt() {
             self.read_motors(&data_pkt.1);
             self.pkt_buffer[1] = data_pkt.into();
             self.pkt_buffer.swap(0, 1);
+            self.link_stats.packets += 1;
+            self.link_stats.max_gap = self.link_stats.max_gap.max(self.last_update.elapsed());
             self.last_update = Instant::now();
 
-            *controller = self.pkt_buffer.clone().into();
+            controller.update(self.pkt_buffer.clone());
 
             RobotState::from_brain_state(
                 self.pkt_buffer[0].brain_state,
@@ -127,58 +284,313 @@ impl Brain {
             if self.last_update.elapsed() > crate::BRAIN_TIMEOUT && *robot_state != RobotState::Off
             {
                 log::warn!("Connection to the brain has been lost.");
+                self.link_stats.disconnects += 1;
                 return RobotState::Off;
             }
             *robot_state
         }
     }
+    // current draw of each motor in mA (indexed by port - 1)
+    pub fn motor_currents(&self) -> [Option<i16>; 20] {
+        std::array::from_fn(|i| self.motors[i].current())
+    }
+    // temperature of each motor in C (indexed by port - 1)
+    pub fn motor_temperatures(&self) -> [Option<f64>; 20] {
+        std::array::from_fn(|i| self.motors[i].temperature())
+    }
+    // the link statistics since this was last called
+    pub fn take_link_stats(&mut self) -> LinkStats {
+        std::mem::take(&mut self.link_stats)
+    }
     pub fn auton_program(&self) -> u8 {
         self.pkt_buffer[0].auton_program
     }
     pub fn write_changes(&mut self) {
+        if motor::arbitration_log_enabled() {
+            self.log_motor_writers();
+        }
         let mut ctrl_pkt = ControlPkt::default();
 
-        for motor in &self.motors {
+        let motors = self
+            .motors
+            .iter()
+            .zip(&mut self.position_loops)
+            .zip(&mut self.current_scales);
+        for ((motor, position_loop), current_scale) in motors {
             let port = motor.port() as usize;
-            if !motor.is_connected() {
+            let Some(state) = motor.state() else {
+                *position_loop = None;
+                *current_scale = 1.0;
                 continue;
-            }
-            match motor.target() {
-                motor::Target::Voltage(v) => ctrl_pkt.set_power(port, v, false),
-                motor::Target::PercentVoltage(v) => {
-                    ctrl_pkt.set_power(port, (v * motor::MAX_MILLIVOLT as f64) as i16, false);
+            };
+            let target = motor.effective_target(position_loop, state.position);
+            // (power, is a velocity)
+            let (power, velocity) =
+                match motor::PositionLoop::velocity(position_loop, target, state.position as f64) {
+                    Some(rpm) => {
+                        let max =
+                            AngularVelocity::max_for(motor.gearbox().unwrap_or(Gearbox::Green));
+                        (rpm.clamp(-max.as_rpm(), max.as_rpm()), true)
+                    }
+                    None => match target {
+                        motor::Target::Voltage(v) => (v as f64, false),
+                        motor::Target::PercentVoltage(v) => {
+                            (v * motor::MAX_MILLIVOLT as f64, false)
+                        }
+                        motor::Target::RotationalVelocity(v) => (v as f64, true),
+                        // position targets are always handled by the position loop
+                        _ => (0.0, false),
+                    },
+                };
+
+            // back off the output while the motor draws more than its
+            // limit, for velocity targets this only roughly limits the current
+            match motor.current_limit() {
+                Some(limit) if state.current.abs() > limit => {
+                    *current_scale = (*current_scale - CURRENT_LIMIT_STEP).max(MIN_CURRENT_SCALE);
                 }
-                motor::Target::RotationalVelocity(v) => ctrl_pkt.set_power(port, v, true),
-                motor::Target::None => ctrl_pkt.set_power(port, 0, false),
+                Some(_) => *current_scale = (*current_scale + CURRENT_RECOVERY_STEP).min(1.0),
+                None => *current_scale = 1.0,
             }
+            ctrl_pkt.set_power(port, (power * *current_scale).round() as i16, velocity);
         }
 
         ctrl_pkt.triport_pins = self.triports.load(std::sync::atomic::Ordering::SeqCst);
 
-        self.serial.set_control_pkt(ctrl_pkt);
+        self.link.set_control_pkt(ctrl_pkt);
+    }
+    // logs where each motor was last written from this loop and warns
+    // when different places wrote different targets to the same motor
+    fn log_motor_writers(&self) {
+        for motor in &self.motors {
+            let writers = motor.take_writers();
+            let Some((last, target)) = writers.last() else {
+                continue;
+            };
+            log::debug!("motor {} last written {target:?} by {last}", motor.port());
+            let conflict = writers.iter().any(|(loc, t)| {
+                (loc.file(), loc.line()) != (last.file(), last.line()) && t != target
+            });
+            if conflict {
+                let writers: Vec<_> = writers
+                    .iter()
+                    .map(|(loc, t)| format!("{t:?} by {loc}"))
+                    .collect();
+                log::warn!(
+                    "motor {} written with conflicting targets this loop: {}",
+                    motor.port(),
+                    writers.join(", ")
+                );
+            }
+        }
     }
     pub fn set_gearboxes(&mut self, gearbox: Gearbox, ports: impl IntoIterator<Item = u8>) {
-        self.serial
-            .set_gearboxes(ports.into_iter().map(|p| (p, gearbox)));
-        self.serial.update_gearboxes();
+        let ports: Vec<_> = ports.into_iter().collect();
+        for &port in &ports {
+            unsafe {
+                self.motors[port as usize - 1].set_gearbox(gearbox);
+            }
+        }
+        self.link
+            .set_gearboxes(ports.into_iter().map(|p| (p, gearbox)).collect());
+    }
+    // Every motor connect and disconnect from now on is sent to the
+    // returned receiver, e.g. for a part to stop itself when its motor
+    // is lost. Events are debounced by CONNECTION_DEBOUNCE.
+    pub fn subscribe_motor_events(&self) -> mpsc::Receiver<MotorEvent> {
+        let (tx, rx) = mpsc::channel();
+        self.motor_subscribers.borrow_mut().push(tx);
+        rx
+    }
+    fn send_motor_event(&self, event: MotorEvent) {
+        let owner = self.motor_owner(event.port).unwrap_or("unclaimed");
+        if event.connected {
+            log::info!("Motor on port {} ({owner}) connected.", event.port);
+        } else {
+            log::warn!("Motor on port {} ({owner}) disconnected.", event.port);
+        }
+        // subscribers that have been dropped are removed
+        self.motor_subscribers
+            .borrow_mut()
+            .retain(|tx| tx.send(event).is_ok());
     }
     fn read_motors(&mut self, status_pkt: &StatusPkt) {
-        for motor in &mut self.motors {
+        let motors = self
+            .motors
+            .iter_mut()
+            .zip(&mut self.thermal_levels)
+            .zip(&mut self.connections);
+        let mut events = Vec::new();
+        for ((motor, level), connection) in motors {
             unsafe {
                 motor.set_inner(status_pkt.get_motor_state(motor.port() as usize));
             }
+            if let Some(connected) = connection.update(motor.is_connected()) {
+                events.push(MotorEvent {
+                    port: motor.port(),
+                    connected,
+                });
+            }
+            let Some(temperature) = motor.temperature() else {
+                continue;
+            };
+            let new_level = level.update(temperature);
+            if new_level > *level {
+                log::warn!(
+                    "Motor on port {} is {new_level:?} at {temperature}C.",
+                    motor.port()
+                );
+            } else if new_level < *level {
+                log::info!(
+                    "Motor on port {} has cooled to {new_level:?} at {temperature}C.",
+                    motor.port()
+                );
+            }
+            *level = new_level;
+        }
+        for event in events {
+            self.send_motor_event(event);
         }
     }
     // These should be the only fatal failure points of the robot
-    // use get_motor and get_triport with care (should we make this unsafe?)
-    pub fn get_motor(&self, port: u8) -> Motor {
+    // use get_triport with care (should we make this unsafe?)
+    // doesn't claim the motor, parts get motors through claim_motor
+    fn get_motor(&self, port: u8) -> Motor {
         assert!((1..=20).contains(&port));
         self.motors[port as usize - 1].clone()
     }
     pub fn get_triport(&self, port: u8) -> Triport {
         assert!((1..=8).contains(&port));
+        self.triport_claims.borrow_mut()[port as usize - 1].get_or_insert_with(PortClaim::default);
         unsafe { Triport::new(self.triports.clone(), port - 1) }
     }
+    // take_motor but a conflict is logged along with every port's owner,
+    // the motor is never shared between two parts
+    pub fn claim_motor(
+        &self,
+        port: u8,
+        reversed: bool,
+        owner: &'static str,
+    ) -> Result<Motor, PortError> {
+        let result = self.take_motor(port, reversed, owner);
+        if let Err(e) = &result {
+            log::error!("{owner} failed to take motor port {port}: {e}.");
+            let owners = self
+                .motor_owners()
+                .into_iter()
+                .map(|(port, owner)| format!("{port}: {owner}"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            log::error!("Motor ports are owned as follows: {owners}");
+        }
+        result
+    }
+    // Takes the motor for owner recording it for the wiring report. A port
+    // can be taken more than once by the same owner but not by another
+    // until it is released.
+    pub fn take_motor(
+        &self,
+        port: u8,
+        reversed: bool,
+        owner: &'static str,
+    ) -> Result<Motor, PortError> {
+        if !(1..=20).contains(&port) {
+            return Err(PortError::InvalidPort(port));
+        }
+        if let Some(other) = self.motor_owner(port).filter(|&o| o != owner) {
+            return Err(PortError::Owned { port, owner: other });
+        }
+        let motor = self.get_motor(port);
+        self.motor_claims.borrow_mut()[port as usize - 1] = Some(PortClaim {
+            owner: Some(owner),
+            reversed: Some(reversed),
+        });
+        Ok(motor)
+    }
+    // gives up a motor taken with take_motor so another part can take it
+    pub fn release_motor(&self, port: u8, owner: &'static str) -> Result<(), PortError> {
+        if !(1..=20).contains(&port) {
+            return Err(PortError::InvalidPort(port));
+        }
+        if self.motor_owner(port) != Some(owner) {
+            return Err(PortError::NotOwner { port, owner });
+        }
+        self.motor_claims.borrow_mut()[port as usize - 1] = None;
+        Ok(())
+    }
+    pub fn motor_owner(&self, port: u8) -> Option<&'static str> {
+        let claims = self.motor_claims.borrow();
+        claims.get(port.wrapping_sub(1) as usize)?.as_ref()?.owner
+    }
+    // every owned motor port and its owner
+    pub fn motor_owners(&self) -> Vec<(u8, &'static str)> {
+        (1..=20)
+            .filter_map(|port| Some((port, self.motor_owner(port)?)))
+            .collect()
+    }
+    pub fn claim_triport(&self, port: u8, owner: &'static str) -> Triport {
+        let triport = self.get_triport(port);
+        let mut claims = self.triport_claims.borrow_mut();
+        let claim = claims[port as usize - 1].get_or_insert_with(PortClaim::default);
+        if let Some(other) = claim.owner.filter(|&o| o != owner) {
+            log::warn!("Triport {port} claimed by {owner} is already used by {other}.");
+        }
+        claim.owner = Some(owner);
+        triport
+    }
+    // A human readable list of every port in use and what for, generated
+    // from the claims made so far rather then maintained by hand.
+    // Connected motors that were never claimed are listed too.
+    pub fn wiring_report(&self) -> String {
+        let mut report = String::new();
+        let claims = self.motor_claims.borrow();
+        for (motor, claim) in self.motors.iter().zip(claims.iter()) {
+            let connected = if motor.is_connected() {
+                "connected"
+            } else {
+                "disconnected"
+            };
+            let Some(claim) = claim else {
+                if motor.is_connected() {
+                    let _ = writeln!(report, "motor {:>2}: unclaimed ({connected})", motor.port());
+                }
+                continue;
+            };
+            let reversed = match claim.reversed {
+                Some(true) => "reversed",
+                Some(false) => "forward",
+                None => "direction unknown",
+            };
+            let gearbox = motor
+                .gearbox()
+                .map_or("gearbox unset".to_owned(), |g| format!("{g:?} gearbox"));
+            let _ = writeln!(
+                report,
+                "motor {:>2}: {}, {reversed}, {gearbox} ({connected})",
+                motor.port(),
+                claim.owner.unwrap_or("unknown owner"),
+            );
+        }
+        for (i, claim) in self.triport_claims.borrow().iter().enumerate() {
+            if let Some(claim) = claim {
+                let _ = writeln!(
+                    report,
+                    "triport {}: {}",
+                    i + 1,
+                    claim.owner.unwrap_or("unknown owner")
+                );
+            }
+        }
+        report
+    }
+    // logs the wiring report and writes it to path
+    pub fn save_wiring_report(&self, path: &str) {
+        let report = self.wiring_report();
+        log::info!("Wiring report:\n{report}");
+        if let Err(e) = std::fs::write(path, report) {
+            log::warn!("Failed to write wiring report to {path}: {e}");
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -0,0 +1,133 @@
+use std::time::{Duration, Instant};
+
+// how often overruns of the main loop budgets are reported
+const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy)]
+struct PartTime {
+    name: &'static str,
+    budget: Duration,
+    overruns: u32,
+    worst: Duration,
+    // times the part was skipped as the loop had no time left for it
+    skipped: u32,
+}
+
+// Times the parts run in the main loop against a per part budget and
+// keeps the whole loop within its budget by skipping parts that can
+// wait (reports, calibration) when there isn't time left for them.
+// Overruns and skips are summarised every REPORT_INTERVAL rather then
+// logged each loop.
+pub struct LoopBudget {
+    parts: Vec<PartTime>,
+    loop_budget: Duration,
+    loop_start: Instant,
+    last_report: Instant,
+}
+
+impl LoopBudget {
+    pub fn new(loop_budget: Duration) -> Self {
+        Self {
+            parts: Vec::new(),
+            loop_budget,
+            loop_start: Instant::now(),
+            last_report: Instant::now(),
+        }
+    }
+    // called at the start of every loop
+    pub fn start_loop(&mut self) {
+        self.loop_start = Instant::now();
+    }
+    fn part(&mut self, name: &'static str, budget: Duration) -> &mut PartTime {
+        let i = match self.parts.iter().position(|p| p.name == name) {
+            Some(i) => i,
+            None => {
+                self.parts.push(PartTime {
+                    name,
+                    budget,
+                    overruns: 0,
+                    worst: Duration::ZERO,
+                    skipped: 0,
+                });
+                self.parts.len() - 1
+            }
+        };
+        let part = &mut self.parts[i];
+        part.budget = budget;
+        part
+    }
+    // runs a part that has to run every loop (e.g. control)
+    pub fn time<T>(&mut self, name: &'static str, budget: Duration, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let out = f();
+        let elapsed = start.elapsed();
+        if elapsed > budget {
+            let part = self.part(name, budget);
+            part.overruns += 1;
+            part.worst = part.worst.max(elapsed);
+        }
+        out
+    }
+    // runs a part that can wait for a later loop, None if it was skipped
+    // as its budget doesn't fit in what is left of the loop's
+    pub fn time_deferrable<T>(
+        &mut self,
+        name: &'static str,
+        budget: Duration,
+        f: impl FnOnce() -> T,
+    ) -> Option<T> {
+        if self.loop_start.elapsed() + budget > self.loop_budget {
+            self.part(name, budget).skipped += 1;
+            return None;
+        }
+        Some(self.time(name, budget, f))
+    }
+    // logs the overruns and skips since the last report, called once per loop
+    pub fn report(&mut self) {
+        if self.last_report.elapsed() < REPORT_INTERVAL {
+            return;
+        }
+        self.last_report = Instant::now();
+        for part in self.parts.drain(..) {
+            if part.overruns > 0 {
+                log::warn!(
+                    "{} overran its budget of {:?} {} times (worst {:?}).",
+                    part.name,
+                    part.budget,
+                    part.overruns,
+                    part.worst
+                );
+            }
+            if part.skipped > 0 {
+                log::warn!(
+                    "{} was skipped {} times as the loop was out of time.",
+                    part.name,
+                    part.skipped
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // budgets are hours apart so how long the test takes doesn't matter
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    #[test]
+    fn only_deferrable_parts_are_skipped() {
+        let mut budget = LoopBudget::new(2 * HOUR);
+        budget.start_loop();
+        assert_eq!(budget.time_deferrable("fits", HOUR, || 1), Some(1));
+        assert_eq!(budget.time_deferrable("too long", 3 * HOUR, || 2), None);
+
+        // with no time left only parts that have to run do
+        let mut spent = LoopBudget::new(Duration::ZERO);
+        spent.start_loop();
+        let tiny = Duration::from_nanos(1);
+        assert_eq!(spent.time_deferrable("report", tiny, || 3), None);
+        assert_eq!(spent.time("control", tiny, || 4), 4);
+    }
+}
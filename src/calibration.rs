@@ -0,0 +1,292 @@
+use std::time::{Duration, Instant};
+
+use crate::{config::RobotConfig, odom::Odometry};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CalibrationStatus {
+    Running,
+    Done,
+}
+
+// Calibration work that parts can run while the robot is disabled
+// (e.g. sitting on the field before a match). Steps should be short
+// as every step in a loop shares BackgroundCalibration's time budget.
+pub trait Calibration {
+    fn name(&self) -> &'static str;
+    // does a small piece of work, called once a loop while disabled
+    fn step(&mut self, odom: &mut Odometry) -> CalibrationStatus;
+    // called when the robot enables during the calibration, the
+    // calibration is continued from step when disabled again
+    fn suspend(&mut self) {}
+    // called once done to store the result for the next startup
+    fn save(&self, _config: &mut RobotConfig) {}
+}
+
+// Runs calibrations while the robot is disabled, suspending them
+// the moment the robot is enabled.
+pub struct BackgroundCalibration {
+    tasks: Vec<Box<dyn Calibration>>,
+    // max time spent on calibration each loop
+    budget: Duration,
+    // task to step first next loop so every task gets a turn
+    next: usize,
+    running: bool,
+}
+
+impl BackgroundCalibration {
+    pub fn new(budget: Duration) -> Self {
+        Self {
+            tasks: Vec::new(),
+            budget,
+            next: 0,
+            running: false,
+        }
+    }
+    pub fn add(&mut self, task: Box<dyn Calibration>) {
+        self.tasks.push(task);
+    }
+    pub fn poll(&mut self, disabled: bool, odom: &mut Odometry, config: &mut RobotConfig) {
+        if !disabled {
+            if self.running {
+                for task in &mut self.tasks {
+                    log::info!("Suspended calibration: {}", task.name());
+                    task.suspend();
+                }
+                self.running = false;
+            }
+            return;
+        }
+        self.running = !self.tasks.is_empty();
+
+        let start = Instant::now();
+        for _ in 0..self.tasks.len() {
+            if start.elapsed() > self.budget {
+                break;
+            }
+            self.next %= self.tasks.len();
+            if self.tasks[self.next].step(odom) == CalibrationStatus::Done {
+                let task = self.tasks.remove(self.next);
+                log::info!("Finished calibration: {}", task.name());
+                task.save(config);
+                if self.tasks.is_empty() {
+                    self.running = false;
+                    return;
+                }
+            } else {
+                self.next += 1;
+            }
+        }
+    }
+}
+
+// the robot is considered still below these velocities (m/s and rad/s)
+const STILL_VELOCITY: f64 = 0.005;
+const STILL_ANGULAR_VELOCITY: f64 = 0.02;
+// standard deviation (rad/s) of the gyro readings above which the
+// robot is probably vibrating (or the IMU is loose)
+const MAX_GYRO_NOISE: f64 = 0.005;
+
+// Measures the gyro bias by averaging the angular velocity while the
+// robot is still. Samples taken while the robot is moved are discarded.
+pub struct GyroBias {
+    samples: usize,
+    bias: Option<f64>,
+    sum: f64,
+    sum_sq: f64,
+    count: usize,
+}
+
+impl GyroBias {
+    // samples is the number of readings (one per loop) to average
+    pub fn new(samples: usize) -> Self {
+        Self {
+            samples,
+            bias: None,
+            sum: 0.0,
+            sum_sq: 0.0,
+            count: 0,
+        }
+    }
+}
+
+impl Calibration for GyroBias {
+    fn name(&self) -> &'static str {
+        "gyro bias"
+    }
+    fn step(&mut self, odom: &mut Odometry) -> CalibrationStatus {
+        let rate = odom.angular_velocity();
+        if odom.settle_velocity().abs() > STILL_VELOCITY || rate.abs() > STILL_ANGULAR_VELOCITY {
+            self.suspend();
+            return CalibrationStatus::Running;
+        }
+        self.sum += rate;
+        self.sum_sq += rate * rate;
+        self.count += 1;
+        if self.count < self.samples {
+            return CalibrationStatus::Running;
+        }
+        let mean = self.sum / self.count as f64;
+        let noise = (self.sum_sq / self.count as f64 - mean * mean)
+            .max(0.0)
+            .sqrt();
+        log::info!("Gyro noise is {noise}rad/s.");
+        if noise > MAX_GYRO_NOISE {
+            log::warn!("The gyro is noisy, check the IMU is mounted firmly.");
+        }
+        // the reading already includes the current bias and scale
+        let bias = odom.gyro_bias() - mean / odom.gyro_scale();
+        odom.set_gyro_bias(bias);
+        self.bias = Some(bias);
+        CalibrationStatus::Done
+    }
+    // the fallback until the bias is measured at the next startup
+    fn save(&self, config: &mut RobotConfig) {
+        if let Some(bias) = self.bias {
+            config.set("imu.gyro_bias", bias);
+            if let Err(e) = config.save() {
+                log::error!("Failed to save the gyro bias: {e}");
+            }
+        }
+    }
+    fn suspend(&mut self) {
+        self.sum = 0.0;
+        self.sum_sq = 0.0;
+        self.count = 0;
+    }
+}
+
+// temperature range (degrees C) needed before fitting the bias drift
+const MIN_TEMPERATURE_SPAN: f64 = 4.0;
+
+// Fits how the gyro bias changes with temperature while the robot sits
+// still and warms up (e.g. powered on in the pits). Only the rate of
+// change is fit as GyroBias measures the bias itself at startup.
+pub struct GyroTemperature {
+    // bias the samples were taken with, a change invalidates them
+    bias: f64,
+    // sums for a least squares fit of rate against temperature
+    n: f64,
+    sum_t: f64,
+    sum_r: f64,
+    sum_tt: f64,
+    sum_tr: f64,
+    min_t: f64,
+    max_t: f64,
+    slope: Option<f64>,
+}
+
+impl GyroTemperature {
+    pub fn new() -> Self {
+        Self {
+            bias: 0.0,
+            n: 0.0,
+            sum_t: 0.0,
+            sum_r: 0.0,
+            sum_tt: 0.0,
+            sum_tr: 0.0,
+            min_t: f64::INFINITY,
+            max_t: f64::NEG_INFINITY,
+            slope: None,
+        }
+    }
+    fn clear(&mut self) {
+        *self = Self {
+            bias: self.bias,
+            ..Self::new()
+        };
+    }
+}
+
+impl Calibration for GyroTemperature {
+    fn name(&self) -> &'static str {
+        "gyro temperature"
+    }
+    fn step(&mut self, odom: &mut Odometry) -> CalibrationStatus {
+        let Some(t) = odom.gyro_temperature() else {
+            return CalibrationStatus::Running;
+        };
+        let rate = odom.angular_velocity();
+        // samples while moving are skipped but unlike GyroBias the fit
+        // carries on as the robot can't heat up in one sitting
+        if odom.settle_velocity().abs() > STILL_VELOCITY || rate.abs() > STILL_ANGULAR_VELOCITY {
+            return CalibrationStatus::Running;
+        }
+        if odom.gyro_bias() != self.bias {
+            self.bias = odom.gyro_bias();
+            self.clear();
+        }
+        self.n += 1.0;
+        self.sum_t += t;
+        self.sum_r += rate;
+        self.sum_tt += t * t;
+        self.sum_tr += t * rate;
+        self.min_t = self.min_t.min(t);
+        self.max_t = self.max_t.max(t);
+        if self.max_t - self.min_t < MIN_TEMPERATURE_SPAN {
+            return CalibrationStatus::Running;
+        }
+        let denom = self.n * self.sum_tt - self.sum_t * self.sum_t;
+        let residual = (self.n * self.sum_tr - self.sum_t * self.sum_r) / denom;
+        // the readings are already corrected with the current slope and
+        // the correction is subtracted so the drift left adds to it
+        let slope = odom.gyro_temperature_slope() + residual / odom.gyro_scale();
+        odom.set_gyro_temperature_slope(slope);
+        self.slope = Some(slope);
+        CalibrationStatus::Done
+    }
+    fn save(&self, config: &mut RobotConfig) {
+        if let Some(slope) = self.slope {
+            config.set("imu.gyro_temperature_slope", slope);
+            if let Err(e) = config.save() {
+                log::error!("Failed to save the gyro temperature slope: {e}");
+            }
+        }
+    }
+}
+
+// smallest fraction of the expected turn accepted as a measurement
+const MIN_SCALE_TURN: f64 = 0.5;
+// corrections beyond this are assumed to be a miscounted turn
+const MAX_SCALE_CORRECTION: f64 = 0.1;
+
+// Guided calibration of the gyro scale factor. The robot is turned by
+// hand exactly `turns` full turns (e.g. lined up against a field wall
+// before and after) and the heading integrated by the gyro is compared
+// to turns * 2π. Unlike GyroBias this needs the user so isn't run in
+// the background.
+pub struct GyroScale {
+    turns: f64,
+    start_heading: f64,
+}
+
+impl GyroScale {
+    pub fn start(odom: &Odometry, turns: f64) -> Self {
+        log::info!("Gyro scale calibration started, turn the robot {turns} times.");
+        Self {
+            turns,
+            start_heading: odom.heading(),
+        }
+    }
+    // the corrected scale, None if the measured turn isn't believable
+    pub fn finish(self, odom: &Odometry) -> Option<f64> {
+        let measured = (odom.heading() - self.start_heading).abs();
+        let expected = self.turns * std::f64::consts::TAU;
+        if measured < expected * MIN_SCALE_TURN {
+            log::warn!("Gyro scale calibration only turned {measured}rad, ignoring.");
+            return None;
+        }
+        let correction = expected / measured;
+        if (correction - 1.0).abs() > MAX_SCALE_CORRECTION {
+            log::warn!("Gyro scale correction of {correction} is too large, check the turn count.");
+            return None;
+        }
+        Some(odom.gyro_scale() * correction)
+    }
+    // stores the scale under "imu.gyro_scale" where it is loaded at startup
+    pub fn save(scale: f64, config: &mut RobotConfig) {
+        config.set("imu.gyro_scale", scale);
+        if let Err(e) = config.save() {
+            log::error!("Failed to save the gyro scale: {e}");
+        }
+    }
+}
@@ -0,0 +1,193 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use protocol::{
+    device::{CompetitionState, ControllerButtons, Gearbox},
+    ControlPkt, StatusPkt,
+};
+
+use crate::link::Link;
+
+// set to a file path to record the serial traffic to that file
+pub const CAPTURE_ENV: &str = "LEMON_CAPTURE";
+// set to a capture file to run the robot from it instead of the brain
+pub const REPLAY_ENV: &str = "LEMON_REPLAY";
+
+// Records all traffic going through a link to a file with one packet
+// per line (seconds since the capture started as the second field):
+// S <t> <state> <buttons> <axes x4> <auton> [<port>:<pos>,<vel>,<current>,<voltage>,<temp>]...
+// C <t> <control packet debug output>
+pub struct CaptureLink<L> {
+    link: L,
+    file: BufWriter<File>,
+    start: Instant,
+}
+
+impl<L: Link> CaptureLink<L> {
+    pub fn new(link: L, file: File) -> Self {
+        Self {
+            link,
+            file: BufWriter::new(file),
+            start: Instant::now(),
+        }
+    }
+    fn write_status(&mut self, timestamp: Instant, pkt: &StatusPkt) -> std::io::Result<()> {
+        let t = timestamp
+            .saturating_duration_since(self.start)
+            .as_secs_f64();
+        let axes = pkt.controller_axes;
+        write!(
+            self.file,
+            "S {t:.6} {} {} {} {} {} {} {}",
+            pkt.state.bits(),
+            pkt.controller_buttons.bits(),
+            axes[0],
+            axes[1],
+            axes[2],
+            axes[3],
+            pkt.auton
+        )?;
+        for port in 1..=20 {
+            if let Some(m) = pkt.get_motor_state(port) {
+                write!(
+                    self.file,
+                    " {port}:{},{},{},{},{}",
+                    m.position, m.velocity, m.current, m.voltage, m.temperature
+                )?;
+            }
+        }
+        writeln!(self.file)?;
+        // flush every status packet so little is lost if the robot is turned off
+        self.file.flush()
+    }
+}
+
+impl<L: Link> Link for CaptureLink<L> {
+    fn take_status_pkt(&mut self) -> Option<(Instant, StatusPkt)> {
+        let pkt = self.link.take_status_pkt()?;
+        if let Err(e) = self.write_status(pkt.0, &pkt.1) {
+            log::warn!("Failed to write status packet to capture: {e}");
+        }
+        Some(pkt)
+    }
+    fn set_control_pkt(&mut self, pkt: ControlPkt) {
+        let t = self.start.elapsed().as_secs_f64();
+        if let Err(e) = writeln!(self.file, "C {t:.6} {pkt:?}") {
+            log::warn!("Failed to write control packet to capture: {e}");
+        }
+        self.link.set_control_pkt(pkt);
+    }
+    fn set_gearboxes(&mut self, gearboxes: Vec<(u8, Gearbox)>) {
+        self.link.set_gearboxes(gearboxes);
+    }
+}
+
+// Reads the status packets from a capture with timestamps relative
+// to now so they can be replayed through a MockLink in real time.
+// Note that motor states are not restored as a StatusPkt can't be
+// built with them, only the competition state and controller are.
+pub fn load_status(path: impl AsRef<Path>) -> std::io::Result<Vec<(Instant, StatusPkt)>> {
+    let start = Instant::now();
+    let mut status = Vec::new();
+    for (i, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        if !line.starts_with("S ") {
+            continue;
+        }
+        match parse_status(&line) {
+            Some((t, pkt)) => status.push((start + Duration::from_secs_f64(t), pkt)),
+            None => log::warn!("Invalid status packet on line {} of capture", i + 1),
+        }
+    }
+    Ok(status)
+}
+
+fn parse_status(line: &str) -> Option<(f64, StatusPkt)> {
+    let mut fields = line.split_whitespace().skip(1);
+    let t: f64 = fields.next()?.parse().ok()?;
+    let state = CompetitionState::from_bits_truncate(fields.next()?.parse().ok()?);
+    let buttons = ControllerButtons::from_bits_truncate(fields.next()?.parse().ok()?);
+    let mut axes = [0i8; 4];
+    for axis in &mut axes {
+        *axis = fields.next()?.parse().ok()?;
+    }
+    let auton = fields.next()?.parse().ok()?;
+    Some((
+        t.max(0.0),
+        StatusPkt {
+            state,
+            controller_buttons: buttons,
+            controller_axes: axes,
+            auton,
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        brain::Brain,
+        command::{InstantCommand, Scheduler},
+        link::MockLink,
+        robot::RobotState,
+    };
+
+    const CAPTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/replay.cap");
+    // the triport pins of every control packet sent, rewritten when
+    // LEMON_BLESS is set
+    const GOLDEN: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/replay.golden");
+
+    // Replays a capture through the brain and controller with each
+    // button toggling the triport of its bit and compares the control
+    // packets sent back with a known good run.
+    #[test]
+    fn replay_matches_golden() {
+        let status = load_status(CAPTURE).expect("the capture is checked in");
+        let count = status.len();
+        let (link, control) = MockLink::new(status);
+        let (mut brain, mut controller) = Brain::with_link(Box::new(link));
+        let mut commands = (0..8).fold(Scheduler::new(), |commands, bit| {
+            let triport = brain.get_triport(bit + 1);
+            let button = ControllerButtons::from_bits_truncate(1 << bit);
+            commands.on_press(button, move || {
+                let triport = triport.clone();
+                Box::new(InstantCommand::new("toggle", Vec::new(), move || {
+                    triport.toggle()
+                }))
+            })
+        });
+
+        // the brain takes the first two packets when it starts, after
+        // that a control packet is sent for every status packet
+        let mut state = RobotState::default();
+        let mut replayed = 2;
+        brain.take_link_stats();
+        while replayed < count {
+            state = brain.update_state(&mut controller, &state);
+            if brain.take_link_stats().packets == 0 {
+                std::thread::yield_now();
+                continue;
+            }
+            replayed += 1;
+            commands.run(&controller);
+            brain.write_changes();
+        }
+
+        let output: String = control
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|pkt| format!("{:08b}\n", pkt.triport_pins))
+            .collect();
+        if std::env::var("LEMON_BLESS").is_ok() {
+            std::fs::write(GOLDEN, &output).unwrap();
+        }
+        let golden = std::fs::read_to_string(GOLDEN).expect("the golden file is checked in");
+        assert_eq!(output, golden);
+    }
+}
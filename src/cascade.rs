@@ -0,0 +1,114 @@
+use crate::{
+    motor::{AngularVelocity, Motor, Target},
+    path::{SettleCondition, Settled},
+    pid::Pid,
+    robot::RobotState,
+    subsystem::Subsystem,
+};
+
+// Position control of a motor or group of motors that move together
+// (e.g. a loader or catapult) done on the coprocessor. The outer
+// position loop here turns the position error into a velocity target
+// and the brain's velocity mode closes the inner loop, so mechanisms
+// can go to a position instead of driving until they pass it.
+#[derive(Debug, Clone)]
+pub struct CascadedPosition {
+    // the motors and if they are reversed
+    motors: Vec<(Motor, bool)>,
+    // output is the fraction of max_velocity to move at
+    pid: Pid,
+    max_velocity: AngularVelocity,
+    // error in motor position units and velocity in rpm
+    settle: Settled,
+    settled: bool,
+}
+
+impl CascadedPosition {
+    pub fn new(
+        motors: Vec<(Motor, bool)>,
+        pid: Pid,
+        max_velocity: AngularVelocity,
+        settle: SettleCondition,
+    ) -> Self {
+        Self {
+            motors,
+            pid,
+            max_velocity,
+            settle: Settled::new(settle),
+            settled: false,
+        }
+    }
+    pub fn set_target(&mut self, position: f64) {
+        self.pid.set_target(position);
+        self.pid.reset();
+        self.settle.reset();
+        self.settled = false;
+    }
+    pub fn target(&self) -> f64 {
+        self.pid.target()
+    }
+    // average position of the connected motors, None if none are connected
+    pub fn position(&self) -> Option<f64> {
+        let positions: Vec<f64> = self
+            .motors
+            .iter()
+            .filter_map(|(motor, rev)| {
+                let pos = motor.state()?.position as f64;
+                Some(if *rev { -pos } else { pos })
+            })
+            .collect();
+        if positions.is_empty() {
+            return None;
+        }
+        Some(positions.iter().sum::<f64>() / positions.len() as f64)
+    }
+    // sets the motor velocities, called every loop. Returns true once
+    // the motors have settled at the target
+    pub fn poll(&mut self) -> bool {
+        let Some(position) = self.position() else {
+            log::warn!("No motors connected for position control, stopping.");
+            self.stop();
+            return false;
+        };
+        let out = self.pid.poll(position).clamp(-1.0, 1.0);
+        let rpm = out * self.max_velocity.as_rpm();
+        for (motor, rev) in &mut self.motors {
+            motor.set_velocity(AngularVelocity::from_rpm(if *rev { -rpm } else { rpm }));
+        }
+
+        let velocity = self
+            .motors
+            .iter()
+            .filter_map(|(motor, _)| motor.state())
+            .map(|state| state.velocity as f64)
+            .fold(0.0, |max: f64, v| max.max(v.abs()));
+        self.settled = self.settle.update(self.pid.error(position), velocity);
+        self.settled
+    }
+    // if the last poll found the motors settled at the target
+    pub fn settled(&self) -> bool {
+        self.settled
+    }
+    pub fn stop(&mut self) {
+        for (motor, _) in &mut self.motors {
+            motor.set_target(Target::RotationalVelocity(0));
+        }
+    }
+}
+
+impl Subsystem for CascadedPosition {
+    fn name(&self) -> &'static str {
+        "position control"
+    }
+    fn periodic(&mut self, _state: RobotState) {
+        self.poll();
+    }
+    fn stop(&mut self) {
+        CascadedPosition::stop(self);
+    }
+    fn telemetry(&self) -> Vec<(&'static str, f64)> {
+        let mut values = vec![("target", self.target())];
+        values.extend(self.position().map(|p| ("position", p)));
+        values
+    }
+}
@@ -0,0 +1,394 @@
+use std::time::{Duration, Instant};
+
+use protocol::device::ControllerButtons;
+
+use crate::controller::Controller;
+
+// An action run by the Scheduler over one or more loops (e.g. spin up
+// the flywheel, fire the catapult). Commands hold their own handles to
+// what they control (motors, triports) and declare which subsystems
+// they use so two commands can never drive the same part at once.
+pub trait Command {
+    fn name(&self) -> &'static str;
+    // names of the subsystems used (see Subsystem::name)
+    fn requirements(&self) -> Vec<&'static str>;
+    fn initialize(&mut self) {}
+    // called every loop while the command is running
+    fn execute(&mut self);
+    fn is_finished(&mut self) -> bool {
+        false
+    }
+    // interrupted is true if the command was cancelled before finishing
+    fn end(&mut self, _interrupted: bool) {}
+}
+
+// runs f once
+pub struct InstantCommand<F: FnMut()> {
+    name: &'static str,
+    requirements: Vec<&'static str>,
+    f: F,
+}
+
+impl<F: FnMut()> InstantCommand<F> {
+    pub fn new(name: &'static str, requirements: Vec<&'static str>, f: F) -> Self {
+        Self {
+            name,
+            requirements,
+            f,
+        }
+    }
+}
+
+impl<F: FnMut()> Command for InstantCommand<F> {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+    fn requirements(&self) -> Vec<&'static str> {
+        self.requirements.clone()
+    }
+    fn execute(&mut self) {
+        (self.f)();
+    }
+    fn is_finished(&mut self) -> bool {
+        true
+    }
+}
+
+// runs f every loop until cancelled then runs stop, e.g. an intake
+// while a button is held
+pub struct RunCommand<F: FnMut(), S: FnMut()> {
+    name: &'static str,
+    requirements: Vec<&'static str>,
+    f: F,
+    stop: S,
+}
+
+impl<F: FnMut(), S: FnMut()> RunCommand<F, S> {
+    pub fn new(name: &'static str, requirements: Vec<&'static str>, f: F, stop: S) -> Self {
+        Self {
+            name,
+            requirements,
+            f,
+            stop,
+        }
+    }
+}
+
+impl<F: FnMut(), S: FnMut()> Command for RunCommand<F, S> {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+    fn requirements(&self) -> Vec<&'static str> {
+        self.requirements.clone()
+    }
+    fn execute(&mut self) {
+        (self.f)();
+    }
+    fn end(&mut self, _interrupted: bool) {
+        (self.stop)();
+    }
+}
+
+// ends the command (as interrupted) if it is still running after timeout
+pub struct Timeout {
+    command: Box<dyn Command>,
+    timeout: Duration,
+    start: Instant,
+    timed_out: bool,
+}
+
+impl Timeout {
+    pub fn new(command: Box<dyn Command>, timeout: Duration) -> Self {
+        Self {
+            command,
+            timeout,
+            start: Instant::now(),
+            timed_out: false,
+        }
+    }
+}
+
+impl Command for Timeout {
+    fn name(&self) -> &'static str {
+        self.command.name()
+    }
+    fn requirements(&self) -> Vec<&'static str> {
+        self.command.requirements()
+    }
+    fn initialize(&mut self) {
+        self.start = Instant::now();
+        self.timed_out = false;
+        self.command.initialize();
+    }
+    fn execute(&mut self) {
+        self.command.execute();
+    }
+    fn is_finished(&mut self) -> bool {
+        if self.command.is_finished() {
+            return true;
+        }
+        self.timed_out = self.start.elapsed() >= self.timeout;
+        self.timed_out
+    }
+    fn end(&mut self, interrupted: bool) {
+        if self.timed_out {
+            log::warn!("{} timed out after {:?}.", self.name(), self.timeout);
+        }
+        self.command.end(interrupted || self.timed_out);
+    }
+}
+
+// runs the commands one after another
+pub struct Sequence {
+    name: &'static str,
+    commands: Vec<Box<dyn Command>>,
+    current: usize,
+}
+
+impl Sequence {
+    pub fn new(name: &'static str, commands: Vec<Box<dyn Command>>) -> Self {
+        Self {
+            name,
+            commands,
+            current: 0,
+        }
+    }
+}
+
+impl Command for Sequence {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+    // every subsystem used by any step so the sequence isn't
+    // interrupted part way through by a command for a later step
+    fn requirements(&self) -> Vec<&'static str> {
+        let mut requirements: Vec<_> = self
+            .commands
+            .iter()
+            .flat_map(|c| c.requirements())
+            .collect();
+        requirements.sort_unstable();
+        requirements.dedup();
+        requirements
+    }
+    fn initialize(&mut self) {
+        self.current = 0;
+        if let Some(first) = self.commands.first_mut() {
+            first.initialize();
+        }
+    }
+    fn execute(&mut self) {
+        let Some(command) = self.commands.get_mut(self.current) else {
+            return;
+        };
+        command.execute();
+        if command.is_finished() {
+            command.end(false);
+            self.current += 1;
+            if let Some(next) = self.commands.get_mut(self.current) {
+                next.initialize();
+            }
+        }
+    }
+    fn is_finished(&mut self) -> bool {
+        self.current >= self.commands.len()
+    }
+    fn end(&mut self, interrupted: bool) {
+        if let Some(command) = self.commands.get_mut(self.current) {
+            command.end(interrupted);
+        }
+    }
+}
+
+// runs the commands together until they have all finished
+pub struct Parallel {
+    name: &'static str,
+    commands: Vec<(Box<dyn Command>, bool)>,
+}
+
+impl Parallel {
+    pub fn new(name: &'static str, commands: Vec<Box<dyn Command>>) -> Self {
+        let commands = commands.into_iter().map(|c| (c, false)).collect();
+        Self { name, commands }
+    }
+}
+
+impl Command for Parallel {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+    fn requirements(&self) -> Vec<&'static str> {
+        let mut requirements: Vec<_> = self
+            .commands
+            .iter()
+            .flat_map(|(c, _)| c.requirements())
+            .collect();
+        requirements.sort_unstable();
+        requirements.dedup();
+        requirements
+    }
+    fn initialize(&mut self) {
+        for (command, finished) in &mut self.commands {
+            *finished = false;
+            command.initialize();
+        }
+    }
+    fn execute(&mut self) {
+        for (command, finished) in self.commands.iter_mut().filter(|(_, f)| !f) {
+            command.execute();
+            if command.is_finished() {
+                command.end(false);
+                *finished = true;
+            }
+        }
+    }
+    fn is_finished(&mut self) -> bool {
+        self.commands.iter().all(|(_, finished)| *finished)
+    }
+    fn end(&mut self, interrupted: bool) {
+        for (command, _) in self.commands.iter_mut().filter(|(_, f)| !f) {
+            command.end(interrupted);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Trigger {
+    // scheduled when the button is pressed
+    Press,
+    // scheduled when pressed and cancelled when released
+    Held,
+    // scheduled when pressed and cancelled when pressed again
+    Toggle,
+}
+
+struct Binding {
+    button: ControllerButtons,
+    trigger: Trigger,
+    command: Box<dyn FnMut() -> Box<dyn Command>>,
+    // the command started by the binding if it is running
+    running: Option<u64>,
+}
+
+// Runs the scheduled commands every loop, starting commands bound to
+// controller buttons. A command that needs a subsystem in use by a
+// running command interrupts it.
+#[derive(Default)]
+pub struct Scheduler {
+    running: Vec<(u64, Box<dyn Command>)>,
+    bindings: Vec<Binding>,
+    next_id: u64,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    fn bind(
+        mut self,
+        button: ControllerButtons,
+        trigger: Trigger,
+        command: impl FnMut() -> Box<dyn Command> + 'static,
+    ) -> Self {
+        self.bindings.push(Binding {
+            button,
+            trigger,
+            command: Box::new(command),
+            running: None,
+        });
+        self
+    }
+    pub fn on_press(
+        self,
+        button: ControllerButtons,
+        command: impl FnMut() -> Box<dyn Command> + 'static,
+    ) -> Self {
+        self.bind(button, Trigger::Press, command)
+    }
+    pub fn while_held(
+        self,
+        button: ControllerButtons,
+        command: impl FnMut() -> Box<dyn Command> + 'static,
+    ) -> Self {
+        self.bind(button, Trigger::Held, command)
+    }
+    pub fn toggle_on_press(
+        self,
+        button: ControllerButtons,
+        command: impl FnMut() -> Box<dyn Command> + 'static,
+    ) -> Self {
+        self.bind(button, Trigger::Toggle, command)
+    }
+    // starts the command interrupting any using the same subsystems,
+    // returning an id for cancel
+    pub fn schedule(&mut self, mut command: Box<dyn Command>) -> u64 {
+        let requirements = command.requirements();
+        let (conflicting, running): (Vec<_>, Vec<_>) = std::mem::take(&mut self.running)
+            .into_iter()
+            .partition(|(_, c)| c.requirements().iter().any(|r| requirements.contains(r)));
+        self.running = running;
+        for (_, mut other) in conflicting {
+            log::info!("{} interrupted by {}.", other.name(), command.name());
+            other.end(true);
+        }
+        command.initialize();
+        let id = self.next_id;
+        self.next_id += 1;
+        self.running.push((id, command));
+        id
+    }
+    pub fn cancel(&mut self, id: u64) {
+        if let Some(i) = self.running.iter().position(|(i, _)| *i == id) {
+            let (_, mut command) = self.running.remove(i);
+            command.end(true);
+        }
+    }
+    // e.g. when the robot is disabled
+    pub fn cancel_all(&mut self) {
+        for (_, mut command) in self.running.drain(..) {
+            command.end(true);
+        }
+    }
+    pub fn is_running(&self, id: u64) -> bool {
+        self.running.iter().any(|(i, _)| *i == id)
+    }
+    // polls the bindings then runs every command once, called every loop
+    pub fn run(&mut self, controller: &Controller) {
+        for i in 0..self.bindings.len() {
+            let binding = &self.bindings[i];
+            let running = binding.running.filter(|&id| self.is_running(id));
+            let (start, stop) = match binding.trigger {
+                Trigger::Press => (controller.pressed(binding.button), false),
+                Trigger::Held => (
+                    controller.pressed(binding.button),
+                    controller.released(binding.button),
+                ),
+                Trigger::Toggle => {
+                    let pressed = controller.pressed(binding.button);
+                    (pressed && running.is_none(), pressed && running.is_some())
+                }
+            };
+            if stop {
+                if let Some(id) = running {
+                    self.cancel(id);
+                }
+                self.bindings[i].running = None;
+            } else if start {
+                let command = (self.bindings[i].command)();
+                self.bindings[i].running = Some(self.schedule(command));
+            }
+        }
+
+        let mut i = 0;
+        while i < self.running.len() {
+            let command = &mut self.running[i].1;
+            command.execute();
+            if command.is_finished() {
+                let (_, mut command) = self.running.remove(i);
+                command.end(false);
+            } else {
+                i += 1;
+            }
+        }
+    }
+}
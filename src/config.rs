@@ -0,0 +1,232 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+// how often the config file is checked for changes
+const FILE_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Default)]
+struct Value {
+    // f64 stored as bits so it can be shared without a lock
+    bits: AtomicU64,
+    // incremented on every change
+    version: AtomicU64,
+}
+
+impl Value {
+    fn new(v: f64) -> Self {
+        Self {
+            bits: AtomicU64::new(v.to_bits()),
+            version: AtomicU64::new(0),
+        }
+    }
+    fn get(&self) -> f64 {
+        f64::from_bits(self.bits.load(Ordering::Relaxed))
+    }
+    fn set(&self, v: f64) {
+        self.bits.store(v.to_bits(), Ordering::Relaxed);
+        self.version.fetch_add(1, Ordering::Release);
+    }
+}
+
+// A handle to a config value which always reads the latest
+// value, so parts can hold onto it and see changes live.
+#[derive(Debug, Clone)]
+pub struct Watched {
+    value: Arc<Value>,
+    seen: u64,
+}
+
+impl Watched {
+    pub fn get(&self) -> f64 {
+        self.value.get()
+    }
+    // returns true once after each change to the value
+    pub fn changed(&mut self) -> bool {
+        let version = self.value.version.load(Ordering::Acquire);
+        let changed = version != self.seen;
+        self.seen = version;
+        changed
+    }
+}
+
+// Runtime configurable values stored as `key = value` lines
+// (# starts a comment) which are reloaded when the file
+// changes and can be set at runtime (e.g. from the Mediator).
+pub struct RobotConfig {
+    path: PathBuf,
+    values: HashMap<String, Arc<Value>>,
+    // keys set at runtime since the file was last saved
+    unsaved: HashSet<String>,
+    modified: Option<SystemTime>,
+    last_poll: Instant,
+}
+
+impl RobotConfig {
+    pub fn load(path: impl Into<PathBuf>) -> Self {
+        let mut config = Self {
+            path: path.into(),
+            values: HashMap::new(),
+            unsaved: HashSet::new(),
+            modified: None,
+            last_poll: Instant::now(),
+        };
+        config.reload();
+        config
+    }
+    pub fn get(&self, key: &str) -> Option<f64> {
+        self.values.get(key).map(|v| v.get())
+    }
+    // watch a value, using default if the value has not been set
+    pub fn watch(&mut self, key: &str, default: f64) -> Watched {
+        let value = self
+            .values
+            .entry(key.to_owned())
+            .or_insert_with(|| Arc::new(Value::new(default)))
+            .clone();
+        let seen = value.version.load(Ordering::Acquire);
+        Watched { value, seen }
+    }
+    // sets a value which is written to the file on the next save
+    pub fn set(&mut self, key: &str, v: f64) {
+        if self.update(key, v) {
+            self.unsaved.insert(key.to_owned());
+        }
+    }
+    // returns true if the value changed
+    fn update(&mut self, key: &str, v: f64) -> bool {
+        match self.values.get(key) {
+            Some(value) if value.get() == v => false,
+            Some(value) => {
+                log::info!("Config value {key} changed to {v}");
+                value.set(v);
+                true
+            }
+            None => {
+                self.values.insert(key.to_owned(), Arc::new(Value::new(v)));
+                true
+            }
+        }
+    }
+    // reload the config file if it has changed since it was last read
+    pub fn poll_file(&mut self) {
+        if self.last_poll.elapsed() < FILE_POLL_INTERVAL {
+            return;
+        }
+        self.last_poll = Instant::now();
+        let modified = std::fs::metadata(&self.path)
+            .and_then(|m| m.modified())
+            .ok();
+        if modified.is_some() && modified != self.modified {
+            self.reload();
+        }
+    }
+    // Writes the values set since the last save to the config file,
+    // replacing their lines in place (keeping comments and every other
+    // line) and adding new keys to the end. Watched defaults that were
+    // never set aren't written.
+    pub fn save(&mut self) -> std::io::Result<()> {
+        if self.unsaved.is_empty() {
+            return Ok(());
+        }
+        let contents = match std::fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        let mut changes: Vec<_> = self
+            .unsaved
+            .iter()
+            .map(|k| (k.as_str(), self.values[k].get()))
+            .collect();
+        changes.sort_by(|a, b| a.0.cmp(b.0));
+        std::fs::write(&self.path, updated_contents(&contents, &changes))?;
+        self.unsaved.clear();
+        self.modified = std::fs::metadata(&self.path)
+            .and_then(|m| m.modified())
+            .ok();
+        Ok(())
+    }
+    fn reload(&mut self) {
+        let contents = match std::fs::read_to_string(&self.path) {
+            Ok(c) => c,
+            Err(e) => {
+                log::warn!("Failed to read config file {:?}: {e}", self.path);
+                return;
+            }
+        };
+        self.modified = std::fs::metadata(&self.path)
+            .and_then(|m| m.modified())
+            .ok();
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                log::warn!("Invalid line {} in config file: {line:?}", i + 1);
+                continue;
+            };
+            let Ok(value) = value.trim().parse::<f64>() else {
+                log::warn!("Invalid value on line {} in config file: {line:?}", i + 1);
+                continue;
+            };
+            self.update(key.trim(), value);
+        }
+        log::info!("Loaded config file {:?}", self.path);
+    }
+}
+
+// contents with the value of each changed key replaced on its line,
+// keys not in contents are added to the end
+fn updated_contents(contents: &str, changes: &[(&str, f64)]) -> String {
+    let mut written = vec![false; changes.len()];
+    let mut updated = String::new();
+    for line in contents.lines() {
+        let (setting, comment) = line.split_at(line.find('#').unwrap_or(line.len()));
+        let change = setting.split_once('=').and_then(|(key, _)| {
+            let i = changes.iter().position(|(k, _)| *k == key.trim())?;
+            Some((i, key))
+        });
+        match change {
+            Some((i, key)) => {
+                written[i] = true;
+                let comment = if comment.is_empty() {
+                    String::new()
+                } else {
+                    format!(" {comment}")
+                };
+                updated += &format!("{key}= {}{comment}\n", changes[i].1);
+            }
+            None => updated += &format!("{line}\n"),
+        }
+    }
+    for ((key, value), _) in changes.iter().zip(written).filter(|(_, w)| !w) {
+        updated += &format!("{key} = {value}\n");
+    }
+    updated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn updates_lines_in_place() {
+        let contents =
+            "# drive\ndrive.trim = 0.01 # per side\n\ndrive.mode=2\nimu.gyro_bias = 0.1\n";
+        let updated = updated_contents(contents, &[("drive.trim", 0.03), ("pid.angle.kp", 0.5)]);
+        assert_eq!(
+            updated,
+            "# drive\ndrive.trim = 0.03 # per side\n\ndrive.mode=2\nimu.gyro_bias = 0.1\npid.angle.kp = 0.5\n"
+        );
+        assert_eq!(updated_contents("", &[("a", 1.0)]), "a = 1\n");
+        assert_eq!(updated_contents("a=1\n", &[]), "a=1\n");
+    }
+}
@@ -1,11 +1,67 @@
 use protocol::device::ControllerButtons;
 
-use crate::brain::Packet;
+use crate::{brain::Packet, config::RobotConfig};
+
+// names of the axes in the config, in the order of Controller::axes
+const AXIS_NAMES: [&str; 4] = ["lx", "ly", "rx", "ry"];
+
+// shape of the response of an axis once outside the deadband
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum CurveShape {
+    #[default]
+    Linear,
+    // (e^(k|v|) - 1) / (e^k - 1), higher k gives finer control near the centre
+    Exponential(f64),
+    // w v^3 + (1 - w) v, blending linear (w = 0) and cubic (w = 1)
+    Cubic(f64),
+}
+
+// Deadband and response curve applied to a stick axis before it is used
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct AxisCurve {
+    pub deadband: f64,
+    pub shape: CurveShape,
+}
+
+impl AxisCurve {
+    // `<prefix>.deadband` and either `<prefix>.expo` or `<prefix>.cubic`
+    pub fn load(config: &RobotConfig, prefix: &str) -> Option<Self> {
+        let get = |key: &str| config.get(&format!("{prefix}.{key}"));
+        let shape = match (get("expo"), get("cubic")) {
+            (Some(k), _) if k != 0.0 => Some(CurveShape::Exponential(k)),
+            (_, Some(w)) => Some(CurveShape::Cubic(w.clamp(0.0, 1.0))),
+            _ => None,
+        };
+        let deadband = get("deadband");
+        if shape.is_none() && deadband.is_none() {
+            return None;
+        }
+        Some(Self {
+            deadband: deadband.unwrap_or(0.0).clamp(0.0, 0.99),
+            shape: shape.unwrap_or_default(),
+        })
+    }
+    pub fn apply(&self, v: f64) -> f64 {
+        let magnitude = v.abs().min(1.0);
+        if magnitude <= self.deadband {
+            return 0.0;
+        }
+        // rescale so the output starts from 0 at the edge of the deadband
+        let magnitude = (magnitude - self.deadband) / (1.0 - self.deadband);
+        let magnitude = match self.shape {
+            CurveShape::Linear => magnitude,
+            CurveShape::Exponential(k) => (k * magnitude).exp_m1() / k.exp_m1(),
+            CurveShape::Cubic(w) => w * magnitude.powi(3) + (1.0 - w) * magnitude,
+        };
+        magnitude.copysign(v)
+    }
+}
 
 pub struct Controller {
     last: ControllerButtons,
     current: ControllerButtons,
     axes: [f64; 4],
+    curves: [AxisCurve; 4],
 }
 
 impl From<[Packet; 2]> for Controller {
@@ -21,22 +77,37 @@ impl From<[Packet; 2]> for Controller {
             last: second.buttons,
             current: first.buttons,
             axes,
+            curves: [AxisCurve::default(); 4],
         }
     }
 }
 
 impl Controller {
+    // replaces the buttons and axes keeping the curves
+    pub fn update(&mut self, pkts: [Packet; 2]) {
+        let curves = self.curves;
+        *self = pkts.into();
+        self.curves = curves;
+    }
+    // Curves for each axis from the driver's settings in the config, a
+    // setting under `controller.<axis>` (e.g. `controller.ly.expo`)
+    // overrides one under `controller` which applies to every axis
+    pub fn load_curves(&mut self, config: &RobotConfig) {
+        let all = AxisCurve::load(config, "controller").unwrap_or_default();
+        self.curves = AXIS_NAMES
+            .map(|axis| AxisCurve::load(config, &format!("controller.{axis}")).unwrap_or(all));
+    }
     pub fn lx(&self) -> f64 {
-        self.axes[0]
+        self.curves[0].apply(self.axes[0])
     }
     pub fn ly(&self) -> f64 {
-        self.axes[1]
+        self.curves[1].apply(self.axes[1])
     }
     pub fn rx(&self) -> f64 {
-        self.axes[2]
+        self.curves[2].apply(self.axes[2])
     }
     pub fn ry(&self) -> f64 {
-        self.axes[3]
+        self.curves[3].apply(self.axes[3])
     }
     // helper function to check if a button matching with a bit is activated
     // in ControllerButtons. This also checks if only a single bit is being
@@ -67,3 +138,41 @@ impl Controller {
         self.last = self.current;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::close;
+
+    #[test]
+    fn curves_start_at_the_deadband_and_reach_full_stick() {
+        let shapes = [
+            CurveShape::Linear,
+            CurveShape::Exponential(3.0),
+            CurveShape::Cubic(0.5),
+        ];
+        for shape in shapes {
+            let curve = AxisCurve {
+                deadband: 0.1,
+                shape,
+            };
+            let mut last = 0.0;
+            for i in 0..=200 {
+                let v = i as f64 / 200.0;
+                let out = curve.apply(v);
+                assert_eq!(curve.apply(-v), -out, "{shape:?} isn't symmetric at {v}");
+                assert!(out >= last, "{shape:?} drops from {last} to {out} at {v}");
+                if v <= curve.deadband {
+                    assert_eq!(out, 0.0, "{shape:?} moves inside the deadband at {v}");
+                }
+                // no more then linear from the edge of the deadband, so
+                // there is no jump when leaving it
+                let linear = ((v - curve.deadband) / (1.0 - curve.deadband)).max(0.0);
+                assert!(out <= linear + 1e-12, "{shape:?} gives {out} at {v}");
+                last = out;
+            }
+            assert!(close(curve.apply(1.0), 1.0));
+            assert_eq!(curve.apply(1.5), curve.apply(1.0));
+        }
+    }
+}
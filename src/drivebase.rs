@@ -1,13 +1,181 @@
+use std::{
+    f64::consts::{FRAC_PI_2, PI, TAU},
+    time::{Duration, Instant},
+};
+
 use protocol::device::Gearbox;
 
 use crate::{
-    brain::Brain,
-    motor::{self, Motor},
+    brain::{Brain, PortError},
+    controller::Controller,
+    feedforward::Feedforward,
+    filters::SlewLimiter,
+    motor::{self, AngularVelocity, BrakeMode, Motor, MotorModel},
+    odom::MotorTracking,
+    pid::{AntiWindup, Pid, PidGains},
+    subsystem::Subsystem,
 };
 
+// bounds on the time between velocity targets used to find the
+// target acceleration, anything longer is treated as starting from rest
+const MIN_DT: f64 = 0.001;
+const MAX_DT: f64 = 0.1;
+// below this forward input curvature drive turns on the spot like arcade
+const QUICK_TURN_THRESHOLD: f64 = 0.1;
+// largest trim allowed, a side is never slowed by more than this fraction
+const MAX_TRIM: f64 = 0.2;
+// anti-tip stays on this long after the pitch rate drops back down
+const ANTI_TIP_HOLD: Duration = Duration::from_millis(300);
+// field centric turn output per radian of heading error
+const FIELD_CENTRIC_KP: f64 = 1.2;
+// stick deflection below which field centric drive doesn't move
+const FIELD_CENTRIC_DEADBAND: f64 = 0.1;
+
+// How the driver's sticks are mixed into left and right outputs, set by
+// "drive.mode" which can be changed at runtime
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DriveMode {
+    // left stick forward, right stick turns
+    #[default]
+    Arcade,
+    // each stick drives its side
+    Tank,
+    // like arcade but the right stick sets the curvature of the path so
+    // turning is as sharp at any speed, "cheesy" drive
+    Curvature,
+    // the left stick points the direction to drive on the field and
+    // the robot turns to face it (either way round) while driving
+    FieldCentric,
+}
+
+impl DriveMode {
+    // 0 arcade, 1 tank, 2 curvature and 3 field centric
+    pub fn from_config(value: f64) -> Self {
+        match value as i32 {
+            1 => Self::Tank,
+            2 => Self::Curvature,
+            3 => Self::FieldCentric,
+            _ => Self::Arcade,
+        }
+    }
+    // left and right percent outputs for the controller's sticks, heading
+    // is relative to the driver's forward and only used by field centric
+    pub fn sides(self, controller: &Controller, turn_multiplier: f64, heading: f64) -> (f64, f64) {
+        match self {
+            Self::Arcade => arcade(controller.ly(), controller.rx() * turn_multiplier),
+            Self::Tank => (controller.ly(), controller.ry()),
+            Self::Curvature => curvature(controller.ly(), controller.rx() * turn_multiplier),
+            Self::FieldCentric => field_centric(controller.lx(), controller.ly(), heading),
+        }
+    }
+}
+
+pub fn arcade(forward: f64, turn: f64) -> (f64, f64) {
+    (
+        (forward + turn).clamp(-1.0, 1.0),
+        (forward - turn).clamp(-1.0, 1.0),
+    )
+}
+
+// turn sets the curvature rather than the turn rate, near stopped it
+// turns on the spot as with arcade
+pub fn curvature(forward: f64, turn: f64) -> (f64, f64) {
+    if forward.abs() < QUICK_TURN_THRESHOLD {
+        return arcade(forward, turn);
+    }
+    let turn = forward.abs() * turn;
+    let (left, right) = (forward + turn, forward - turn);
+    // scale both down so the ratio (and so the curvature) is kept
+    let max = left.abs().max(right.abs()).max(1.0);
+    (left / max, right / max)
+}
+
+// Drives in the direction of the stick (x right and y away from the
+// driver) with heading being the robot's heading (rad, counter clockwise)
+// from facing away from the driver. The stick is rotated into the robot's
+// frame, the robot turns towards it and drives the part of it ahead (or
+// behind) of the robot so it only moves straight once facing the stick.
+pub fn field_centric(x: f64, y: f64, heading: f64) -> (f64, f64) {
+    let magnitude = x.hypot(y).min(1.0);
+    if magnitude < FIELD_CENTRIC_DEADBAND {
+        return (0.0, 0.0);
+    }
+    let wrap = |a: f64| (a + PI).rem_euclid(TAU) - PI;
+    let error = wrap((-x).atan2(y) - heading);
+    // drive backwards rather than turning around
+    let (error, direction) = if error.abs() > FRAC_PI_2 {
+        (wrap(error - PI), -1.0)
+    } else {
+        (error, 1.0)
+    };
+    let forward = direction * magnitude * error.cos();
+    // a counter clockwise error is a negative (left) arcade turn
+    arcade(forward, -FIELD_CENTRIC_KP * error * magnitude)
+}
+
+// Limits how fast the forward output can change while the robot is
+// pitching faster than max_pitch_rate (rad/s) so a tall robot that
+// starts to wheelie or tip when accelerating hard settles back down.
+// Turning isn't limited.
+#[derive(Debug, Clone, Copy)]
+pub struct AntiTip {
+    max_pitch_rate: f64,
+    forward: SlewLimiter,
+    tipping_since: Option<Instant>,
+}
+
+impl AntiTip {
+    // forward_rate is the max change of the forward output per second while tipping
+    pub fn new(max_pitch_rate: f64, forward_rate: f64) -> Self {
+        Self {
+            max_pitch_rate: max_pitch_rate.abs(),
+            forward: SlewLimiter::new(forward_rate),
+            tipping_since: None,
+        }
+    }
+    pub fn is_tipping(&self) -> bool {
+        self.tipping_since
+            .is_some_and(|t| t.elapsed() < ANTI_TIP_HOLD)
+    }
+    fn update(&mut self, pitch_rate: f64) {
+        if pitch_rate.abs() > self.max_pitch_rate {
+            if !self.is_tipping() {
+                log::warn!("Robot tipping ({pitch_rate} rad/s), limiting the drive acceleration.");
+            }
+            self.tipping_since = Some(Instant::now());
+        }
+    }
+    fn reset(&mut self) {
+        self.forward.reset(0.0);
+        self.tipping_since = None;
+    }
+    fn limit(&mut self, left: f64, right: f64) -> (f64, f64) {
+        let (forward, turn) = (0.5 * (left + right), 0.5 * (left - right));
+        if !self.is_tipping() {
+            self.forward.reset(forward);
+            return (left, right);
+        }
+        let forward = self.forward.limit(forward);
+        (forward + turn, forward - turn)
+    }
+}
+
 pub struct Tankdrive<const SIDE_N: usize> {
     left: [(Motor, bool); SIDE_N],
     right: [(Motor, bool); SIDE_N],
+    // when set velocities are converted to voltages by the model
+    // instead of using the brain's velocity mode
+    feedforward: Option<Feedforward>,
+    // when set the error between the target and measured velocity of
+    // each side is corrected on the coprocessor, added to the feedforward
+    velocity_pids: Option<[Pid; 2]>,
+    // last velocity targets (rpm) and when they were set
+    last_velocity: Option<([f64; 2], Instant)>,
+    // limits the rate of change of the left and right percent outputs
+    slew: Option<[SlewLimiter; 2]>,
+    anti_tip: Option<AntiTip>,
+    // see set_trim
+    trim: f64,
 }
 
 impl<const SIDE_N: usize> Tankdrive<SIDE_N> {
@@ -16,22 +184,135 @@ impl<const SIDE_N: usize> Tankdrive<SIDE_N> {
         right: [(u8, bool); SIDE_N],
         gearbox: Gearbox,
         brain: &mut Brain,
-    ) -> Self {
-        let to_motor_array = |v: [(u8, bool); SIDE_N]| v.map(|e| (brain.get_motor(e.0), e.1));
+    ) -> Result<Self, PortError> {
+        let mut claimed = Vec::with_capacity(2 * SIDE_N);
+        for &(port, rev) in left.iter().chain(&right) {
+            claimed.push((brain.claim_motor(port, rev, "drivebase")?, rev));
+        }
+        let mut claimed = claimed.into_iter();
+        let mut next = || claimed.next().expect("a motor is claimed for every port");
         let s = Self {
-            left: to_motor_array(left),
-            right: to_motor_array(right),
+            left: std::array::from_fn(|_| next()),
+            right: std::array::from_fn(|_| next()),
+            feedforward: None,
+            velocity_pids: None,
+            last_velocity: None,
+            slew: None,
+            anti_tip: None,
+            trim: 0.0,
         };
         brain.set_gearboxes(
             gearbox,
             s.left.iter().chain(s.right.iter()).map(|(m, _)| m.port()),
         );
-        s
+        Ok(s)
+    }
+    pub fn set_feedforward(&mut self, feedforward: Option<Feedforward>) {
+        self.feedforward = feedforward;
+        self.last_velocity = None;
+    }
+    // Gains of the per side velocity loops run here rather than by the
+    // brain's velocity mode, None to leave velocity to the feedforward (or
+    // the brain without one). The error and output are fractions of the
+    // gearbox's max velocity and of the max voltage.
+    pub fn set_velocity_pid(&mut self, gains: Option<PidGains>) {
+        self.velocity_pids = gains.map(|gains| {
+            let mut pid = Pid::with_gains(gains);
+            pid.anti_windup = AntiWindup::ConditionalIntegration;
+            [pid.clone(), pid]
+        });
+        self.last_velocity = None;
+    }
+    // what the drive does when stopped e.g. hold so it can't be pushed
+    pub fn set_brake_mode(&mut self, mode: BrakeMode) {
+        for (motor, _) in self.left.iter_mut().chain(self.right.iter_mut()) {
+            motor.set_brake_mode(mode);
+        }
+    }
+    // current limit (mA) of each drive motor so pushing doesn't brown out the brain
+    pub fn set_current_limit(&mut self, limit: Option<i16>) {
+        for (motor, _) in self.left.iter_mut().chain(self.right.iter_mut()) {
+            motor.set_current_limit(limit);
+        }
+    }
+    // the gearing and wheels of every drive motor
+    pub fn set_model(&mut self, model: MotorModel) {
+        for (motor, _) in self.left.iter_mut().chain(self.right.iter_mut()) {
+            motor.set_model(model);
+        }
+    }
+    // average wheel surface speed (m/s) of each side of the connected
+    // motors, None for a side without any or without a wheel diameter
+    pub fn side_velocities(&self) -> [Option<f64>; 2] {
+        [&self.left, &self.right].map(|side| {
+            let velocities: Vec<f64> = side
+                .iter()
+                .filter_map(|(motor, rev)| {
+                    let v = motor.linear_velocity()?;
+                    Some(if *rev { -v } else { v })
+                })
+                .collect();
+            (!velocities.is_empty())
+                .then(|| velocities.iter().sum::<f64>() / velocities.len() as f64)
+        })
+    }
+    // max change of the percent outputs per second, None for no limit
+    pub fn set_slew(&mut self, rate: Option<f64>) {
+        self.slew = rate.map(|rate| [SlewLimiter::new(rate); 2]);
+    }
+    // Slows one side by a fraction so a side that drags less doesn't make
+    // straight driving curve, positive slows the right side (steering
+    // right) and negative the left. Clamped to MAX_TRIM.
+    pub fn set_trim(&mut self, trim: f64) {
+        if trim.abs() > MAX_TRIM {
+            log::warn!("A drive trim of {trim} is larger then the max of {MAX_TRIM}. Clamping.");
+        }
+        self.trim = trim.clamp(-MAX_TRIM, MAX_TRIM);
+    }
+    pub fn trim(&self) -> f64 {
+        self.trim
+    }
+    // Applies the trim to open loop driver outputs, closed loop outputs
+    // (auton and the turn controller) correct for the drag themselves.
+    pub fn apply_trim(&self, left: f64, right: f64) -> (f64, f64) {
+        (
+            left * (1.0 + self.trim.min(0.0)),
+            right * (1.0 - self.trim.max(0.0)),
+        )
     }
+    pub fn set_anti_tip(&mut self, anti_tip: Option<AntiTip>) {
+        self.anti_tip = anti_tip;
+    }
+    // called every loop with the IMU's pitch rate (see AntiTip)
+    pub fn update_pitch_rate(&mut self, pitch_rate: Option<f64>) {
+        if let (Some(anti_tip), Some(rate)) = (self.anti_tip.as_mut(), pitch_rate) {
+            anti_tip.update(rate);
+        }
+    }
+    fn slew(&mut self, left: f64, right: f64) -> (f64, f64) {
+        let (left, right) = match self.slew.as_mut() {
+            Some([l, r]) => (l.limit(left), r.limit(right)),
+            None => (left, right),
+        };
+        match self.anti_tip.as_mut() {
+            Some(anti_tip) => anti_tip.limit(left, right),
+            None => (left, right),
+        }
+    }
+    // odometry from the drive motor encoders, scale is meters per unit of motor position
+    pub fn tracking_source(&self, scale: f64) -> MotorTracking {
+        MotorTracking::new([&self.left, &self.right].map(|side| side.to_vec()), scale)
+    }
+    // handles to the left and right side motors
+    pub fn motors(&self) -> [Vec<Motor>; 2] {
+        [&self.left, &self.right].map(|side| side.iter().map(|(m, _)| m.clone()).collect())
+    }
+    #[track_caller]
     pub fn set_side_percent_voltage(&mut self, left: f64, right: f64) {
         if left.abs() > 1.0 || right.abs() > 1.0 {
             log::warn!("Tankdrive::set_side_percent_voltage recieved values outside of [-1, 1]: (left: {left}, right: {right}). Values will be clamped");
         }
+        let (left, right) = self.slew(left.clamp(-1.0, 1.0), right.clamp(-1.0, 1.0));
 
         let map_val = |v: f64, rev: bool| {
             let mut v = v.clamp(-1.0, 1.0);
@@ -48,27 +329,212 @@ impl<const SIDE_N: usize> Tankdrive<SIDE_N> {
             motor.set_target(motor::Target::PercentVoltage(map_val(right, *rev)));
         }
     }
-    pub fn set_side_percent_max_rpm(&mut self, left: f64, right: f64, max_rpm: f64) {
+    // sets the side velocities as a fraction of max_velocity
+    #[track_caller]
+    pub fn set_side_percent_max_velocity(
+        &mut self,
+        left: f64,
+        right: f64,
+        max_velocity: AngularVelocity,
+    ) {
         if left.abs() > 1.0 || right.abs() > 1.0 {
-            log::warn!("Tankdrive::set_side_percent_max_rpm recieved values outside of [-1, 1]: (left: {left}, right: {right}). Values will be clamped");
+            log::warn!("Tankdrive::set_side_percent_max_velocity recieved values outside of [-1, 1]: (left: {left}, right: {right}). Values will be clamped");
         }
+        let max_rpm = max_velocity.as_rpm();
         if max_rpm < 0.0 {
-            log::warn!("Tankdrive::set_side_percent_max_rpm recieved a negative max_rpm: {max_rpm} rpm. Value will be made positive");
+            log::warn!("Tankdrive::set_side_percent_max_velocity recieved a negative max velocity: {max_rpm} rpm. Value will be made positive");
         }
 
-        let map_val = |v: f64, rev: bool| {
-            let mut v = v.clamp(-1.0, 1.0);
+        let (left, right) = self.slew(left.clamp(-1.0, 1.0), right.clamp(-1.0, 1.0));
+        let map_val = |v: f64| AngularVelocity::from_rpm(v * max_rpm.abs());
+        self.set_side_velocity(map_val(left), map_val(right));
+    }
+    // sets the velocity of each side, clamped to the max velocity of the gearbox
+    #[track_caller]
+    pub fn set_side_velocity(&mut self, left: AngularVelocity, right: AngularVelocity) {
+        if self.feedforward.is_some() || self.velocity_pids.is_some() {
+            self.set_side_voltage_velocity(left, right);
+            return;
+        }
+        self.last_velocity = None;
+
+        let map_val = |v: AngularVelocity, rev: bool| {
             if rev {
-                v = -v;
+                AngularVelocity::from_rpm(-v.as_rpm())
+            } else {
+                v
             }
-            (v * max_rpm) as i16
         };
 
         for (motor, rev) in &mut self.left {
-            motor.set_target(motor::Target::RotationalVelocity(map_val(left, *rev)));
+            motor.set_velocity(map_val(left, *rev));
         }
         for (motor, rev) in &mut self.right {
-            motor.set_target(motor::Target::RotationalVelocity(map_val(right, *rev)));
+            motor.set_velocity(map_val(right, *rev));
+        }
+    }
+    // average measured velocity (rpm) of the connected motors of each side
+    fn measured_velocity(&self) -> [Option<f64>; 2] {
+        [&self.left, &self.right].map(|side| {
+            let velocities: Vec<f64> = side
+                .iter()
+                .filter_map(|(motor, rev)| {
+                    let v = motor.state()?.velocity as f64;
+                    Some(if *rev { -v } else { v })
+                })
+                .collect();
+            (!velocities.is_empty())
+                .then(|| velocities.iter().sum::<f64>() / velocities.len() as f64)
+        })
+    }
+    // velocity by voltage from the feedforward and velocity loops
+    #[track_caller]
+    fn set_side_voltage_velocity(&mut self, left: AngularVelocity, right: AngularVelocity) {
+        let now = Instant::now();
+        let velocity = [left.as_rpm(), right.as_rpm()];
+        // acceleration of the targets, zero when there is no recent target
+        let (accel, recent) = match self.last_velocity {
+            Some((last, t)) if now.duration_since(t).as_secs_f64() < MAX_DT => {
+                let dt = now.duration_since(t).as_secs_f64().max(MIN_DT);
+                ([0, 1].map(|i| (velocity[i] - last[i]) / dt), true)
+            }
+            _ => ([0.0; 2], false),
+        };
+        self.last_velocity = Some((velocity, now));
+
+        let max_rpm = self
+            .left
+            .first()
+            .and_then(|(m, _)| m.gearbox())
+            .map_or(
+                AngularVelocity::max_for(Gearbox::Green),
+                AngularVelocity::max_for,
+            )
+            .as_rpm();
+        let measured = self.measured_velocity();
+        let max_mv = motor::MAX_MILLIVOLT as f64;
+        let mut voltage = [0, 1].map(|i| {
+            self.feedforward.map_or(0.0, |ff| {
+                ff.voltage(AngularVelocity::from_rpm(velocity[i]), accel[i])
+            })
+        });
+        if let Some(pids) = self.velocity_pids.as_mut() {
+            for ((pid, v), (target, measured)) in pids
+                .iter_mut()
+                .zip(&mut voltage)
+                .zip(velocity.into_iter().zip(measured))
+            {
+                // start from a clean integral after the drive was driven another way
+                if !recent {
+                    pid.reset();
+                }
+                // without a measurement there is nothing to correct
+                let Some(measured) = measured else {
+                    continue;
+                };
+                pid.set_target(target / max_rpm);
+                *v += pid.poll(measured / max_rpm).clamp(-1.0, 1.0) * max_mv;
+            }
+        }
+
+        let map_val = |side: usize, rev: bool| {
+            let v = voltage[side].clamp(-max_mv, max_mv) as i16;
+            motor::Target::Voltage(if rev { -v } else { v })
+        };
+        for (motor, rev) in &mut self.left {
+            motor.set_target(map_val(0, *rev));
+        }
+        for (motor, rev) in &mut self.right {
+            motor.set_target(map_val(1, *rev));
+        }
+    }
+}
+
+impl<const SIDE_N: usize> Subsystem for Tankdrive<SIDE_N> {
+    fn name(&self) -> &'static str {
+        "drivebase"
+    }
+    // stops straight away rather than ramping down through the limiters,
+    // which start again from zero when the robot is enabled
+    fn stop(&mut self) {
+        for limiter in self.slew.iter_mut().flatten() {
+            limiter.reset(0.0);
+        }
+        if let Some(anti_tip) = self.anti_tip.as_mut() {
+            anti_tip.reset();
+        }
+        for pid in self.velocity_pids.iter_mut().flatten() {
+            pid.reset();
+        }
+        self.last_velocity = None;
+        for (motor, _) in self.left.iter_mut().chain(self.right.iter_mut()) {
+            motor.set_target(motor::Target::PercentVoltage(0.0));
+        }
+    }
+    fn telemetry(&self) -> Vec<(&'static str, f64)> {
+        let [left, right] = self.measured_velocity();
+        [("left_rpm", left), ("right_rpm", right)]
+            .into_iter()
+            .filter_map(|(key, v)| Some((key, v?)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, close};
+
+    #[test]
+    fn curvature_keeps_the_turn_radius_at_any_speed() {
+        let mut sweep = testing::Sweep::new(3333);
+        for _ in 0..1000 {
+            let speed = sweep.range(QUICK_TURN_THRESHOLD, 1.0);
+            let forward = if sweep.range(0.0, 1.0) < 0.5 {
+                -speed
+            } else {
+                speed
+            };
+            let turn = sweep.range(-1.0, 1.0);
+            let (left, right) = curvature(forward, turn);
+            assert!(left.abs() <= 1.0 && right.abs() <= 1.0);
+            // the ratio of the sides sets the radius of the turn so the
+            // same stick gives the same radius however fast the robot goes
+            let ratio = (left - right) / (left + right);
+            assert!(
+                close(ratio, turn * forward.signum()),
+                "curvature({forward}, {turn}) = ({left}, {right})"
+            );
+        }
+        // nearly stopped it turns on the spot like arcade
+        assert_eq!(curvature(0.0, 0.5), arcade(0.0, 0.5));
+    }
+
+    #[test]
+    fn field_centric_only_depends_on_the_stick_relative_to_the_robot() {
+        let mut sweep = testing::Sweep::new(3334);
+        for _ in 0..1000 {
+            let (x, y) = (sweep.range(-1.0, 1.0), sweep.range(-1.0, 1.0));
+            let heading = sweep.range(-TAU, TAU);
+            let sides = field_centric(x, y, heading);
+
+            // turning the stick and the robot together changes nothing
+            let turn = sweep.range(-TAU, TAU);
+            let (sin, cos) = turn.sin_cos();
+            let (turned_x, turned_y) = (x * cos - y * sin, y * cos + x * sin);
+            let turned = field_centric(turned_x, turned_y, heading + turn);
+            assert!(
+                close(sides.0, turned.0) && close(sides.1, turned.1),
+                "stick ({x}, {y}) at {heading} gives {sides:?} but {turned:?} turned by {turn}"
+            );
+
+            // facing the stick drives straight at the stick's magnitude
+            let facing = (-x).atan2(y);
+            let (left, right) = field_centric(x, y, facing);
+            let magnitude = x.hypot(y).min(1.0);
+            if magnitude >= FIELD_CENTRIC_DEADBAND {
+                assert!(close(left, magnitude) && close(right, magnitude));
+            }
         }
     }
 }
@@ -0,0 +1,187 @@
+// Extended Kalman filter over the pose [x, y, heading]. Movement
+// measured by the tracking wheels drives the prediction and the IMU
+// heading and the position reached by the drive encoders are fused in
+// as measurements. The covariance says how much the pose can be
+// trusted, it grows as the robot drives and shrinks with each reading.
+
+// variance of the pose when it is set, roughly how well the robot can
+// be placed by hand, (1cm)^2 and (1deg)^2
+const POSITION_PRIOR: f64 = 1e-4;
+const HEADING_PRIOR: f64 = 3e-4;
+
+#[derive(Debug, Clone, Copy)]
+pub struct PoseEkf {
+    state: [f64; 3],
+    covariance: [[f64; 3]; 3],
+    // variance added per meter driven and per radian turned
+    pub distance_noise: f64,
+    pub turn_noise: f64,
+    // variance added to [x, y, heading] every prediction, even when
+    // still, so the filter never becomes certain of the pose
+    pub step_noise: [f64; 3],
+}
+
+impl PoseEkf {
+    pub fn new(position: [f64; 2], heading: f64) -> Self {
+        Self {
+            state: [position[0], position[1], heading],
+            covariance: [
+                [POSITION_PRIOR, 0.0, 0.0],
+                [0.0, POSITION_PRIOR, 0.0],
+                [0.0, 0.0, HEADING_PRIOR],
+            ],
+            distance_noise: 1e-4,
+            turn_noise: 1e-3,
+            step_noise: [1e-8, 1e-8, 1e-8],
+        }
+    }
+    pub fn position(&self) -> [f64; 2] {
+        [self.state[0], self.state[1]]
+    }
+    pub fn heading(&self) -> f64 {
+        self.state[2]
+    }
+    pub fn covariance(&self) -> [[f64; 3]; 3] {
+        self.covariance
+    }
+    // moves the pose by a displacement in the robot frame, the
+    // displacement should already be along the arc driven (a chord)
+    // so it is applied at the average heading over the movement
+    pub fn predict(&mut self, local: [f64; 2], diff_heading: f64) {
+        let [dx, dy] = local;
+        let (sin, cos) = (self.state[2] + 0.5 * diff_heading).sin_cos();
+        self.state[0] += cos * dx - sin * dy;
+        self.state[1] += sin * dx + cos * dy;
+        self.state[2] += diff_heading;
+
+        // jacobian of the motion with respect to the state, only the
+        // heading affects the position
+        let f = [
+            [1.0, 0.0, -sin * dx - cos * dy],
+            [0.0, 1.0, cos * dx - sin * dy],
+            [0.0, 0.0, 1.0],
+        ];
+        let mut p = mul(mul(f, self.covariance), transpose(f));
+        let dist = (dx.powi(2) + dy.powi(2)).sqrt();
+        p[0][0] += self.distance_noise * dist;
+        p[1][1] += self.distance_noise * dist;
+        p[2][2] += self.turn_noise * diff_heading.abs();
+        for (i, noise) in self.step_noise.into_iter().enumerate() {
+            p[i][i] += noise;
+        }
+        self.covariance = p;
+    }
+    // adds variance to the position when the movement measured is
+    // suspect (e.g. the robot was hit) without moving the pose
+    pub fn inflate_position(&mut self, variance: f64) {
+        self.covariance[0][0] += variance;
+        self.covariance[1][1] += variance;
+    }
+    // Squared heading innovation over its variance (the normalised
+    // innovation squared), how many variances a heading measurement is
+    // from the prediction. Large values mean the prediction was wrong.
+    pub fn heading_nis(&self, heading: f64, variance: f64) -> f64 {
+        let s = self.covariance[2][2] + variance;
+        (heading - self.state[2]).powi(2) / s
+    }
+    // fuses a direct measurement of the heading with the given variance
+    pub fn update_heading(&mut self, heading: f64, variance: f64) {
+        let s = self.covariance[2][2] + variance;
+        if s <= 0.0 {
+            // both are certain so the measurement is taken as is
+            self.state[2] = heading;
+            return;
+        }
+        let gain = [0, 1, 2].map(|i| self.covariance[i][2] / s);
+        let innovation = heading - self.state[2];
+        for (x, k) in self.state.iter_mut().zip(gain) {
+            *x += k * innovation;
+        }
+        // P = (I - KH)P where H picks out the heading
+        let row = self.covariance[2];
+        for (i, k) in gain.into_iter().enumerate() {
+            for (j, r) in row.into_iter().enumerate() {
+                self.covariance[i][j] -= k * r;
+            }
+        }
+    }
+    // fuses a measurement of the position with the given variance on
+    // each axis
+    pub fn update_position(&mut self, position: [f64; 2], variance: f64) {
+        let p = self.covariance;
+        // S = HPH' + R where H picks out the position
+        let s = [[p[0][0] + variance, p[0][1]], [p[1][0], p[1][1] + variance]];
+        let det = s[0][0] * s[1][1] - s[0][1] * s[1][0];
+        if det <= 0.0 {
+            return;
+        }
+        let s_inv = [
+            [s[1][1] / det, -s[0][1] / det],
+            [-s[1][0] / det, s[0][0] / det],
+        ];
+        // K = PH'S^-1
+        let gain: [[f64; 2]; 3] = std::array::from_fn(|i| {
+            std::array::from_fn(|j| p[i][0] * s_inv[0][j] + p[i][1] * s_inv[1][j])
+        });
+        let innovation = [position[0] - self.state[0], position[1] - self.state[1]];
+        for (x, k) in self.state.iter_mut().zip(gain) {
+            *x += k[0] * innovation[0] + k[1] * innovation[1];
+        }
+        // P = (I - KH)P
+        for (i, k) in gain.into_iter().enumerate() {
+            for j in 0..3 {
+                self.covariance[i][j] -= k[0] * p[0][j] + k[1] * p[1][j];
+            }
+        }
+    }
+    pub fn set_position(&mut self, position: [f64; 2]) {
+        self.state[0] = position[0];
+        self.state[1] = position[1];
+        for i in 0..3 {
+            for j in 0..2 {
+                self.covariance[i][j] = 0.0;
+                self.covariance[j][i] = 0.0;
+            }
+        }
+        self.covariance[0][0] = POSITION_PRIOR;
+        self.covariance[1][1] = POSITION_PRIOR;
+    }
+    pub fn set_heading(&mut self, heading: f64) {
+        self.state[2] = heading;
+        for i in 0..3 {
+            self.covariance[i][2] = 0.0;
+            self.covariance[2][i] = 0.0;
+        }
+        self.covariance[2][2] = HEADING_PRIOR;
+    }
+}
+
+fn mul(a: [[f64; 3]; 3], b: [[f64; 3]; 3]) -> [[f64; 3]; 3] {
+    std::array::from_fn(|i| std::array::from_fn(|j| (0..3).map(|k| a[i][k] * b[k][j]).sum()))
+}
+
+fn transpose(a: [[f64; 3]; 3]) -> [[f64; 3]; 3] {
+    std::array::from_fn(|i| std::array::from_fn(|j| a[j][i]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::close;
+
+    #[test]
+    fn position_updates_weigh_by_the_variances() {
+        let mut ekf = PoseEkf::new([0.0, 0.0], 0.0);
+        ekf.covariance[0][0] = 0.04;
+        ekf.covariance[1][1] = 0.01;
+        ekf.update_position([1.0, 1.0], 0.01);
+        // the less certain x moves further towards the measurement
+        assert!(close(ekf.position()[0], 0.8));
+        assert!(close(ekf.position()[1], 0.5));
+        assert!(close(ekf.covariance()[0][0], 0.008));
+        assert!(close(ekf.covariance()[1][1], 0.005));
+        // the heading isn't correlated with the position so is left alone
+        assert_eq!(ekf.heading(), 0.0);
+        assert_eq!(ekf.covariance()[2][2], HEADING_PRIOR);
+    }
+}
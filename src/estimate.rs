@@ -0,0 +1,161 @@
+use std::{collections::VecDeque, time::Instant};
+
+use crate::config::RobotConfig;
+
+// how the samples are fit when estimating the rate of change
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FitKind {
+    // slope of a least squares line through the window, smooth but lags
+    // behind changes in velocity by about half the window
+    #[default]
+    Linear,
+    // Savitzky-Golay style quadratic fit evaluated at the newest sample,
+    // lags less while accelerating at the cost of more noise
+    Quadratic,
+}
+
+// Estimates the first and second derivative of N values from a sliding
+// window of timestamped samples by fitting a polynomial to the window.
+// The samples don't need to be evenly spaced in time.
+#[derive(Debug, Clone)]
+pub struct Differentiator<const N: usize> {
+    window: usize,
+    kind: FitKind,
+    samples: VecDeque<(Instant, [f64; N])>,
+}
+
+impl<const N: usize> Differentiator<N> {
+    pub fn new(window: usize, kind: FitKind) -> Self {
+        // a quadratic needs at least 3 points
+        let window = window.max(3);
+        Self {
+            window,
+            kind,
+            samples: VecDeque::with_capacity(window),
+        }
+    }
+    // window under `<prefix>.window` and a quadratic fit if `<prefix>.quadratic` is set
+    pub fn load(config: &RobotConfig, prefix: &str, default_window: usize) -> Self {
+        let window = config
+            .get(&format!("{prefix}.window"))
+            .map_or(default_window, |w| w as usize);
+        let kind = match config.get(&format!("{prefix}.quadratic")) {
+            Some(v) if v != 0.0 => FitKind::Quadratic,
+            _ => FitKind::Linear,
+        };
+        Self::new(window, kind)
+    }
+    pub fn push(&mut self, time: Instant, vals: [f64; N]) {
+        if self.samples.len() == self.window {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((time, vals));
+    }
+    // moves every sample so a jump (e.g. setting the pose) isn't seen as movement
+    pub fn shift(&mut self, offset: [f64; N]) {
+        for (_, vals) in &mut self.samples {
+            for (v, o) in vals.iter_mut().zip(offset) {
+                *v += o;
+            }
+        }
+    }
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+    // rate of change at the newest sample, None until there are enough samples
+    pub fn rate(&self) -> Option<[f64; N]> {
+        match self.kind {
+            FitKind::Linear => self.fit_linear(),
+            FitKind::Quadratic => self.fit_quadratic().map(|(rate, _)| rate),
+        }
+    }
+    // second derivative at the newest sample, always from a quadratic fit
+    pub fn acceleration(&self) -> Option<[f64; N]> {
+        self.fit_quadratic().map(|(_, accel)| accel)
+    }
+    // times relative to the newest sample
+    fn times(&self) -> Vec<f64> {
+        let Some(&(newest, _)) = self.samples.back() else {
+            return Vec::new();
+        };
+        self.samples
+            .iter()
+            .map(|(t, _)| -newest.duration_since(*t).as_secs_f64())
+            .collect()
+    }
+    fn fit_linear(&self) -> Option<[f64; N]> {
+        if self.samples.len() < 2 {
+            return None;
+        }
+        let times = self.times();
+        let inv_len = 1.0 / times.len() as f64;
+        let avg_time = times.iter().sum::<f64>() * inv_len;
+        let denom = times.iter().map(|t| (t - avg_time).powi(2)).sum::<f64>();
+        let mut slope = [0.0; N];
+        for (i, s) in slope.iter_mut().enumerate() {
+            let avg = self.samples.iter().map(|(_, v)| v[i]).sum::<f64>() * inv_len;
+            *s = self
+                .samples
+                .iter()
+                .zip(&times)
+                .map(|((_, v), t)| (v[i] - avg) * (t - avg_time))
+                .sum::<f64>()
+                / denom;
+        }
+        slope.iter().all(|s| s.is_finite()).then_some(slope)
+    }
+    // fits v = c0 + c1 t + c2 t^2 with t = 0 at the newest sample
+    // returning (c1, 2 c2), the derivatives at the newest sample
+    fn fit_quadratic(&self) -> Option<([f64; N], [f64; N])> {
+        if self.samples.len() < 3 {
+            return None;
+        }
+        let times = self.times();
+        // sums of t^k for the normal equations
+        let mut st = [0.0; 5];
+        for t in &times {
+            let mut p = 1.0;
+            for s in &mut st {
+                *s += p;
+                p *= t;
+            }
+        }
+        let m = [
+            [st[0], st[1], st[2]],
+            [st[1], st[2], st[3]],
+            [st[2], st[3], st[4]],
+        ];
+        let det = det3(m);
+        if det.abs() < 1e-18 {
+            return None;
+        }
+
+        let mut rate = [0.0; N];
+        let mut accel = [0.0; N];
+        for i in 0..N {
+            let mut b = [0.0; 3];
+            for ((_, v), t) in self.samples.iter().zip(&times) {
+                b[0] += v[i];
+                b[1] += v[i] * t;
+                b[2] += v[i] * t * t;
+            }
+            // Cramer's rule for c1 and c2
+            let replace = |col: usize| {
+                let mut m = m;
+                for (row, b) in m.iter_mut().zip(b) {
+                    row[col] = b;
+                }
+                det3(m) / det
+            };
+            rate[i] = replace(1);
+            accel[i] = 2.0 * replace(2);
+        }
+        (rate.iter().chain(&accel).all(|v| v.is_finite())).then_some((rate, accel))
+    }
+}
+
+fn det3(m: [[f64; 3]; 3]) -> f64 {
+    m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+        - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+        + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+}
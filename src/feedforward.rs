@@ -0,0 +1,44 @@
+use crate::{
+    config::RobotConfig,
+    motor::{AngularVelocity, MAX_MILLIVOLT},
+};
+
+// Motor model converting a target velocity and acceleration to the
+// voltage needed to reach it, V = kS * sign(v) + kV * v + kA * a.
+// This drives the motors directly by voltage rather then relying on
+// the brain's velocity mode which saturates near the max velocity.
+// kS is in mV, kV in mV per rpm and kA in mV per rpm/s.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Feedforward {
+    pub ks: f64,
+    pub kv: f64,
+    pub ka: f64,
+}
+
+impl Feedforward {
+    pub const fn new(ks: f64, kv: f64, ka: f64) -> Self {
+        Self { ks, kv, ka }
+    }
+    // the model under `<prefix>.ks`, `<prefix>.kv` and `<prefix>.ka`,
+    // None if kV isn't set as the model would do nothing without it
+    pub fn load(config: &RobotConfig, prefix: &str) -> Option<Self> {
+        let kv = config.get(&format!("{prefix}.kv"))?;
+        Some(Self {
+            ks: config.get(&format!("{prefix}.ks")).unwrap_or(0.0),
+            kv,
+            ka: config.get(&format!("{prefix}.ka")).unwrap_or(0.0),
+        })
+    }
+    // voltage in mV clamped to what the motor can output
+    pub fn voltage(&self, velocity: AngularVelocity, accel: f64) -> f64 {
+        let rpm = velocity.as_rpm();
+        // no static friction to overcome when holding still
+        let ks = if rpm == 0.0 {
+            0.0
+        } else {
+            self.ks * rpm.signum()
+        };
+        let max = MAX_MILLIVOLT as f64;
+        (ks + self.kv * rpm + self.ka * accel).clamp(-max, max)
+    }
+}
@@ -0,0 +1,67 @@
+// Named positions on the field so routes don't repeat magic numbers.
+// Positions are relative to where the robot was when odometry started
+// (x forwards and y to the left of the robot), so the robot has to be
+// placed at its auton start before the program starts. Only the heading
+// is reset at the start of auton.
+
+// a length on the field, typed so measurements taken in inches (e.g.
+// off the field drawings) can't be mixed up with meters
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Length(f64);
+
+impl Length {
+    pub const fn meters(meters: f64) -> Self {
+        Self(meters)
+    }
+    pub const fn inches(inches: f64) -> Self {
+        Self(inches * 0.0254)
+    }
+    pub const fn as_meters(self) -> f64 {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Waypoint {
+    pub x: Length,
+    pub y: Length,
+}
+
+impl Waypoint {
+    // lined up with the match load zone
+    pub const LOAD_ZONE_APPROACH: Self = Self::new(Length::meters(1.313), Length::meters(0.0));
+    // where the robot turns to back into the match load bar
+    pub const LOAD_ZONE: Self = Self::new(Length::meters(1.318), Length::meters(0.62));
+    // clear of the match load bar after loading
+    pub const LOAD_ZONE_EXIT: Self = Self::new(Length::meters(1.254), Length::meters(0.724));
+
+    pub const fn new(x: Length, y: Length) -> Self {
+        Self { x, y }
+    }
+    // the position in meters as used by odometry and the path segments
+    pub const fn meters(self) -> [f64; 2] {
+        [self.x.as_meters(), self.y.as_meters()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::close;
+
+    #[test]
+    fn inches_and_meters_name_the_same_place() {
+        let [x, y] = Waypoint::new(Length::inches(24.0), Length::inches(-12.0)).meters();
+        assert!(close(x, 0.6096));
+        assert!(close(y, -0.3048));
+        assert!(Length::inches(1.0) < Length::meters(0.03));
+    }
+
+    #[test]
+    fn load_zone_route_is_unchanged() {
+        // the points the route used before they were named
+        assert_eq!(Waypoint::LOAD_ZONE_APPROACH.meters(), [1.313, 0.0]);
+        assert_eq!(Waypoint::LOAD_ZONE.meters(), [1.318, 0.62]);
+        assert_eq!(Waypoint::LOAD_ZONE_EXIT.meters(), [1.254, 0.724]);
+    }
+}
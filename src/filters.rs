@@ -0,0 +1,45 @@
+use std::time::Instant;
+
+// longest time between calls counted towards the allowed change, so a
+// limiter that wasn't used for a while doesn't let a step straight through
+const MAX_DT: f64 = 0.05;
+
+// Limits how fast a value can change, e.g. the drive voltage so a step
+// from full forwards to full backwards doesn't lift the wheels or trip
+// the current limit. The value starts at zero.
+#[derive(Debug, Clone, Copy)]
+pub struct SlewLimiter {
+    // max change per second
+    rate: f64,
+    value: f64,
+    last: Option<Instant>,
+}
+
+impl SlewLimiter {
+    pub fn new(rate: f64) -> Self {
+        Self {
+            rate: rate.abs(),
+            value: 0.0,
+            last: None,
+        }
+    }
+    // moves towards target by at most rate times the time since the last call
+    pub fn limit(&mut self, target: f64) -> f64 {
+        let now = Instant::now();
+        let dt = self
+            .last
+            .map_or(0.0, |t| now.duration_since(t).as_secs_f64().min(MAX_DT));
+        let max_change = self.rate * dt;
+        self.value += (target - self.value).clamp(-max_change, max_change);
+        self.last = Some(now);
+        self.value
+    }
+    pub fn value(&self) -> f64 {
+        self.value
+    }
+    // jumps straight to value e.g. when the output is taken over by something else
+    pub fn reset(&mut self, value: f64) {
+        self.value = value;
+        self.last = None;
+    }
+}
@@ -0,0 +1,43 @@
+// Gyro used by odometry for the heading, so the IMU can be swapped
+// without touching the odometry. All angles are in radians.
+pub trait HeadingSensor: Send {
+    // reads the sensor and integrates the heading, called every odometry update
+    fn calc_heading(&mut self) -> f64;
+    fn heading(&self) -> f64;
+    fn set_heading(&mut self, heading: f64);
+    // rad/s from the last reading
+    fn angular_velocity(&self) -> f64;
+    // zeros the heading and restarts the integration
+    fn reset(&mut self);
+    // offset added to every angular velocity reading in rad/s
+    fn bias(&self) -> f64;
+    fn set_bias(&mut self, bias: f64);
+    // multiplier applied after the bias (see GyroScale)
+    fn scale(&self) -> f64;
+    fn set_scale(&mut self, scale: f64);
+    // false while the sensor isn't responding, the heading isn't
+    // updated so odometry tracks it some other way until it returns
+    fn is_healthy(&self) -> bool {
+        true
+    }
+    // degrees C, None if the sensor can't measure it
+    fn temperature(&self) -> Option<f64> {
+        None
+    }
+    // change in bias (rad/s) per degree C corrected for as the sensor
+    // warms up, ignored without a temperature (see GyroTemperature)
+    fn temperature_slope(&self) -> f64 {
+        0.0
+    }
+    fn set_temperature_slope(&mut self, _slope: f64) {}
+    // rad/s about the robot's left axis (nose down is positive) from
+    // the last reading, None if the sensor only measures yaw
+    fn pitch_rate(&self) -> Option<f64> {
+        None
+    }
+    // robot frame x (forwards) and y (left) acceleration in m/s^2 from
+    // the last reading, None without an accelerometer
+    fn acceleration(&self) -> Option<[f64; 2]> {
+        None
+    }
+}
@@ -0,0 +1,261 @@
+use std::time::Instant;
+
+use crate::{
+    brain::{Brain, PortError},
+    motor::{self, Motor, Target},
+    path::{SettleCondition, Settled},
+    pid::Pid,
+    robot::RobotState,
+    subsystem::Subsystem,
+};
+
+// output (fraction of max voltage) needed to hold the lift against gravity
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Gravity {
+    #[default]
+    None,
+    // the same at every height, for a linear lift
+    Elevator(f64),
+    // largest when the arm is horizontal, for an arm lift. The arm is
+    // horizontal at the given position and units_per_radian converts
+    // from motor position to the arm angle
+    Arm {
+        kg: f64,
+        horizontal: f64,
+        units_per_radian: f64,
+    },
+}
+
+impl Gravity {
+    fn output(&self, position: f64) -> f64 {
+        match *self {
+            Self::None => 0.0,
+            Self::Elevator(kg) => kg,
+            Self::Arm {
+                kg,
+                horizontal,
+                units_per_radian,
+            } => kg * ((position - horizontal) / units_per_radian).cos(),
+        }
+    }
+}
+
+// A lift (or arm) moved between named preset heights along a trapezoidal
+// profile. The pid follows the profile with the gravity feedforward
+// holding the lift up, and targets and manual power are kept within the
+// soft limits. Positions are motor positions (see POSITION_UNITS_PER_REV).
+// Shared as an Rc<RefCell<Lift>> between the Subsystems, which move it
+// every loop, and the commands and path segments that set its target.
+#[derive(Debug)]
+pub struct Lift {
+    motors: Vec<(Motor, bool)>,
+    presets: Vec<(&'static str, f64)>,
+    // output is the fraction of max voltage on top of the feedforward
+    pid: Pid,
+    gravity: Gravity,
+    // units per second (squared)
+    max_velocity: f64,
+    max_acceleration: f64,
+    limits: Option<[f64; 2]>,
+    settle: Settled,
+    // the profile being followed, (start, goal, when it started)
+    profile: Option<(f64, f64, Instant)>,
+    settled: bool,
+}
+
+impl Lift {
+    pub fn new(
+        motors: &[(u8, bool)],
+        pid: Pid,
+        max_velocity: f64,
+        max_acceleration: f64,
+        settle: SettleCondition,
+        brain: &Brain,
+    ) -> Result<Self, PortError> {
+        Ok(Self {
+            motors: motors
+                .iter()
+                .map(|&(port, rev)| Ok((brain.claim_motor(port, rev, "lift")?, rev)))
+                .collect::<Result<_, PortError>>()?,
+            presets: Vec::new(),
+            pid,
+            gravity: Gravity::None,
+            max_velocity,
+            max_acceleration,
+            limits: None,
+            settle: Settled::new(settle),
+            profile: None,
+            settled: false,
+        })
+    }
+    pub fn preset(mut self, name: &'static str, position: f64) -> Self {
+        self.presets.push((name, position));
+        self
+    }
+    pub fn gravity(mut self, gravity: Gravity) -> Self {
+        self.gravity = gravity;
+        self
+    }
+    pub fn soft_limits(mut self, min: f64, max: f64) -> Self {
+        self.limits = Some([min.min(max), min.max(max)]);
+        self
+    }
+    fn clamp(&self, position: f64) -> f64 {
+        match self.limits {
+            Some([min, max]) => position.clamp(min, max),
+            None => position,
+        }
+    }
+    // average position of the connected motors, None if none are connected
+    pub fn position(&self) -> Option<f64> {
+        let positions: Vec<f64> = self
+            .motors
+            .iter()
+            .filter_map(|(motor, rev)| {
+                let pos = motor.state()?.position as f64;
+                Some(if *rev { -pos } else { pos })
+            })
+            .collect();
+        if positions.is_empty() {
+            return None;
+        }
+        Some(positions.iter().sum::<f64>() / positions.len() as f64)
+    }
+    // moves to a preset, false if there is no preset with the name
+    pub fn go_to(&mut self, name: &str) -> bool {
+        let Some(&(_, position)) = self.presets.iter().find(|(n, _)| *n == name) else {
+            log::warn!("The lift has no preset named {name}.");
+            return false;
+        };
+        self.set_target(position);
+        true
+    }
+    // moves to a position, clamped to the soft limits
+    pub fn set_target(&mut self, position: f64) {
+        let goal = self.clamp(position);
+        if goal != position {
+            log::warn!("Lift target {position} is outside the soft limits, moving to {goal}.");
+        }
+        // the profile starts from where the lift is (or was heading)
+        let start = self
+            .position()
+            .or(self.profile.map(|(_, goal, _)| goal))
+            .unwrap_or(goal);
+        self.profile = Some((start, goal, Instant::now()));
+        self.pid.reset();
+        self.settle.reset();
+        self.settled = false;
+    }
+    pub fn target(&self) -> Option<f64> {
+        self.profile.map(|(_, goal, _)| goal)
+    }
+    // name of the preset the lift is going to (or at)
+    pub fn target_preset(&self) -> Option<&'static str> {
+        let target = self.target()?;
+        self.presets
+            .iter()
+            .find(|(_, p)| *p == target)
+            .map(|(name, _)| *name)
+    }
+    // the next preset above (up) or below the current target, for
+    // stepping through the presets with buttons
+    pub fn step_preset(&mut self, up: bool) {
+        let current = self.target().or(self.position()).unwrap_or(0.0);
+        let next = self
+            .presets
+            .iter()
+            .map(|(_, p)| *p)
+            .filter(|&p| if up { p > current } else { p < current })
+            .min_by(|a, b| (a - current).abs().total_cmp(&(b - current).abs()));
+        if let Some(next) = next {
+            self.set_target(next);
+        }
+    }
+    // sets the output every loop to follow the profile, returns true
+    // once settled at the target
+    pub fn poll(&mut self) -> bool {
+        let Some((start, goal, started)) = self.profile else {
+            return false;
+        };
+        let Some(position) = self.position() else {
+            log::warn!("No lift motors connected, stopping.");
+            self.stop();
+            return false;
+        };
+        let (setpoint, velocity) = motor::trapezoid(
+            start,
+            goal,
+            self.max_velocity,
+            self.max_acceleration,
+            started.elapsed().as_secs_f64(),
+        );
+        self.pid.set_target(setpoint);
+        let output = self.pid.poll(position) + self.gravity.output(position);
+        self.set_output(output);
+
+        let measured = self
+            .motors
+            .iter()
+            .filter_map(|(motor, _)| motor.state())
+            .map(|state| state.velocity as f64)
+            .fold(0.0, |max: f64, v| max.max(v.abs()));
+        // the profile has to have finished for the lift to be settled, a
+        // move to where the lift already is finishes straight away
+        let error = if setpoint == goal && velocity == 0.0 {
+            goal - position
+        } else {
+            f64::INFINITY
+        };
+        self.settled = self.settle.update(error, measured);
+        self.settled
+    }
+    pub fn settled(&self) -> bool {
+        self.settled
+    }
+    // Drives the lift by hand (fraction of max voltage), stopping at the
+    // soft limits. Cancels the target until the next set_target.
+    pub fn manual(&mut self, power: f64) {
+        self.profile = None;
+        let position = self.position();
+        let blocked = match (self.limits, position) {
+            (Some([min, max]), Some(pos)) => {
+                (pos <= min && power < 0.0) || (pos >= max && power > 0.0)
+            }
+            _ => false,
+        };
+        let power = if blocked { 0.0 } else { power };
+        let gravity = position.map_or(0.0, |pos| self.gravity.output(pos));
+        self.set_output(power + gravity);
+    }
+    // stops the profile leaving the motors unpowered
+    pub fn stop(&mut self) {
+        self.profile = None;
+        for (motor, _) in &mut self.motors {
+            motor.set_target(Target::None);
+        }
+    }
+    fn set_output(&mut self, output: f64) {
+        let output = output.clamp(-1.0, 1.0);
+        for (motor, rev) in &mut self.motors {
+            motor.set_target(Target::PercentVoltage(if *rev { -output } else { output }));
+        }
+    }
+}
+
+impl Subsystem for Lift {
+    fn name(&self) -> &'static str {
+        "lift"
+    }
+    fn periodic(&mut self, _state: RobotState) {
+        self.poll();
+    }
+    fn stop(&mut self) {
+        Lift::stop(self);
+    }
+    fn telemetry(&self) -> Vec<(&'static str, f64)> {
+        let mut values = Vec::new();
+        values.extend(self.target().map(|t| ("target", t)));
+        values.extend(self.position().map(|p| ("position", p)));
+        values
+    }
+}
@@ -0,0 +1,184 @@
+use std::{
+    collections::VecDeque,
+    sync::{mpsc, Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use client::coprocessor::serial::{find_v5_port, Serial, SerialSpawner};
+use protocol::{
+    device::{CompetitionState, Gearbox},
+    ControlPkt, StatusPkt,
+};
+
+// how often a simulated status packet is produced while the brain isn't connected
+const BENCH_PKT_INTERVAL: Duration = Duration::from_millis(10);
+// time between attempts to find the brain in bench mode
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+// The connection between the coprocessor and the brain. On the robot
+// this is the serial port but a MockLink can stand in for it to run
+// the full stack off the robot.
+pub trait Link {
+    fn take_status_pkt(&mut self) -> Option<(Instant, StatusPkt)>;
+    fn set_control_pkt(&mut self, pkt: ControlPkt);
+    fn set_gearboxes(&mut self, gearboxes: Vec<(u8, Gearbox)>);
+}
+
+impl Link for Serial {
+    fn take_status_pkt(&mut self) -> Option<(Instant, StatusPkt)> {
+        Serial::take_status_pkt(self)
+    }
+    fn set_control_pkt(&mut self, pkt: ControlPkt) {
+        Serial::set_control_pkt(self, pkt);
+    }
+    fn set_gearboxes(&mut self, gearboxes: Vec<(u8, Gearbox)>) {
+        Serial::set_gearboxes(self, gearboxes.into_iter());
+        self.update_gearboxes();
+    }
+}
+
+// a single attempt at opening the serial connection to the brain
+pub fn try_connect() -> Option<Serial> {
+    let port = find_v5_port().ok()?;
+    let sp = SerialSpawner::open(&port.0.port_name).ok()?;
+    Some(sp.spawn_threaded(None))
+}
+
+// Used in bench mode, reports a disabled brain with an idle controller
+// until the brain is plugged in (checking every RECONNECT_INTERVAL) so
+// the robot can be started on a desk without waiting on the brain.
+// Finding and opening the port can block so each attempt runs on its
+// own thread.
+pub struct BenchLink {
+    serial: Option<Serial>,
+    last_pkt: Instant,
+    last_attempt: Instant,
+    // the result of the attempt in progress
+    attempt: Option<mpsc::Receiver<Option<Serial>>>,
+    // applied once the brain is connected
+    gearboxes: Vec<(u8, Gearbox)>,
+}
+
+impl BenchLink {
+    pub fn new() -> Self {
+        let mut link = Self {
+            serial: None,
+            last_pkt: Instant::now(),
+            last_attempt: Instant::now(),
+            attempt: None,
+            gearboxes: Vec::new(),
+        };
+        link.reconnect();
+        link
+    }
+    pub fn is_connected(&self) -> bool {
+        self.serial.is_some()
+    }
+    fn reconnect(&mut self) {
+        self.last_attempt = Instant::now();
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            // the link may have been dropped by the time this finishes
+            let _ = tx.send(try_connect());
+        });
+        self.attempt = Some(rx);
+    }
+    // takes the serial connection if the attempt in progress found the brain
+    fn poll_attempt(&mut self) {
+        let Some(attempt) = &self.attempt else {
+            return;
+        };
+        let serial = match attempt.try_recv() {
+            Ok(serial) => serial,
+            Err(mpsc::TryRecvError::Empty) => return,
+            Err(mpsc::TryRecvError::Disconnected) => None,
+        };
+        self.attempt = None;
+        let Some(mut serial) = serial else {
+            return;
+        };
+        log::info!("Bench mode connected to the brain.");
+        if !self.gearboxes.is_empty() {
+            Link::set_gearboxes(&mut serial, std::mem::take(&mut self.gearboxes));
+        }
+        self.serial = Some(serial);
+    }
+}
+
+impl Default for BenchLink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Link for BenchLink {
+    fn take_status_pkt(&mut self) -> Option<(Instant, StatusPkt)> {
+        if let Some(serial) = &mut self.serial {
+            return Link::take_status_pkt(serial);
+        }
+        self.poll_attempt();
+        if self.serial.is_some() {
+            return None;
+        }
+        if self.attempt.is_none() && self.last_attempt.elapsed() > RECONNECT_INTERVAL {
+            self.reconnect();
+        }
+        if self.last_pkt.elapsed() < BENCH_PKT_INTERVAL {
+            return None;
+        }
+        self.last_pkt = Instant::now();
+        let pkt = StatusPkt {
+            state: CompetitionState::DISABLED,
+            ..Default::default()
+        };
+        Some((self.last_pkt, pkt))
+    }
+    fn set_control_pkt(&mut self, pkt: ControlPkt) {
+        if let Some(serial) = &mut self.serial {
+            Link::set_control_pkt(serial, pkt);
+        }
+    }
+    fn set_gearboxes(&mut self, gearboxes: Vec<(u8, Gearbox)>) {
+        match &mut self.serial {
+            Some(serial) => Link::set_gearboxes(serial, gearboxes),
+            None => self.gearboxes.extend(gearboxes),
+        }
+    }
+}
+
+// Feeds a queue of status packets (e.g. from a recorded match) to the
+// robot as their timestamps pass and records every control packet
+// sent back so the output can be compared against a known good run.
+pub struct MockLink {
+    status: VecDeque<(Instant, StatusPkt)>,
+    control: Arc<Mutex<Vec<ControlPkt>>>,
+}
+
+impl MockLink {
+    // returns the link and a handle to the control packets it receives
+    pub fn new(
+        status: impl IntoIterator<Item = (Instant, StatusPkt)>,
+    ) -> (Self, Arc<Mutex<Vec<ControlPkt>>>) {
+        let control = Arc::new(Mutex::new(Vec::new()));
+        (
+            Self {
+                status: status.into_iter().collect(),
+                control: control.clone(),
+            },
+            control,
+        )
+    }
+}
+
+impl Link for MockLink {
+    fn take_status_pkt(&mut self) -> Option<(Instant, StatusPkt)> {
+        if self.status.front()?.0 > Instant::now() {
+            return None;
+        }
+        self.status.pop_front()
+    }
+    fn set_control_pkt(&mut self, pkt: ControlPkt) {
+        self.control.lock().unwrap().push(pkt);
+    }
+    fn set_gearboxes(&mut self, _gearboxes: Vec<(u8, Gearbox)>) {}
+}
@@ -1,25 +1,32 @@
-use crate::brain::Brain;
+use crate::brain::{Brain, PortError};
+use crate::mirror::MirroredPair;
 use crate::motor;
-use crate::motor::Motor;
+
+// degrees the sides can drift apart before one is taken to have
+// slipped, the motors are stopped rather then grinding the other side
+const DIVERGE_THRESHOLD: i32 = 90;
 
 pub struct Loader {
-    motors: [(Motor, bool); 2],
+    motors: MirroredPair,
 }
 
 impl Loader {
-    pub fn new(motors: [(u8, bool); 2], brain: &Brain) -> Self {
-        Self {
-            motors: motors.map(|e| (brain.get_motor(e.0), e.1)),
-        }
+    pub fn new(motors: [(u8, bool); 2], brain: &Brain) -> Result<Self, PortError> {
+        Ok(Self {
+            motors: MirroredPair::new("loader", motors, DIVERGE_THRESHOLD, true, brain)?,
+        })
     }
     pub fn set_side_percent_voltage(&mut self, percent: f64) {
         if percent.abs() > 1.0 {
             log::warn!("Loader::set_side_percent_voltage recieved values outside of [-1, 1]: {percent}. Values will be clamped");
         }
 
-        for (motor, rev) in &mut self.motors {
-            let v = if *rev { -percent } else { percent };
-            motor.set_target(motor::Target::PercentVoltage(v));
-        }
+        self.motors.check();
+        self.motors
+            .set_target(motor::Target::PercentVoltage(percent));
+    }
+    // a ball is stuck if either motor has stalled
+    pub fn is_jammed(&self) -> bool {
+        self.motors.is_stalled()
     }
 }
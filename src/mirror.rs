@@ -0,0 +1,144 @@
+use crate::{
+    brain::{Brain, PortError},
+    motor::{Motor, Target},
+    robot::RobotState,
+    subsystem::Subsystem,
+};
+
+// Two motors driving the same mechanism from either side (e.g. a
+// catapult or loader) which always get the same command, with the
+// reversed motor getting the negated command. Their positions are
+// cross-checked so one side failing (skipped gear, broken coupler)
+// is caught rather then hidden by averaging the two sides.
+pub struct MirroredPair {
+    name: &'static str,
+    motors: [(Motor, bool); 2],
+    // max allowed difference in position between the sides
+    threshold: i32,
+    // stop both motors once the sides diverge
+    auto_stop: bool,
+    // difference in position when the sides were last in sync
+    offset: Option<i32>,
+    diverged: bool,
+}
+
+impl MirroredPair {
+    pub fn new(
+        name: &'static str,
+        motors: [(u8, bool); 2],
+        threshold: i32,
+        auto_stop: bool,
+        brain: &Brain,
+    ) -> Result<Self, PortError> {
+        let claim = |(port, rev): (u8, bool)| -> Result<_, PortError> {
+            Ok((brain.claim_motor(port, rev, name)?, rev))
+        };
+        Ok(Self {
+            name,
+            motors: [claim(motors[0])?, claim(motors[1])?],
+            threshold,
+            auto_stop,
+            offset: None,
+            diverged: false,
+        })
+    }
+    #[track_caller]
+    pub fn set_target(&mut self, target: Target) {
+        if self.diverged && self.auto_stop {
+            return;
+        }
+        for (motor, rev) in &mut self.motors {
+            motor.set_target(if *rev { negate(target) } else { target });
+        }
+    }
+    // Checks the sides are in sync, this should be called every loop.
+    // Returns false once the sides have diverged.
+    pub fn check(&mut self) -> bool {
+        if self.diverged {
+            return false;
+        }
+        let Some(diff) = self.position_diff() else {
+            return true;
+        };
+        let offset = *self.offset.get_or_insert(diff);
+        if (diff - offset).abs() <= self.threshold {
+            return true;
+        }
+
+        self.diverged = true;
+        log::error!(
+            "{}: motors on ports {} and {} diverged by {} (threshold {}).",
+            self.name,
+            self.motors[0].0.port(),
+            self.motors[1].0.port(),
+            diff - offset,
+            self.threshold
+        );
+        if self.auto_stop {
+            log::error!("{}: stopping both motors.", self.name);
+            for (motor, _) in &mut self.motors {
+                motor.set_target(Target::None);
+            }
+        }
+        false
+    }
+    // either side has stalled
+    pub fn is_stalled(&self) -> bool {
+        self.motors.iter().any(|(motor, _)| motor.is_stalled())
+    }
+    pub fn has_diverged(&self) -> bool {
+        self.diverged
+    }
+    // clears a divergence (e.g. after a repair) resyncing the sides
+    pub fn reset(&mut self) {
+        self.diverged = false;
+        self.offset = None;
+    }
+    // difference between the positions of the sides, with the
+    // reversed side negated so that in sync sides have a fixed offset
+    fn position_diff(&self) -> Option<i32> {
+        let [a, b] = &self.motors;
+        let pos = |(motor, rev): &(Motor, bool)| {
+            motor
+                .state()
+                .map(|s| if *rev { -s.position } else { s.position })
+        };
+        Some(pos(a)? - pos(b)?)
+    }
+}
+
+impl Subsystem for MirroredPair {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+    fn periodic(&mut self, _state: RobotState) {
+        self.check();
+    }
+    fn disabled(&mut self) {
+        self.check();
+    }
+    fn stop(&mut self) {
+        for (motor, _) in &mut self.motors {
+            motor.set_target(Target::None);
+        }
+    }
+}
+
+fn negate(target: Target) -> Target {
+    match target {
+        Target::None => Target::None,
+        Target::RotationalVelocity(v) => Target::RotationalVelocity(-v),
+        Target::Voltage(v) => Target::Voltage(-v),
+        Target::PercentVoltage(v) => Target::PercentVoltage(-v),
+        Target::Position(p) => Target::Position(-p),
+        Target::ProfiledPosition {
+            target,
+            max_vel,
+            max_accel,
+        } => Target::ProfiledPosition {
+            target: -target,
+            max_vel,
+            max_accel,
+        },
+    }
+}
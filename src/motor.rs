@@ -1,8 +1,68 @@
-use std::sync::{Arc, RwLock};
+use std::{
+    panic::Location,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
+    time::{Duration, Instant},
+};
 
 use protocol::device::{Gearbox, MotorState};
 
+use crate::config::RobotConfig;
+
 pub const MAX_MILLIVOLT: i32 = 12000;
+// the brain reports motor positions in degrees of the output shaft
+pub const POSITION_UNITS_PER_REV: f64 = 360.0;
+// rpm commanded per unit of position error by the onboard position loop
+const POSITION_KP: f64 = 0.5;
+// motor temperatures (C) for the thermal levels, the motors start
+// limiting their own power at 55C
+const WARM_TEMPERATURE: f64 = 45.0;
+const HOT_TEMPERATURE: f64 = 55.0;
+// a motor must cool this far below a level before leaving it
+const THERMAL_HYSTERESIS: f64 = 2.0;
+
+// when enabled every write to a motor target is recorded along with
+// where it came from so code fighting over a motor can be found from
+// the logs (see Brain::write_changes)
+static ARBITRATION_LOG: AtomicBool = AtomicBool::new(false);
+
+pub fn set_arbitration_log(enabled: bool) {
+    ARBITRATION_LOG.store(enabled, Ordering::Relaxed);
+}
+
+pub fn arbitration_log_enabled() -> bool {
+    ARBITRATION_LOG.load(Ordering::Relaxed)
+}
+
+// angular velocity of a motor output shaft, stored in rpm
+// since that is the unit the brain uses for velocity targets
+#[derive(Debug, Default, Clone, Copy, PartialEq, PartialOrd)]
+pub struct AngularVelocity(f64);
+
+impl AngularVelocity {
+    pub const fn from_rpm(rpm: f64) -> Self {
+        Self(rpm)
+    }
+    pub fn from_rad_per_sec(v: f64) -> Self {
+        Self(v * 60.0 / std::f64::consts::TAU)
+    }
+    pub fn as_rpm(self) -> f64 {
+        self.0
+    }
+    pub fn as_rad_per_sec(self) -> f64 {
+        self.0 * std::f64::consts::TAU / 60.0
+    }
+    // the maximum velocity of a motor with the given gearbox
+    pub fn max_for(gearbox: Gearbox) -> Self {
+        match gearbox {
+            Gearbox::Red => Self(100.0),
+            Gearbox::Green => Self(200.0),
+            Gearbox::Blue => Self(600.0),
+        }
+    }
+}
 
 // placeholder
 #[derive(Debug, Clone)]
@@ -15,6 +75,46 @@ pub struct Motor {
 pub struct MotorInner {
     state: Option<MotorState>,
     target: Target,
+    gearbox: Option<Gearbox>,
+    // writes to the target since the last loop (if the arbitration log is enabled)
+    writers: Vec<(&'static Location<'static>, Target)>,
+    // the owner of the ExclusiveMotor handle if there is one
+    owner: Option<&'static str>,
+    // a rejected write has been logged since the motor was claimed
+    rejected_logged: bool,
+    brake_mode: BrakeMode,
+    // max current in mA (see Brain::write_changes)
+    current_limit: Option<i16>,
+    model: MotorModel,
+    stall_detection: StallDetection,
+    // when the motor started looking stalled
+    stalling_since: Option<Instant>,
+    stalled: bool,
+    // a stall started since take_stall_started was last called
+    stall_started: bool,
+}
+
+// A motor is stalled when it has been commanded at least min_power
+// (as a fraction of the max voltage or velocity) while turning slower
+// than max_velocity (rpm) and drawing at least min_current (mA) for
+// the whole window.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StallDetection {
+    pub min_power: f64,
+    pub max_velocity: f64,
+    pub min_current: i16,
+    pub window: Duration,
+}
+
+impl Default for StallDetection {
+    fn default() -> Self {
+        Self {
+            min_power: 0.3,
+            max_velocity: 5.0,
+            min_current: 1000,
+            window: Duration::from_millis(250),
+        }
+    }
 }
 
 #[derive(Debug, Default, Clone, Copy, PartialEq)]
@@ -24,6 +124,189 @@ pub enum Target {
     RotationalVelocity(i16),
     Voltage(i16),
     PercentVoltage(f64),
+    // position (see POSITION_UNITS_PER_REV) held by a loop on the
+    // coprocessor commanding the brain's velocity mode (see PositionLoop)
+    Position(i32),
+    // the same but moving along a trapezoidal profile with a max
+    // velocity and acceleration in position units per second (squared)
+    ProfiledPosition {
+        target: i32,
+        max_vel: f64,
+        max_accel: f64,
+    },
+}
+
+// What a motor does when it is stopped (a target of None or zero
+// voltage). The control packet only has voltage and velocity so the
+// modes are done with those by the brain when writing targets.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum BrakeMode {
+    // no power, the motor spins freely
+    #[default]
+    Coast,
+    // a velocity target of zero, resists movement but can be pushed
+    Brake,
+    // holds the position the motor stopped at (see PositionLoop)
+    Hold,
+}
+
+impl BrakeMode {
+    // 0 coast, 1 brake and 2 hold
+    pub fn from_config(value: f64) -> Self {
+        match value as i32 {
+            1 => Self::Brake,
+            2 => Self::Hold,
+            _ => Self::Coast,
+        }
+    }
+}
+
+// What a motor drives so its position and velocity can be read at the
+// output instead of every user multiplying by its own constants
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MotorModel {
+    // output revolutions per motor (cartridge) revolution
+    pub gear_ratio: f64,
+    // diameter (m) of a wheel on the output if there is one
+    pub wheel_diameter: Option<f64>,
+}
+
+impl Default for MotorModel {
+    fn default() -> Self {
+        Self {
+            gear_ratio: 1.0,
+            wheel_diameter: None,
+        }
+    }
+}
+
+impl MotorModel {
+    // `<prefix>.gear_ratio` (default 1) and `<prefix>.wheel_diameter`
+    pub fn load(config: &RobotConfig, prefix: &str) -> Self {
+        Self {
+            gear_ratio: config.get(&format!("{prefix}.gear_ratio")).unwrap_or(1.0),
+            wheel_diameter: config.get(&format!("{prefix}.wheel_diameter")),
+        }
+    }
+    // meters travelled per unit of motor position, None without a wheel
+    pub fn meters_per_unit(&self) -> Option<f64> {
+        let circumference = std::f64::consts::PI * self.wheel_diameter?;
+        Some(circumference * self.gear_ratio / POSITION_UNITS_PER_REV)
+    }
+}
+
+// How close a motor is to overheating (see Brain::read_motors)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ThermalLevel {
+    #[default]
+    Normal,
+    Warm,
+    // the motor is limiting its power
+    Hot,
+}
+
+impl ThermalLevel {
+    // the level at the given temperature when previously at self
+    pub fn update(self, temperature: f64) -> Self {
+        let level = |t: f64| {
+            if t >= HOT_TEMPERATURE {
+                Self::Hot
+            } else if t >= WARM_TEMPERATURE {
+                Self::Warm
+            } else {
+                Self::Normal
+            }
+        };
+        let rising = level(temperature);
+        if rising >= self {
+            return rising;
+        }
+        level(temperature + THERMAL_HYSTERESIS).min(self)
+    }
+}
+
+// State of the coprocessor side loop for a Position or ProfiledPosition
+// target, run by the brain every loop when writing targets.
+#[derive(Debug, Clone, Copy)]
+pub struct PositionLoop {
+    target: Target,
+    start_position: f64,
+    start: Instant,
+}
+
+impl PositionLoop {
+    pub fn target(&self) -> Target {
+        self.target
+    }
+    // velocity (rpm) to command for the target, None (and the loop
+    // cleared) if the target isn't a position
+    pub fn velocity(state: &mut Option<Self>, target: Target, position: f64) -> Option<f64> {
+        let (goal, profile) = match target {
+            Target::Position(goal) => (goal as f64, None),
+            Target::ProfiledPosition {
+                target,
+                max_vel,
+                max_accel,
+            } => (target as f64, Some((max_vel, max_accel))),
+            _ => {
+                *state = None;
+                return None;
+            }
+        };
+        // a new target starts its profile from where the motor is
+        let state = match state {
+            Some(s) if s.target == target => s,
+            _ => state.insert(Self {
+                target,
+                start_position: position,
+                start: Instant::now(),
+            }),
+        };
+        let (setpoint, velocity) = match profile {
+            None => (goal, 0.0),
+            Some((max_vel, max_accel)) => trapezoid(
+                state.start_position,
+                goal,
+                max_vel,
+                max_accel,
+                state.start.elapsed().as_secs_f64(),
+            ),
+        };
+        let feedforward = velocity * 60.0 / POSITION_UNITS_PER_REV;
+        Some(POSITION_KP * (setpoint - position) + feedforward)
+    }
+}
+
+// position and velocity t seconds along a trapezoidal profile from start to goal
+pub fn trapezoid(start: f64, goal: f64, max_vel: f64, max_accel: f64, t: f64) -> (f64, f64) {
+    let (max_vel, max_accel) = (max_vel.abs(), max_accel.abs());
+    if max_vel == 0.0 || max_accel == 0.0 {
+        return (goal, 0.0);
+    }
+    let dist = (goal - start).abs();
+    let dir = (goal - start).signum();
+    // the peak velocity is lower than the max for short moves
+    let peak = max_vel.min((dist * max_accel).sqrt());
+    // already there, also avoids dividing by a zero peak below
+    if dist == 0.0 || peak == 0.0 {
+        return (goal, 0.0);
+    }
+    let accel_time = peak / max_accel;
+    let cruise_time = (dist - peak * accel_time) / peak;
+    let total = 2.0 * accel_time + cruise_time;
+    // exactly the goal so callers can tell the profile has finished
+    if t >= total {
+        return (goal, 0.0);
+    }
+    let (pos, vel) = if t < accel_time {
+        (0.5 * max_accel * t * t, max_accel * t)
+    } else if t < accel_time + cruise_time {
+        (0.5 * peak * accel_time + peak * (t - accel_time), peak)
+    } else {
+        let left = total - t;
+        (dist - 0.5 * max_accel * left * left, max_accel * left)
+    };
+    (start + dir * pos, dir * vel)
 }
 
 impl Motor {
@@ -46,7 +329,12 @@ impl Motor {
         };
         reader.state.is_some()
     }
-    pub fn set_target(&mut self, mut target: Target) {
+    #[track_caller]
+    pub fn set_target(&mut self, target: Target) {
+        self.write_target(target, false);
+    }
+    #[track_caller]
+    fn write_target(&mut self, mut target: Target, exclusive: bool) {
         match target {
             Target::Voltage(ref mut v) => {
                 if v.abs() > 12000 {
@@ -79,7 +367,99 @@ impl Motor {
             return;
         };
 
+        if let Some(owner) = writer.owner.filter(|_| !exclusive) {
+            // only log the first rejection to avoid a message every loop
+            if !std::mem::replace(&mut writer.rejected_logged, true) {
+                log::warn!(
+                    "Write of {target:?} to motor {} from {} rejected as it is owned by {owner}.",
+                    self.port,
+                    Location::caller()
+                );
+            }
+            return;
+        }
+
         writer.target = target;
+        if arbitration_log_enabled() {
+            writer.writers.push((Location::caller(), target));
+        }
+    }
+    // the writes to the target since this was last called
+    pub fn take_writers(&self) -> Vec<(&'static Location<'static>, Target)> {
+        let Ok(ref mut writer) = self.inner.write() else {
+            log::error!(
+                "Motor on port {} has poisoned lock! Failed to read writers.",
+                self.port
+            );
+            return Vec::new();
+        };
+        std::mem::take(&mut writer.writers)
+    }
+    // sets a velocity target clamped to the maximum velocity of the
+    // motor's gearbox (green is assumed if the gearbox was never set)
+    #[track_caller]
+    pub fn set_velocity(&mut self, velocity: AngularVelocity) {
+        if let Some(target) = self.velocity_target(velocity) {
+            self.write_target(target, false);
+        }
+    }
+    fn velocity_target(&self, velocity: AngularVelocity) -> Option<Target> {
+        let max = AngularVelocity::max_for(self.gearbox().unwrap_or(Gearbox::Green)).as_rpm();
+        let mut rpm = velocity.as_rpm();
+        if !rpm.is_finite() {
+            log::warn!("An invalid velocity of {rpm}rpm was passed to set_velocity. Ignoring.");
+            return None;
+        } else if rpm.abs() > max {
+            log::warn!("A velocity of {rpm}rpm was passed to set_velocity on a motor with a max of {max}rpm. Clamping.");
+            rpm = rpm.clamp(-max, max);
+        }
+        Some(Target::RotationalVelocity(rpm.round() as i16))
+    }
+    // Claims the motor so that only the returned handle can write to it
+    // until it is dropped, writes from other handles are rejected. None
+    // is returned if the motor is already claimed.
+    pub fn claim_exclusive(&self, owner: &'static str) -> Option<ExclusiveMotor> {
+        let Ok(ref mut writer) = self.inner.write() else {
+            log::error!(
+                "Motor on port {} has poisoned lock! Failed to claim motor.",
+                self.port
+            );
+            return None;
+        };
+        if let Some(other) = writer.owner {
+            log::error!(
+                "{owner} failed to claim motor {} as it is owned by {other}.",
+                self.port
+            );
+            return None;
+        }
+        writer.owner = Some(owner);
+        writer.rejected_logged = false;
+        Some(ExclusiveMotor {
+            motor: self.clone(),
+        })
+    }
+    pub fn gearbox(&self) -> Option<Gearbox> {
+        let Ok(reader) = self.inner.read() else {
+            log::error!(
+                "Motor on port {} has poisoned lock! Failed to read gearbox.",
+                self.port
+            );
+            return None;
+        };
+        reader.gearbox
+    }
+    // this function is marked as unsafe as it should only be called
+    // from the brain struct as it doesn't change the actual gearbox
+    pub unsafe fn set_gearbox(&mut self, gearbox: Gearbox) {
+        let Ok(ref mut writer) = self.inner.write() else {
+            log::error!(
+                "Motor on port {} has poisoned lock! Failed to set gearbox for motor.",
+                self.port
+            );
+            return;
+        };
+        writer.gearbox = Some(gearbox);
     }
     // this function is marked as unsafe as it should only
     // be called from the brain struct with care
@@ -92,10 +472,164 @@ impl Motor {
             return;
         };
         writer.state = new_inner;
+        writer.update_stall();
+    }
+    // see StallDetection
+    pub fn is_stalled(&self) -> bool {
+        let Ok(reader) = self.inner.read() else {
+            log::error!(
+                "Motor on port {} has poisoned lock! Failed to read stall.",
+                self.port
+            );
+            return false;
+        };
+        reader.stalled
+    }
+    // whether a stall started since this was last called, so a mechanism
+    // polling it (e.g. an intake reversing) only reacts once per stall
+    pub fn take_stall_started(&self) -> bool {
+        let Ok(ref mut writer) = self.inner.write() else {
+            log::error!(
+                "Motor on port {} has poisoned lock! Failed to read stall.",
+                self.port
+            );
+            return false;
+        };
+        std::mem::take(&mut writer.stall_started)
+    }
+    pub fn set_stall_detection(&mut self, detection: StallDetection) {
+        let Ok(ref mut writer) = self.inner.write() else {
+            log::error!(
+                "Motor on port {} has poisoned lock! Failed to set stall detection.",
+                self.port
+            );
+            return;
+        };
+        writer.stall_detection = detection;
+    }
+    // last state reported by the brain (None if disconnected)
+    pub fn state(&self) -> Option<MotorState> {
+        let Ok(reader) = self.inner.read() else {
+            log::error!(
+                "Motor on port {} has poisoned lock! Failed to read state.",
+                self.port
+            );
+            return None;
+        };
+        reader.state
+    }
+    // current draw in mA
+    pub fn current(&self) -> Option<i16> {
+        self.state().map(|s| s.current)
+    }
+    pub fn model(&self) -> MotorModel {
+        let Ok(reader) = self.inner.read() else {
+            log::error!(
+                "Motor on port {} has poisoned lock! Failed to read model.",
+                self.port
+            );
+            return MotorModel::default();
+        };
+        reader.model
+    }
+    pub fn set_model(&mut self, model: MotorModel) {
+        let Ok(ref mut writer) = self.inner.write() else {
+            log::error!(
+                "Motor on port {} has poisoned lock! Failed to set model.",
+                self.port
+            );
+            return;
+        };
+        writer.model = model;
+    }
+    // position of the output in revolutions (see MotorModel)
+    pub fn output_revolutions(&self) -> Option<f64> {
+        let position = self.state()?.position as f64;
+        Some(position / POSITION_UNITS_PER_REV * self.model().gear_ratio)
+    }
+    // velocity of the output (see MotorModel)
+    pub fn output_velocity(&self) -> Option<AngularVelocity> {
+        let rpm = self.state()?.velocity as f64;
+        Some(AngularVelocity::from_rpm(rpm * self.model().gear_ratio))
+    }
+    // distance (m) travelled by the wheel on the output, None without a wheel
+    pub fn distance(&self) -> Option<f64> {
+        Some(self.state()?.position as f64 * self.model().meters_per_unit()?)
+    }
+    // surface speed (m/s) of the wheel on the output, None without a wheel
+    pub fn linear_velocity(&self) -> Option<f64> {
+        let diameter = self.model().wheel_diameter?;
+        Some(self.output_velocity()?.as_rad_per_sec() * 0.5 * diameter)
+    }
+    // temperature in C
+    pub fn temperature(&self) -> Option<f64> {
+        self.state().map(|s| s.temperature as f64)
     }
     pub fn port(&self) -> u8 {
         self.port
     }
+    pub fn current_limit(&self) -> Option<i16> {
+        let Ok(reader) = self.inner.read() else {
+            log::error!(
+                "Motor on port {} has poisoned lock! Failed to read current limit.",
+                self.port
+            );
+            return None;
+        };
+        reader.current_limit
+    }
+    // limit in mA enforced by scaling down the output, None for no limit
+    pub fn set_current_limit(&mut self, limit: Option<i16>) {
+        let Ok(ref mut writer) = self.inner.write() else {
+            log::error!(
+                "Motor on port {} has poisoned lock! Failed to set current limit.",
+                self.port
+            );
+            return;
+        };
+        writer.current_limit = limit.map(i16::abs);
+    }
+    pub fn brake_mode(&self) -> BrakeMode {
+        let Ok(reader) = self.inner.read() else {
+            log::error!(
+                "Motor on port {} has poisoned lock! Failed to read brake mode.",
+                self.port
+            );
+            return BrakeMode::default();
+        };
+        reader.brake_mode
+    }
+    pub fn set_brake_mode(&mut self, mode: BrakeMode) {
+        let Ok(ref mut writer) = self.inner.write() else {
+            log::error!(
+                "Motor on port {} has poisoned lock! Failed to set brake mode.",
+                self.port
+            );
+            return;
+        };
+        writer.brake_mode = mode;
+    }
+    // the target actually written to the brain once the brake mode is
+    // applied, hold_loop is the motor's position loop (see PositionLoop)
+    pub fn effective_target(&self, hold_loop: &Option<PositionLoop>, position: i32) -> Target {
+        let target = self.target();
+        let stopped = matches!(
+            target,
+            Target::None | Target::Voltage(0) | Target::PercentVoltage(0.0)
+        );
+        if !stopped {
+            return target;
+        }
+        match self.brake_mode() {
+            BrakeMode::Coast => target,
+            BrakeMode::Brake => Target::RotationalVelocity(0),
+            // keep holding where the motor first stopped
+            BrakeMode::Hold => match hold_loop.map(|l| l.target()) {
+                Some(hold @ Target::Position(_)) => hold,
+                _ => Target::Position(position),
+            },
+        }
+    }
     pub fn target(&self) -> Target {
         let Ok(reader) = self.inner.read() else {
             log::error!(
@@ -107,3 +641,129 @@ impl Motor {
         reader.target
     }
 }
+
+impl MotorInner {
+    // the commanded power as a fraction of the max, None for position
+    // targets as the power is only known to the brain's position loop
+    fn commanded_power(&self) -> Option<f64> {
+        match self.target {
+            Target::None => Some(0.0),
+            Target::Voltage(v) => Some(v as f64 / MAX_MILLIVOLT as f64),
+            Target::PercentVoltage(v) => Some(v),
+            Target::RotationalVelocity(v) => {
+                let max = AngularVelocity::max_for(self.gearbox.unwrap_or(Gearbox::Green));
+                Some(v as f64 / max.as_rpm())
+            }
+            Target::Position(_) | Target::ProfiledPosition { .. } => None,
+        }
+    }
+    fn update_stall(&mut self) {
+        let detection = self.stall_detection;
+        let stalling = match (&self.state, self.commanded_power()) {
+            (Some(state), Some(power)) => {
+                power.abs() >= detection.min_power
+                    && (state.velocity as f64).abs() <= detection.max_velocity
+                    && state.current.abs() >= detection.min_current
+            }
+            _ => false,
+        };
+        if !stalling {
+            self.stalling_since = None;
+            self.stalled = false;
+            return;
+        }
+        let since = *self.stalling_since.get_or_insert_with(Instant::now);
+        if !self.stalled && since.elapsed() >= detection.window {
+            self.stalled = true;
+            self.stall_started = true;
+        }
+    }
+}
+
+// The only handle allowed to write to a motor while it exists, for
+// mechanisms where another writer (e.g. an auton segment) grabbing the
+// motor would be unsafe. See Motor::claim_exclusive.
+#[derive(Debug)]
+pub struct ExclusiveMotor {
+    motor: Motor,
+}
+
+impl ExclusiveMotor {
+    #[track_caller]
+    pub fn set_target(&mut self, target: Target) {
+        self.motor.write_target(target, true);
+    }
+    #[track_caller]
+    pub fn set_velocity(&mut self, velocity: AngularVelocity) {
+        if let Some(target) = self.motor.velocity_target(velocity) {
+            self.motor.write_target(target, true);
+        }
+    }
+    // the motor for reading, writes through it are rejected
+    pub fn motor(&self) -> &Motor {
+        &self.motor
+    }
+}
+
+impl Drop for ExclusiveMotor {
+    fn drop(&mut self) {
+        let Ok(ref mut writer) = self.motor.inner.write() else {
+            log::error!(
+                "Motor on port {} has poisoned lock! Failed to release motor.",
+                self.motor.port
+            );
+            return;
+        };
+        writer.owner = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::close;
+
+    #[test]
+    fn zero_length_profile_holds_at_the_goal() {
+        // used to divide by a zero peak velocity giving NaN
+        assert_eq!(trapezoid(5.0, 5.0, 10.0, 10.0, 0.0), (5.0, 0.0));
+        assert_eq!(trapezoid(5.0, 5.0, 10.0, 10.0, 1.0), (5.0, 0.0));
+    }
+
+    #[test]
+    fn long_profile_cruises_then_stops_at_the_goal() {
+        // accelerates for 1s, cruises until 10s and stops at 11s
+        let (pos, vel) = trapezoid(0.0, -100.0, 10.0, 10.0, 5.0);
+        assert!(close(pos, -45.0));
+        assert!(close(vel, -10.0));
+        assert_eq!(trapezoid(0.0, -100.0, 10.0, 10.0, 11.0), (-100.0, 0.0));
+    }
+
+    #[test]
+    fn position_loop_drives_to_the_target() {
+        let mut state = None;
+        let rpm = PositionLoop::velocity(&mut state, Target::Position(100), 40.0);
+        assert_eq!(rpm, Some(POSITION_KP * 60.0));
+        let rpm = PositionLoop::velocity(&mut state, Target::Position(100), 100.0);
+        assert_eq!(rpm, Some(0.0));
+
+        // other targets clear the loop
+        let rpm = PositionLoop::velocity(&mut state, Target::Voltage(0), 100.0);
+        assert_eq!(rpm, None);
+        assert!(state.is_none());
+    }
+
+    #[test]
+    fn profiled_position_starts_from_the_motor() {
+        let mut state = None;
+        let target = Target::ProfiledPosition {
+            target: 100,
+            max_vel: 90.0,
+            max_accel: 180.0,
+        };
+        // a step to the target would command POSITION_KP * 60.0
+        let rpm = PositionLoop::velocity(&mut state, target, 40.0).unwrap();
+        assert!(rpm.abs() < 0.1, "commanded {rpm}rpm");
+        assert_eq!(state.map(|s| s.start_position), Some(40.0));
+    }
+}
@@ -1,141 +1,514 @@
-use crate::bmi088::Bmi088;
+use crate::config::RobotConfig;
+use crate::ekf::PoseEkf;
+use crate::estimate::{Differentiator, FitKind};
+use crate::imu::HeadingSensor;
+use crate::motor::Motor;
 use amt22::Amt22;
 use rppal::spi::Spi;
 use std::{
     collections::VecDeque,
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
     time::{Duration, Instant},
 };
 
-const LEFT_DIST: f64 = 0.045;
-const RIGHT_DIST: f64 = 0.045;
-//const BACK_DIST: f64 = 0.1;
+// fastest a tracking wheel can really move, faster readings are bad reads
+const MAX_WHEEL_SPEED: f64 = 5.0;
+// shortest time between readings used when checking the wheel speed
+const MIN_WHEEL_DT: f64 = 0.002;
+// variance of the IMU heading (rad^2) relative to the heading from the
+// tracking wheels, small as the IMU heading is far more reliable
+const IMU_HEADING_VARIANCE: f64 = 1e-6;
+// variance per meter of the distance measured by the drive encoders,
+// more then the tracking wheels as the drive wheels slip
+const DRIVE_DISTANCE_VARIANCE: f64 = 1e-3;
+// normalised innovation squared of the IMU heading above which the
+// tracking wheels are taken to have jumped (5 standard deviations)
+const HEADING_GATE: f64 = 25.0;
+// consecutive failed reads before a tracking wheel is considered dead
+const MAX_FAILED_READS: u32 = 50;
+// distance one side can move while the other side's encoder doesn't
+// change at all before that encoder is considered flat-lined
+const FLATLINE_DISTANCE: f64 = 0.15;
 
-const NUM_LIN: usize = 30;
-const INV_NUM_LIN: f64 = 1.0 / NUM_LIN as f64;
+// horizontal acceleration (m/s^2) only reached when hitting something
+const COLLISION_ACCEL: f64 = 20.0;
+// difference between the acceleration measured by the IMU and seen by
+// the tracking (m/s^2) above which the robot is being pushed or slipping
+const SLIP_ACCEL: f64 = 4.0;
+// position variance (m^2) added per second of slipping
+const SLIP_VARIANCE: f64 = 0.01;
+
+// number of past poses kept for pose_at, about a second at 200Hz
+const POSE_HISTORY_LEN: usize = 200;
+// samples used for the velocity estimates by default
+pub const VELOCITY_WINDOW: usize = 30;
+
+// Where odometry gets the distance travelled by each side of the
+// robot from, either the Pi-attached tracking wheels or sensors the
+// brain reports in the status packet.
+pub trait TrackingSource: Send {
+    // reads the sensors, dt is the time since the last call
+    fn calc_distances(&mut self, dt: f64);
+    // distance travelled by the left and right side in meters
+    fn distances(&self) -> [f64; 2];
+    // distance travelled sideways (positive to the left), None if not measured
+    fn back_distance(&self) -> Option<f64> {
+        None
+    }
+    // false once the source can no longer be trusted
+    fn healthy(&self) -> bool {
+        true
+    }
+}
+
+// Geometry of the tracking wheels, loaded from the config file
+// so each robot can set its own (see OdomConfig::load)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OdomConfig {
+    // distance of the left and right wheels from the tracking centre
+    pub left_offset: f64,
+    pub right_offset: f64,
+    // distance of the back wheel behind the tracking centre
+    pub back_offset: f64,
+    pub wheel_diameter: f64,
+    // bits of resolution of the AMT22 encoders (12 or 14)
+    pub encoder_bits: u8,
+    // wheels (left, right, back) that count backwards when driving forwards
+    pub reversed: [bool; 3],
+}
+
+impl Default for OdomConfig {
+    fn default() -> Self {
+        Self {
+            left_offset: 0.045,
+            right_offset: 0.045,
+            back_offset: 0.1,
+            // a circumference of 0.219440246853m
+            wheel_diameter: 0.0698500000000,
+            encoder_bits: 12,
+            reversed: [false; 3],
+        }
+    }
+}
+
+impl OdomConfig {
+    pub fn load(config: &RobotConfig) -> Self {
+        let default = Self::default();
+        let get = |key: &str, default: f64| config.get(&format!("odom.{key}")).unwrap_or(default);
+        let encoder_bits = match get("encoder_bits", default.encoder_bits as f64) as u8 {
+            bits @ (12 | 14) => bits,
+            bits => {
+                log::warn!("Unsupported encoder resolution of {bits} bits, using 12.");
+                12
+            }
+        };
+        Self {
+            left_offset: get("left_offset", default.left_offset),
+            right_offset: get("right_offset", default.right_offset),
+            back_offset: get("back_offset", default.back_offset),
+            wheel_diameter: get("wheel_diameter", default.wheel_diameter),
+            encoder_bits,
+            reversed: ["left_reversed", "right_reversed", "back_reversed"]
+                .map(|key| get(key, 0.0) != 0.0),
+        }
+    }
+    fn track_width(&self) -> f64 {
+        self.left_offset + self.right_offset
+    }
+}
 
 pub struct TrackingWheels {
-    //back: Amt22<Spi>,
+    // perpendicular wheel measuring sideways motion, None if not fitted
+    back: Option<Amt22<Spi>>,
     left: Amt22<Spi>,
     right: Amt22<Spi>,
-    // zero offset in rotations
-    zeros: [f64; 2],
-    distances: [f64; 2],
-    last_raw: [f64; 2],
+    // zero offset in rotations (left, right, back)
+    zeros: [f64; 3],
+    distances: [f64; 3],
+    last_raw: [f64; 3],
+    // distance travelled per full rotation in meters (negative when reversed)
+    circumferences: [f64; 3],
+    // fraction of a rotation per encoder tick
+    tick_scale: f64,
+    // reads failed in a row for each wheel
+    failed_reads: [u32; 3],
+    // distance the other side moved since the left/right raw value last changed
+    flatlined_for: [f64; 2],
+    failed: bool,
 }
 
 impl TrackingWheels {
-    // distance travelled per full rotation in meters
-    const TRACKING_CIRCUMFERENCE: f64 = 0.219440246853;
-    const ENCODER_TICK_SCALE: f64 = 1.0 / 4096.0;
-    pub fn new() -> Self {
+    pub fn new(config: &OdomConfig) -> Self {
         let mut delay = rppal::hal::Delay::new();
+        let resolution = if config.encoder_bits == 14 {
+            amt22::Resolution::Res14Bit
+        } else {
+            amt22::Resolution::Res12Bit
+        };
+        let tick_scale = 1.0 / 2f64.powi(config.encoder_bits as i32);
         let get_enc = |ss| {
             let spi =
                 rppal::spi::Spi::new(rppal::spi::Bus::Spi0, ss, 100_000, rppal::spi::Mode::Mode0)
                     .unwrap();
-            amt22::Amt22::new(spi, amt22::Resolution::Res12Bit)
+            amt22::Amt22::new(spi, resolution)
         };
         let mut left = get_enc(rppal::spi::SlaveSelect::Ss1);
         let mut right = get_enc(rppal::spi::SlaveSelect::Ss0);
-        //let mut back = get_enc(rppal::spi::SlaveSelect::Ss2);
+        let mut back = get_enc(rppal::spi::SlaveSelect::Ss2);
         left.reset(Some(&mut delay)).unwrap();
         right.reset(Some(&mut delay)).unwrap();
-        //back.reset(Some(&mut delay)).unwrap();
 
+        // the back wheel is optional, without it sideways motion is ignored
+        let back_zero = back
+            .reset(Some(&mut delay))
+            .ok()
+            .and_then(|_| Self::enc_to_rotations(&mut back, tick_scale));
+        if back_zero.is_none() {
+            log::warn!("Back tracking wheel not found, sideways motion will not be tracked.");
+        }
+
+        let [left_zero, right_zero] =
+            [&mut left, &mut right].map(|v| Self::enc_to_rotations(v, tick_scale).unwrap());
         Self {
             // get zero offset measured in rotations
-            zeros: [&mut left, &mut right].map(|v| Self::enc_to_rotations(v).unwrap()),
-            distances: [0.0; 2],
+            zeros: [left_zero, right_zero, back_zero.unwrap_or(0.0)],
+            distances: [0.0; 3],
             left,
             right,
-            //back,
-            last_raw: [0.0; 2],
+            back: back_zero.map(|_| back),
+            last_raw: [left_zero, right_zero, back_zero.unwrap_or(0.0)],
+            circumferences: config.reversed.map(|rev| {
+                let circumference = std::f64::consts::PI * config.wheel_diameter;
+                if rev {
+                    -circumference
+                } else {
+                    circumference
+                }
+            }),
+            tick_scale,
+            failed_reads: [0; 3],
+            flatlined_for: [0.0; 2],
+            failed: false,
         }
     }
     // returns signed rotations done
-    fn enc_to_rotations(enc: &mut Amt22<Spi>) -> Option<f64> {
+    fn enc_to_rotations(enc: &mut Amt22<Spi>, tick_scale: f64) -> Option<f64> {
         // TODO: remove unwrap
         let (turns, subturns) = enc.read_absolute_position_raw().ok()?;
-        Some(turns as f64 + Self::ENCODER_TICK_SCALE * subturns as f64)
+        Some(turns as f64 + tick_scale * subturns as f64)
     }
-    pub fn distances(&self) -> [f64; 2] {
-        let [l, r] = self.distances;
+}
+
+impl TrackingSource for TrackingWheels {
+    fn distances(&self) -> [f64; 2] {
+        let [l, r, _] = self.distances;
         // account for tracking wheel orientation
         [l, r]
     }
-    // returns distance in meters
-    pub fn calc_distances(&mut self) {
+    fn back_distance(&self) -> Option<f64> {
+        self.back.as_ref().map(|_| self.distances[2])
+    }
+    fn healthy(&self) -> bool {
+        !self.failed
+    }
+    fn calc_distances(&mut self, _dt: f64) {
         // get uncorrected rotation count
-        let rotations = [&mut self.left, &mut self.right].map(Self::enc_to_rotations);
+        let tick_scale = self.tick_scale;
+        let [left, right] =
+            [&mut self.left, &mut self.right].map(|enc| Self::enc_to_rotations(enc, tick_scale));
+        let back = self
+            .back
+            .as_mut()
+            .and_then(|enc| Self::enc_to_rotations(enc, tick_scale));
 
         // fallback to last value if read fails
-        if let Some(r) = rotations[0] {
-            self.last_raw[0] = r;
+        let mut changed = [false; 3];
+        for (i, rotations) in [left, right, back].into_iter().enumerate() {
+            match rotations {
+                Some(r) => {
+                    self.failed_reads[i] = 0;
+                    changed[i] = r != self.last_raw[i];
+                    self.last_raw[i] = r;
+                }
+                None => self.failed_reads[i] += 1,
+            }
+        }
+        let last_distances = self.distances;
+
+        // correct for zero offset and multiply by tracking wheel
+        // circumference to figure out distance travelled, jumps from bad
+        // reads are caught by the pose filter (see Odometry::calc_position)
+        for i in 0..3 {
+            self.distances[i] = (self.last_raw[i] - self.zeros[i]) * self.circumferences[i];
+        }
+
+        // a wheel that stops changing while the other side drives has
+        // come loose or lost its signal but still reads fine
+        for i in 0..2 {
+            if changed[i] {
+                self.flatlined_for[i] = 0.0;
+            } else {
+                self.flatlined_for[i] += (self.distances[1 - i] - last_distances[1 - i]).abs();
+            }
         }
-        if let Some(r) = rotations[1] {
-            self.last_raw[1] = r;
+        if !self.failed {
+            for (i, name) in ["left", "right"].into_iter().enumerate() {
+                if self.failed_reads[i] > MAX_FAILED_READS {
+                    log::error!("The {name} tracking wheel stopped responding.");
+                    self.failed = true;
+                } else if self.flatlined_for[i] > FLATLINE_DISTANCE {
+                    log::error!("The {name} tracking wheel flat-lined.");
+                    self.failed = true;
+                }
+            }
         }
+        // without the back wheel only sideways motion is lost so it is dropped on its own
+        if self.back.is_some() && self.failed_reads[2] > MAX_FAILED_READS {
+            log::warn!(
+                "Back tracking wheel stopped responding, sideways motion will not be tracked."
+            );
+            self.back = None;
+        }
+    }
+}
 
-        // correct for zero offset
-        let rotations = [
-            self.last_raw[0] - self.zeros[0],
-            self.last_raw[1] - self.zeros[1],
-        ];
+// Tracking from encoders the brain reports in the status packet, for
+// robots without the SPI wiring harness. V5 rotation sensors aren't in
+// the status packet so the drive motors' own encoders are used, these
+// slip with the drive wheels so tracking wheels are preferred.
+pub struct MotorTracking {
+    // the motors of each side and if they are reversed
+    sides: [Vec<(Motor, bool)>; 2],
+    // meters travelled per unit of motor position
+    scale: f64,
+    // average position of each side when created
+    zeros: [Option<f64>; 2],
+    distances: [f64; 2],
+}
 
-        // multiply by tracking wheel circumference to figure out distance travelled
-        let new_distances = rotations.map(|v| v * Self::TRACKING_CIRCUMFERENCE);
-        if (self.distances[0] - new_distances[0]).abs() < 0.1 {
-            self.distances[0] = new_distances[0];
+impl MotorTracking {
+    pub fn new(sides: [Vec<(Motor, bool)>; 2], scale: f64) -> Self {
+        let mut tracking = Self {
+            sides,
+            scale,
+            zeros: [None; 2],
+            distances: [0.0; 2],
+        };
+        tracking.zeros = [0, 1].map(|i| tracking.side_position(i));
+        tracking
+    }
+    // average position of the connected motors of a side
+    fn side_position(&self, side: usize) -> Option<f64> {
+        let positions: Vec<f64> = self.sides[side]
+            .iter()
+            .filter_map(|(motor, rev)| {
+                let pos = motor.state()?.position as f64;
+                Some(if *rev { -pos } else { pos })
+            })
+            .collect();
+        if positions.is_empty() {
+            return None;
+        }
+        Some(positions.iter().sum::<f64>() / positions.len() as f64)
+    }
+}
+
+impl TrackingSource for MotorTracking {
+    fn calc_distances(&mut self, dt: f64) {
+        let max_change = MAX_WHEEL_SPEED * dt.max(MIN_WHEEL_DT);
+        for i in 0..2 {
+            // keep the last distance while no motors of the side are connected
+            let Some(pos) = self.side_position(i) else {
+                continue;
+            };
+            let zero = *self.zeros[i].get_or_insert(pos);
+            let new_distance = (pos - zero) * self.scale;
+            // a motor (re)connecting changes the average, absorb the jump
+            if (new_distance - self.distances[i]).abs() > max_change {
+                self.zeros[i] = Some(zero + (new_distance - self.distances[i]) / self.scale);
+                continue;
+            }
+            self.distances[i] = new_distance;
         }
-        if (self.distances[1] - new_distances[1]).abs() < 0.1 {
-            self.distances[1] = new_distances[1];
+    }
+    fn distances(&self) -> [f64; 2] {
+        self.distances
+    }
+}
+
+// which velocity estimate is used when checking if the robot has settled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VelocitySource {
+    // average of the left and right tracking wheel velocities
+    #[default]
+    Wheels,
+    // chassis velocity from the change in the fused pose which is
+    // unaffected by pivoting and less affected by a single bad wheel
+    Pose,
+}
+
+// Small corrections found during practice that are stored
+// in the config file and applied while calculating the pose.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OdomTrim {
+    // heading gained (rad) per meter driven
+    pub heading_per_meter: f64,
+    // heading gained (rad) per second regardless of movement
+    pub heading_per_second: f64,
+    // multiplier for the distance measured by the tracking wheels
+    pub distance_scale: f64,
+}
+
+impl Default for OdomTrim {
+    fn default() -> Self {
+        Self {
+            heading_per_meter: 0.0,
+            heading_per_second: 0.0,
+            distance_scale: 1.0,
+        }
+    }
+}
+
+impl OdomTrim {
+    pub fn load(config: &RobotConfig) -> Self {
+        let default = Self::default();
+        Self {
+            heading_per_meter: config
+                .get("odom.trim.heading_per_meter")
+                .unwrap_or(default.heading_per_meter),
+            heading_per_second: config
+                .get("odom.trim.heading_per_second")
+                .unwrap_or(default.heading_per_second),
+            distance_scale: config
+                .get("odom.trim.distance_scale")
+                .unwrap_or(default.distance_scale),
+        }
+    }
+    pub fn save(&self, config: &mut RobotConfig) {
+        config.set("odom.trim.heading_per_meter", self.heading_per_meter);
+        config.set("odom.trim.heading_per_second", self.heading_per_second);
+        config.set("odom.trim.distance_scale", self.distance_scale);
+        if let Err(e) = config.save() {
+            log::error!("Failed to save odometry trim: {e}");
         }
     }
 }
 
 pub struct Odometry {
-    imu: Bmi088,
-    tracking_wheels: TrackingWheels,
+    imu: Box<dyn HeadingSensor>,
+    tracking_wheels: Box<dyn TrackingSource>,
+    // used instead of the tracking wheels if they fail
+    fallback: Option<Box<dyn TrackingSource>>,
+    degraded: bool,
+    geometry: OdomConfig,
     position: [f64; 2],
     velocity: [f64; 2],
     last_update: Instant,
     last_pos: [f64; 2],
     first_update: bool,
-    last_10_times: VecDeque<Instant>,
-    last_10_vals: VecDeque<[f64; 2]>,
+    // left and right wheel distances
+    side_rates: Differentiator<2>,
+    // x, y and heading
+    pose_rates: Differentiator<3>,
+    settle_source: VelocitySource,
+    trim: OdomTrim,
+    last_calc: Instant,
+    ekf: PoseEkf,
+    last_collision: Option<Instant>,
+    imu_lost: bool,
+    // (time, position, heading) of recent updates, oldest first
+    history: VecDeque<(Instant, [f64; 2], f64)>,
 }
 
 impl Odometry {
-    pub fn new(imu_bias: f64, imu_addr: u16) -> Self {
-        let mut imu = Bmi088::new(imu_bias, imu_addr);
+    pub fn new(imu: Box<dyn HeadingSensor>, geometry: OdomConfig) -> Self {
+        let tracking_wheels = Box::new(TrackingWheels::new(&geometry));
+        Self::with_tracking(imu, geometry, tracking_wheels)
+    }
+    pub fn with_tracking(
+        mut imu: Box<dyn HeadingSensor>,
+        geometry: OdomConfig,
+        tracking_wheels: Box<dyn TrackingSource>,
+    ) -> Self {
         imu.reset();
         Self {
             imu,
-            tracking_wheels: TrackingWheels::new(),
+            tracking_wheels,
+            fallback: None,
+            degraded: false,
+            geometry,
             position: [0.0; 2],
             velocity: [0.0; 2],
             last_update: Instant::now(),
             last_pos: [0.0; 2],
             first_update: true,
-            last_10_times: VecDeque::from([Instant::now(); NUM_LIN]),
-            last_10_vals: VecDeque::from([[0.0; 2]; NUM_LIN]),
+            side_rates: Differentiator::new(VELOCITY_WINDOW, FitKind::Linear),
+            pose_rates: Differentiator::new(VELOCITY_WINDOW, FitKind::Linear),
+            settle_source: VelocitySource::default(),
+            trim: OdomTrim::default(),
+            last_calc: Instant::now(),
+            ekf: PoseEkf::new([0.0; 2], 0.0),
+            last_collision: None,
+            imu_lost: false,
+            history: VecDeque::with_capacity(POSE_HISTORY_LEN),
+        }
+    }
+    // tracking to switch to (with the IMU still providing the heading)
+    // if the tracking wheels fail, e.g. MotorTracking. Until then the
+    // distance it measures is fused with the tracking wheels'
+    pub fn fallback(mut self, source: Box<dyn TrackingSource>) -> Self {
+        self.fallback = Some(source);
+        self
+    }
+    // true once odometry has switched to the fallback tracking
+    pub fn degraded(&self) -> bool {
+        self.degraded
+    }
+    fn check_tracking(&mut self) {
+        if self.degraded || self.tracking_wheels.healthy() {
+            return;
+        }
+        self.degraded = true;
+        match self.fallback.take() {
+            // the fallback absorbs the jump to its own distances on its
+            // first update so the pose carries on from where it was
+            Some(fallback) => {
+                log::error!("Tracking failed, falling back to drive encoders and the IMU.");
+                self.tracking_wheels = fallback;
+            }
+            None => log::error!("Tracking failed with no fallback, the position will be wrong."),
         }
     }
     pub fn calc_position(&mut self) {
+        self.check_tracking();
+
         // gets the distances travelled by each tracking wheel in meters
         let [last_left, last_right] = self.tracking_wheels.distances();
+        let last_back = self.tracking_wheels.back_distance();
+        let last_heading = self.imu.heading();
 
         // update both the heading and wheel distances
+        let now = Instant::now();
+        let dt = now.duration_since(self.last_calc).as_secs_f64();
+        self.last_calc = now;
         self.imu.calc_heading();
-        self.tracking_wheels.calc_distances();
+        self.tracking_wheels.calc_distances(dt);
+        // distance driven by the drive encoders while they aren't
+        // standing in for the tracking wheels
+        let drive_distance = self.fallback.as_mut().map(|drive| {
+            let [last_left, last_right] = drive.distances();
+            drive.calc_distances(dt);
+            let [left, right] = drive.distances();
+            0.5 * (left - last_left + right - last_right)
+        });
 
-        // get the new heading and wheel positions
-        let heading = self.imu.heading();
+        // get the new wheel positions
         let [left, right] = self.tracking_wheels.distances();
-        self.last_10_times.push_back(Instant::now());
-        self.last_10_times.pop_front();
-        self.last_10_vals.push_back([left, right]);
-        self.last_10_vals.pop_front();
+        self.side_rates.push(now, [left, right]);
 
         // get the differences
         let [diff_left, diff_right] = [left - last_left, right - last_right];
@@ -154,55 +527,403 @@ impl Odometry {
             self.first_update = false;
         }
 
-        let (sin, cos) = heading.sin_cos();
+        // weighted so the rotation seen by each wheel cancels out
+        // when the wheels aren't equally far from the centre
+        let geometry = self.geometry;
+        let diff_x_local = (geometry.right_offset * diff_left + geometry.left_offset * diff_right)
+            / geometry.track_width()
+            * self.trim.distance_scale;
 
-        let diff_x_local = 0.5 * (diff_left + diff_right);
+        // turn seen by the tracking wheels, the prediction of the
+        // heading which the IMU heading then corrects
+        let wheel_diff_heading = (diff_right - diff_left) / geometry.track_width();
+        // without the IMU the heading comes from the tracking wheels alone,
+        // the IMU carries on from it when it reconnects
+        let imu_healthy = self.imu.is_healthy();
+        if imu_healthy == self.imu_lost {
+            self.imu_lost = !imu_healthy;
+            if self.imu_lost {
+                log::error!("IMU lost, using the tracking wheels for the heading.");
+            } else {
+                log::info!("IMU back, using it for the heading again.");
+            }
+        }
+        if !imu_healthy {
+            self.imu.set_heading(last_heading + wheel_diff_heading);
+        }
 
-        self.position[0] += cos * diff_x_local;
-        self.position[1] += sin * diff_x_local;
+        // remove the known heading drift before using the heading
+        let drift =
+            self.trim.heading_per_meter * diff_x_local.abs() + self.trim.heading_per_second * dt;
+        self.imu.set_heading(self.imu.heading() - drift);
+        let heading = self.imu.heading();
+        let diff_heading = heading - last_heading;
+
+        // the back wheel also moves sideways when the robot turns as
+        // it is behind the centre so that part is removed
+        let diff_y_local = match (last_back, self.tracking_wheels.back_distance()) {
+            (Some(last), Some(back)) => {
+                (back - last + geometry.back_offset * diff_heading) * self.trim.distance_scale
+            }
+            _ => 0.0,
+        };
+
+        // the robot is assumed to move along an arc (constant curvature)
+        // between updates, so the local movement is the chord of that
+        // arc which points along the average heading
+        let chord = if diff_heading.abs() < 1e-9 {
+            1.0
+        } else {
+            2.0 * (0.5 * diff_heading).sin() / diff_heading
+        };
+        let [dx, dy] = [diff_x_local * chord, diff_y_local * chord];
+
+        let before = self.ekf;
+        self.ekf.predict([dx, dy], wheel_diff_heading);
+        if imu_healthy {
+            // a tracking wheel jumping (a bad read or the encoder
+            // resetting) turns the prediction far from the IMU heading,
+            // the movement is dropped and the position trusted less as
+            // the robot could have moved up to MAX_WHEEL_SPEED
+            if self.ekf.heading_nis(heading, IMU_HEADING_VARIANCE) > HEADING_GATE {
+                log::warn!(
+                    "Tracking wheels turned {wheel_diff_heading}rad against the IMU's {diff_heading}rad, ignoring the movement."
+                );
+                self.ekf = before;
+                self.ekf.predict([0.0, 0.0], 0.0);
+                self.ekf.inflate_position((MAX_WHEEL_SPEED * dt).powi(2));
+            }
+            self.ekf.update_heading(heading, IMU_HEADING_VARIANCE);
+        }
+        // the drive encoders measure where the robot got to from the
+        // pose before this update, that pose's uncertainty is added to
+        // the measurement's as the two aren't independent
+        if let Some(distance) = drive_distance {
+            let heading = before.heading() + 0.5 * (self.ekf.heading() - before.heading());
+            let [x, y] = before.position();
+            let (sin, cos) = heading.sin_cos();
+            let distance = distance * chord * self.trim.distance_scale;
+            let prior = before.covariance();
+            let variance = prior[0][0].max(prior[1][1]) + DRIVE_DISTANCE_VARIANCE * distance.abs();
+            self.ekf
+                .update_position([x + cos * distance, y + sin * distance], variance);
+        }
+        self.check_acceleration(dt);
+        self.position = self.ekf.position();
+        self.pose_rates
+            .push(now, [self.position[0], self.position[1], self.heading()]);
+        if self.history.len() == POSE_HISTORY_LEN {
+            self.history.pop_front();
+        }
+        self.history.push_back((now, self.position, self.heading()));
+    }
+    // compares the accelerometer to the acceleration seen by the
+    // tracking, trusting the tracking less while they disagree
+    fn check_acceleration(&mut self, dt: f64) {
+        let Some([ax, ay]) = self.imu.acceleration() else {
+            return;
+        };
+        if ax.hypot(ay) > COLLISION_ACCEL {
+            if self
+                .last_collision
+                .is_none_or(|t| t.elapsed() > Duration::from_millis(500))
+            {
+                log::warn!("Collision detected ({ax}, {ay})m/s^2.");
+            }
+            self.last_collision = Some(Instant::now());
+        }
+        if (ax - self.chassis_acceleration()).abs() > SLIP_ACCEL {
+            self.ekf.inflate_position(SLIP_VARIANCE * dt);
+        }
     }
     pub fn position(&self) -> [f64; 2] {
         self.position
     }
+    // false while the heading comes from the tracking wheels alone
+    pub fn imu_healthy(&self) -> bool {
+        !self.imu_lost
+    }
+    // robot frame (forwards, left) acceleration from the IMU in m/s^2
+    pub fn acceleration(&self) -> Option<[f64; 2]> {
+        self.imu.acceleration()
+    }
+    // rad/s about the robot's left axis, None without a gyro measuring it
+    pub fn pitch_rate(&self) -> Option<f64> {
+        self.imu.pitch_rate()
+    }
+    // when a spike in acceleration was last seen
+    pub fn last_collision(&self) -> Option<Instant> {
+        self.last_collision
+    }
     pub fn heading(&self) -> f64 {
-        self.imu.heading()
+        self.ekf.heading()
+    }
+    // the pose at an earlier time, interpolated between updates, so
+    // late measurements (e.g. from the status packet) can be fused
+    // against where the robot was when they were taken. None if the
+    // time is older than the history, the latest pose if it is newer
+    pub fn pose_at(&self, time: Instant) -> Option<([f64; 2], f64)> {
+        let after = self.history.partition_point(|(t, _, _)| *t < time);
+        if after == self.history.len() {
+            return Some((self.position, self.heading()));
+        }
+        let (t1, p1, h1) = self.history[after];
+        if after == 0 {
+            return (t1 == time).then_some((p1, h1));
+        }
+        let (t0, p0, h0) = self.history[after - 1];
+        let span = t1.duration_since(t0).as_secs_f64();
+        let f = if span > 0.0 {
+            time.duration_since(t0).as_secs_f64() / span
+        } else {
+            1.0
+        };
+        let lerp = |a: f64, b: f64| a + (b - a) * f;
+        Some(([lerp(p0[0], p1[0]), lerp(p0[1], p1[1])], lerp(h0, h1)))
+    }
+    // covariance of [x, y, heading] from the pose filter
+    pub fn pose_covariance(&self) -> [[f64; 3]; 3] {
+        self.ekf.covariance()
     }
     // note may need smoothing/filtering
     pub fn angular_velocity(&self) -> f64 {
         self.imu.angular_velocity()
     }
+    pub fn gyro_bias(&self) -> f64 {
+        self.imu.bias()
+    }
+    pub fn set_gyro_bias(&mut self, bias: f64) {
+        log::info!("Gyro bias changed from {} to {bias}", self.imu.bias());
+        self.imu.set_bias(bias);
+    }
+    pub fn gyro_temperature(&self) -> Option<f64> {
+        self.imu.temperature()
+    }
+    pub fn gyro_temperature_slope(&self) -> f64 {
+        self.imu.temperature_slope()
+    }
+    pub fn set_gyro_temperature_slope(&mut self, slope: f64) {
+        log::info!(
+            "Gyro temperature slope changed from {} to {slope}",
+            self.imu.temperature_slope()
+        );
+        self.imu.set_temperature_slope(slope);
+    }
+    pub fn gyro_scale(&self) -> f64 {
+        self.imu.scale()
+    }
+    pub fn set_gyro_scale(&mut self, scale: f64) {
+        log::info!("Gyro scale changed from {} to {scale}", self.imu.scale());
+        self.imu.set_scale(scale);
+    }
+    // sets how the velocities and accelerations are estimated
+    pub fn set_rate_estimation(&mut self, side: Differentiator<2>, pose: Differentiator<3>) {
+        self.side_rates = side;
+        self.pose_rates = pose;
+    }
     pub fn side_velocities(&self) -> [f64; 2] {
-        let start = self.last_10_times[0];
-        let times: Vec<_> = self
-            .last_10_times
-            .iter()
-            .map(|v| v.duration_since(start).as_secs_f64())
-            .collect();
-        let avg_time = times.iter().sum::<f64>() * INV_NUM_LIN;
-        let denom = times.iter().map(|v| (v - avg_time).powi(2)).sum::<f64>();
-
-        let avg_x = self.last_10_vals.iter().map(|v| v[0]).sum::<f64>() * INV_NUM_LIN;
-        let avg_y = self.last_10_vals.iter().map(|v| v[1]).sum::<f64>() * INV_NUM_LIN;
-        let x = self
-            .last_10_vals
-            .iter()
-            .zip(times.iter())
-            .map(|(v, t)| (v[0] - avg_x) * (t - avg_time))
-            .sum::<f64>()
-            / denom;
-        let y = self
-            .last_10_vals
-            .iter()
-            .zip(times.iter())
-            .map(|(v, t)| (v[1] - avg_y) * (t - avg_time))
-            .sum::<f64>()
-            / denom;
-        if !x.is_nan() && !y.is_nan() {
-            return [x, y];
+        self.side_rates.rate().unwrap_or(self.velocity)
+    }
+    // world frame velocity of the robot from the change in position
+    pub fn pose_velocity(&self) -> [f64; 2] {
+        self.pose_rates.rate().map_or([0.0; 2], |[x, y, _]| [x, y])
+    }
+    // signed velocity of the chassis in the direction it is facing
+    pub fn chassis_velocity(&self) -> f64 {
+        let [vx, vy] = self.pose_velocity();
+        let (sin, cos) = self.heading().sin_cos();
+        vx * cos + vy * sin
+    }
+    // rate of turn from the fused heading, smoother than angular_velocity
+    pub fn chassis_angular_velocity(&self) -> f64 {
+        self.pose_rates.rate().map_or(0.0, |[_, _, h]| h)
+    }
+    // signed acceleration of the chassis in the direction it is facing
+    pub fn chassis_acceleration(&self) -> f64 {
+        let Some([ax, ay, _]) = self.pose_rates.acceleration() else {
+            return 0.0;
+        };
+        let (sin, cos) = self.heading().sin_cos();
+        ax * cos + ay * sin
+    }
+    pub fn chassis_angular_acceleration(&self) -> f64 {
+        self.pose_rates.acceleration().map_or(0.0, |[_, _, h]| h)
+    }
+    pub fn set_settle_source(&mut self, source: VelocitySource) {
+        self.settle_source = source;
+    }
+    // linear velocity used by segments to check if the robot has settled
+    pub fn settle_velocity(&self) -> f64 {
+        match self.settle_source {
+            VelocitySource::Wheels => {
+                let [l, r] = self.side_velocities();
+                0.5 * (l + r)
+            }
+            VelocitySource::Pose => self.chassis_velocity(),
         }
-        self.velocity
     }
     pub fn reset(&mut self) {
-        self.imu.reset()
+        self.imu.reset();
+        self.pose_rates.clear();
+        self.ekf.set_heading(self.imu.heading());
+        self.history.clear();
+        self.last_calc = Instant::now();
+    }
+    pub fn trim(&self) -> OdomTrim {
+        self.trim
+    }
+    pub fn set_trim(&mut self, trim: OdomTrim) {
+        log::info!("Odometry trim set to {trim:?}");
+        self.trim = trim;
+    }
+    pub fn set_heading(&mut self, heading: f64) {
+        self.pose_rates.shift([0.0, 0.0, heading - self.heading()]);
+        self.imu.set_heading(heading);
+        self.ekf.set_heading(heading);
+        self.history.clear();
+    }
+    pub fn set_position(&mut self, position: [f64; 2]) {
+        // shift the position history as well so the jump doesn't
+        // show up in the pose velocity
+        let diff = [
+            position[0] - self.position[0],
+            position[1] - self.position[1],
+        ];
+        self.pose_rates.shift([diff[0], diff[1], 0.0]);
+        // the history is cleared rather than shifted as measurements from
+        // before the jump are no longer valid in the new frame
+        self.history.clear();
+        self.position = position;
+        self.ekf.set_position(position);
+    }
+    // re-zeros the pose mid run without touching the IMU state unlike reset
+    pub fn set_pose(&mut self, position: [f64; 2], heading: f64) {
+        self.set_position(position);
+        self.set_heading(heading);
+    }
+    // shifts the pose by a correction e.g. found by squaring against a wall
+    pub fn offset_pose(&mut self, position: [f64; 2], heading: f64) {
+        let pos = self.position;
+        self.set_position([pos[0] + position[0], pos[1] + position[1]]);
+        self.set_heading(self.heading() + heading);
+    }
+}
+
+// The latest pose published by the odometry thread, readable without
+// locking. Writes are guarded by a sequence number (odd while writing)
+// so a read never sees half of an update.
+#[derive(Debug, Default)]
+pub struct PoseSnapshot {
+    seq: AtomicU64,
+    pose: [AtomicU64; 3],
+}
+
+impl PoseSnapshot {
+    fn publish(&self, position: [f64; 2], heading: f64) {
+        self.seq.fetch_add(1, Ordering::AcqRel);
+        for (v, new) in self.pose.iter().zip([position[0], position[1], heading]) {
+            v.store(new.to_bits(), Ordering::Release);
+        }
+        self.seq.fetch_add(1, Ordering::AcqRel);
+    }
+    // the position and heading from the same update
+    pub fn pose(&self) -> ([f64; 2], f64) {
+        loop {
+            let seq = self.seq.load(Ordering::Acquire);
+            if seq % 2 == 1 {
+                std::hint::spin_loop();
+                continue;
+            }
+            let [x, y, heading] = self
+                .pose
+                .each_ref()
+                .map(|v| f64::from_bits(v.load(Ordering::Acquire)));
+            if self.seq.load(Ordering::Acquire) == seq {
+                return ([x, y], heading);
+            }
+        }
+    }
+}
+
+// Runs Odometry::calc_position on its own thread at a fixed rate so
+// the pose doesn't depend on the jitter of the main loop. The pose can
+// be read from the snapshot at any time, lock is for everything else
+// (e.g. following a path) and blocks updates while held.
+pub struct OdomThread {
+    odom: Arc<Mutex<Odometry>>,
+    snapshot: Arc<PoseSnapshot>,
+}
+
+impl OdomThread {
+    pub fn spawn(odom: Odometry, period: Duration) -> Self {
+        let snapshot = Arc::new(PoseSnapshot::default());
+        snapshot.publish(odom.position(), odom.heading());
+        let odom = Arc::new(Mutex::new(odom));
+        let thread_odom = odom.clone();
+        let thread_snapshot = snapshot.clone();
+        std::thread::Builder::new()
+            .name("odometry".to_owned())
+            .spawn(move || {
+                let mut next = Instant::now();
+                loop {
+                    {
+                        let mut odom = thread_odom.lock().unwrap();
+                        odom.calc_position();
+                        thread_snapshot.publish(odom.position(), odom.heading());
+                    }
+                    next += period;
+                    let now = Instant::now();
+                    if next < now {
+                        // skip the missed updates rather then running them back to back
+                        log::warn!("Odometry update ran {:?} late.", now - next);
+                        next = now;
+                    }
+                    std::thread::sleep(next - now);
+                }
+            })
+            .expect("the odometry thread is required to drive the robot");
+        Self { odom, snapshot }
+    }
+    // the snapshot is updated when the guard is dropped so changes
+    // (e.g. set_pose) are seen straight away
+    pub fn lock(&self) -> OdomGuard<'_> {
+        OdomGuard {
+            odom: self.odom.lock().unwrap(),
+            snapshot: &self.snapshot,
+        }
+    }
+    pub fn snapshot(&self) -> Arc<PoseSnapshot> {
+        self.snapshot.clone()
+    }
+    pub fn position(&self) -> [f64; 2] {
+        self.snapshot.pose().0
+    }
+    pub fn heading(&self) -> f64 {
+        self.snapshot.pose().1
+    }
+}
+
+pub struct OdomGuard<'a> {
+    odom: MutexGuard<'a, Odometry>,
+    snapshot: &'a PoseSnapshot,
+}
+
+impl Deref for OdomGuard<'_> {
+    type Target = Odometry;
+    fn deref(&self) -> &Odometry {
+        &self.odom
+    }
+}
+
+impl DerefMut for OdomGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Odometry {
+        &mut self.odom
+    }
+}
+
+impl Drop for OdomGuard<'_> {
+    fn drop(&mut self) {
+        self.snapshot
+            .publish(self.odom.position(), self.odom.heading());
     }
 }
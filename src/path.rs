@@ -1,12 +1,19 @@
 use communication::path::Action;
 
+use crate::brain::Brain;
+use crate::cascade::CascadedPosition;
+use crate::lift::Lift;
+use crate::motor::Motor;
 use crate::odom::Odometry;
-use crate::pid::Pid;
+use crate::pid::{Pid, PidGains};
 use crate::triports::*;
 use crate::vec::Vec2;
 
+use std::cell::RefCell;
 use std::collections::VecDeque;
 use std::f64::consts::{PI, TAU};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
 /// Each auton "path" is a Route which is created
 /// from a vector of Actions (communication::path::Action)
@@ -18,9 +25,80 @@ use std::f64::consts::{PI, TAU};
 #[derive(Debug, Clone, Copy)]
 pub enum MinSegment {
     MoveTo([f64; 2]),
+    // drive to a point backwards (facing away from it)
+    MoveToReverse([f64; 2]),
     MoveRel(f64),
     TurnTo(f64),
     TurnRel(f64),
+    // turn to face a point on the field
+    TurnToPoint([f64; 2]),
+    // drive through a point without stopping, leaving it at the given
+    // velocity (fraction of max velocity) which the next segment
+    // starts its profile from
+    MoveThrough([f64; 2], f64),
+    // turn to a heading in a given direction (e.g. the long way
+    // around when the short way is blocked)
+    TurnToDirection(f64, TurnDirection),
+}
+
+// Parses a segment in the form it is printed with {:?} (the format
+// route files are written in e.g. by teach mode), `MoveTo([1.0, 0.5])`
+impl std::str::FromStr for MinSegment {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (name, args) = s
+            .strip_suffix(')')
+            .and_then(|s| s.split_once('('))
+            .ok_or_else(|| format!("expected Segment(args) but found {s}"))?;
+        let args: Vec<&str> = args
+            .split([',', '[', ']'])
+            .map(str::trim)
+            .filter(|a| !a.is_empty())
+            .collect();
+        let num = |i: usize| -> Result<f64, String> {
+            let arg = args
+                .get(i)
+                .ok_or_else(|| format!("missing argument for {name}"))?;
+            arg.parse()
+                .map_err(|_| format!("invalid number {arg} for {name}"))
+        };
+        let expect = |n: usize| {
+            if args.len() == n {
+                Ok(())
+            } else {
+                Err(format!("{name} takes {n} arguments but got {}", args.len()))
+            }
+        };
+        match name {
+            "MoveTo" => expect(2).and(Ok(Self::MoveTo([num(0)?, num(1)?]))),
+            "MoveToReverse" => expect(2).and(Ok(Self::MoveToReverse([num(0)?, num(1)?]))),
+            "MoveRel" => expect(1).and(Ok(Self::MoveRel(num(0)?))),
+            "TurnTo" => expect(1).and(Ok(Self::TurnTo(num(0)?))),
+            "TurnRel" => expect(1).and(Ok(Self::TurnRel(num(0)?))),
+            "TurnToPoint" => expect(2).and(Ok(Self::TurnToPoint([num(0)?, num(1)?]))),
+            "MoveThrough" => expect(3).and(Ok(Self::MoveThrough([num(0)?, num(1)?], num(2)?))),
+            "TurnToDirection" => {
+                expect(2)?;
+                let direction = match args[1] {
+                    "Shortest" => TurnDirection::Shortest,
+                    "Clockwise" => TurnDirection::Clockwise,
+                    "CounterClockwise" => TurnDirection::CounterClockwise,
+                    other => return Err(format!("invalid turn direction {other}")),
+                };
+                Ok(Self::TurnToDirection(num(0)?, direction))
+            }
+            _ => Err(format!("unknown segment {name}")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TurnDirection {
+    #[default]
+    Shortest,
+    Clockwise,
+    CounterClockwise,
 }
 
 #[derive(Debug)]
@@ -52,6 +130,36 @@ pub struct Route {
 const ACCEL_TIME: f64 = 1.5;
 const ACCEL: f64 = 1.0 / ACCEL_TIME;
 
+// Limits shared by the segments of a path (see Path::with_constraints).
+// Velocities are fractions of the max velocity like segment outputs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PathConstraints {
+    pub max_vel: f64,
+    // acceleration in terms of distance travelled (see velocity_profile)
+    pub max_accel: f64,
+    // max output of turns
+    pub max_ang_vel: f64,
+    // limits of the heading profile turns follow in rad/s and rad/s^2,
+    // a non-positive value makes turns target the final heading directly
+    pub turn_vel: f64,
+    pub turn_accel: f64,
+    // distance between the centres of the left and right drive wheels in meters
+    pub track_width: f64,
+}
+
+impl Default for PathConstraints {
+    fn default() -> Self {
+        Self {
+            max_vel: 1.0,
+            max_accel: ACCEL,
+            max_ang_vel: 1.0,
+            turn_vel: 4.0,
+            turn_accel: 10.0,
+            track_width: 0.29,
+        }
+    }
+}
+
 // velocity profile for straight paths based the scalar projection
 // of pos vec2 onto end vec2 relative to start. It is a modified
 // trapezoid profile (where it does not start quite at zero to avoid
@@ -61,36 +169,158 @@ const ACCEL: f64 = 1.0 / ACCEL_TIME;
 // rather then having linear sides (v = at) we have a square root
 // v = sqrt(2da)
 // velocity and acceleration are scaled such that v = 1 is the max
-// velocity
-fn velocity_profile(start: Vec2, end: Vec2, path_dist: f64, pos: Vec2) -> f64 {
+// velocity and are limited by the path constraints
+fn velocity_profile(
+    start: Vec2,
+    end: Vec2,
+    path_dist: f64,
+    pos: Vec2,
+    constraints: &PathConstraints,
+) -> f64 {
+    chained_velocity_profile(start, end, path_dist, pos, 0.0, 0.0, constraints)
+}
+
+// lowest velocity of the first half of a velocity profile
+const MIN_PROFILE_VELOCITY: f64 = 0.1;
+
+// velocity_profile where the robot enters the path at the entry
+// velocity and leaves it at the exit velocity rather then from and
+// to a stop, so consecutive segments can be chained together
+fn chained_velocity_profile(
+    start: Vec2,
+    end: Vec2,
+    path_dist: f64,
+    pos: Vec2,
+    entry: f64,
+    exit: f64,
+    constraints: &PathConstraints,
+) -> f64 {
     // first we find the projected distance along the path
     let proj_norm = (end - start) / path_dist;
     let path = pos - start;
     let dist = path.dot(proj_norm);
 
-    // we then get the distance from the closest end
-    let halfway = 0.5 * path_dist;
-    let from_hw = (halfway - dist).abs();
     // if the dist is negative or longer then the path
     // clamp it to the ends
-    let from_closest_end = (halfway - from_hw).max(0.0);
+    let from_start = dist.max(0.0);
+    let from_end = (path_dist - dist).max(0.0);
 
-    // we then convert that to a velocity and cap it at the max velocity
-    let mut velocity = (2.0 * from_closest_end * ACCEL).sqrt().min(1.0);
-    if dist < halfway {
+    // we then convert that to a velocity (accelerating from the entry
+    // velocity and decelerating to the exit velocity) and cap it at the
+    // max velocity
+    let accel = (entry.powi(2) + 2.0 * from_start * constraints.max_accel).sqrt();
+    let decel = (exit.powi(2) + 2.0 * from_end * constraints.max_accel).sqrt();
+    let mut velocity = accel.min(decel).min(constraints.max_vel);
+    if dist < 0.5 * path_dist {
         // we don't allow for zero velocity near the start of the path
         // as that would stall the robot instead we opt for 10% of max speed
-        velocity = velocity.max(0.1);
+        velocity = velocity.max(MIN_PROFILE_VELOCITY);
     }
     velocity
 }
 
+// Trapezoidal profile of heading with respect to time so turns
+// track a moving setpoint rather then the pid seeing the whole turn
+// as error at once (which overshoots large turns at high gains).
+// If the turn is too short to reach the max velocity the profile
+// becomes a triangle.
+#[derive(Debug, Clone, Copy)]
+struct AngularProfile {
+    start_heading: f64,
+    dist: f64,
+    vel: f64,
+    accel: f64,
+    start: Instant,
+}
+
+impl AngularProfile {
+    fn new(start_heading: f64, target_heading: f64, constraints: &PathConstraints) -> Option<Self> {
+        if constraints.turn_vel <= 0.0 || constraints.turn_accel <= 0.0 {
+            return None;
+        }
+        Some(Self {
+            start_heading,
+            dist: target_heading - start_heading,
+            vel: constraints.turn_vel,
+            accel: constraints.turn_accel,
+            start: Instant::now(),
+        })
+    }
+    fn setpoint(&self) -> f64 {
+        let t = self.start.elapsed().as_secs_f64();
+        let dist = self.dist.abs();
+        // time spent accelerating and the velocity reached
+        let accel_time = (self.vel / self.accel).min((dist / self.accel).sqrt());
+        let peak = self.accel * accel_time;
+        let accel_dist = 0.5 * self.accel * accel_time.powi(2);
+        let cruise_time = if peak > 0.0 {
+            (dist - 2.0 * accel_dist).max(0.0) / peak
+        } else {
+            0.0
+        };
+        let total = 2.0 * accel_time + cruise_time;
+
+        let travelled = if t >= total {
+            dist
+        } else if t < accel_time {
+            0.5 * self.accel * t.powi(2)
+        } else if t < accel_time + cruise_time {
+            accel_dist + peak * (t - accel_time)
+        } else {
+            dist - 0.5 * self.accel * (total - t).powi(2)
+        };
+        self.start_heading + self.dist.signum() * travelled
+    }
+}
+
+// Robot state that segments can read each time a path is
+// followed. Motors and triports can be read through the brain,
+// anything else (e.g. a motor handle) can be captured by the
+// segment itself.
+pub struct RobotCtx<'a> {
+    pub odom: &'a mut Odometry,
+    pub brain: &'a Brain,
+    pub auton_start: Instant,
+    // set by the path being followed if it has its own constraints
+    pub constraints: PathConstraints,
+}
+
+impl RobotCtx<'_> {
+    pub fn auton_elapsed(&self) -> Duration {
+        self.auton_start.elapsed()
+    }
+    pub fn auton_program(&self) -> u8 {
+        self.brain.auton_program()
+    }
+}
+
 #[derive(Debug)]
 pub struct Path {
     // this is a stack so the last element in
     // the vector is the first that will be run
     pub segments: VecDeque<Box<dyn PathSegment>>,
     pub current_segment: Option<Box<dyn PathSegment>>,
+    paused: bool,
+    // restart the current segment on the next follow (after a resume)
+    restart: bool,
+    // exit velocity of the last segment to be passed to the next
+    exit_velocity: f64,
+    // the constraints of the enclosing path (or robot) are used if None
+    constraints: Option<PathConstraints>,
+    // when the current segment started and the segments finished so far
+    segment_start: Instant,
+    history: Vec<SegmentRecord>,
+    // in step mode each segment waits for step before it starts
+    step_mode: bool,
+    step_ready: bool,
+    step_logged: bool,
+}
+
+// a finished segment of a path, used for the match report
+#[derive(Debug, Clone)]
+pub struct SegmentRecord {
+    pub segment: String,
+    pub duration: Duration,
 }
 
 impl Path {
@@ -98,14 +328,47 @@ impl Path {
         Self {
             segments: reversed_segments.into_iter().rev().collect(),
             current_segment: None,
+            paused: false,
+            restart: false,
+            exit_velocity: 0.0,
+            constraints: None,
+            segment_start: Instant::now(),
+            history: Vec::new(),
+            step_mode: false,
+            step_ready: false,
+            step_logged: false,
         }
     }
+    pub fn with_constraints(mut self, constraints: PathConstraints) -> Self {
+        self.constraints = Some(constraints);
+        self
+    }
     pub fn extend(&mut self, v: Box<dyn PathSegment>) {
         self.segments.push_front(v);
     }
     pub fn extend_front(&mut self, v: Box<dyn PathSegment>) {
         self.segments.push_back(v);
     }
+    // Loads a route file with one MinSegment per line in its {:?}
+    // form. Blank lines and lines starting with # are skipped.
+    pub fn load(path: &str) -> std::io::Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        let mut segments: Vec<Box<dyn PathSegment>> = Vec::new();
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let seg: MinSegment = line.parse().map_err(|e| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("line {}: {e}", i + 1),
+                )
+            })?;
+            segments.push(Box::new(seg));
+        }
+        Ok(Self::new(segments))
+    }
     pub fn new_from_actions(actions: &[Action]) -> Self {
         let mut pos = [0.0, 0.0];
         let mut heading = 0.0;
@@ -162,29 +425,73 @@ impl From<Box<dyn PathSegment>> for Path {
         Self {
             segments: vec![seg].into(),
             current_segment: None,
+            paused: false,
+            restart: false,
+            exit_velocity: 0.0,
+            constraints: None,
+            segment_start: Instant::now(),
+            history: Vec::new(),
+            step_mode: false,
+            step_ready: false,
+            step_logged: false,
         }
     }
 }
 
 impl Path {
-    fn transform_segments(&mut self, odom: &Odometry, angle_pid: &mut Pid) {
+    fn transform_segments(&mut self, ctx: &mut RobotCtx, angle_pid: &mut Pid) {
         if self.current_segment.is_some() {
             return;
         }
 
         while let Some(mut new_seg) = self.segments.pop_back() {
             if new_seg.finished_transform() {
+                if self.step_mode && !self.step_ready {
+                    if !std::mem::replace(&mut self.step_logged, true) {
+                        log::info!("step mode, waiting to start segment: {new_seg:?}");
+                    }
+                    self.segments.push_back(new_seg);
+                    return;
+                }
+                self.step_ready = false;
+                self.step_logged = false;
                 log::info!("started new segment: {new_seg:?}");
-                new_seg.start(odom, angle_pid);
+                new_seg.set_entry_velocity(std::mem::take(&mut self.exit_velocity));
+                new_seg.start(ctx, angle_pid);
+                self.segment_start = Instant::now();
                 self.current_segment = Some(new_seg);
                 return;
             }
-            self.segments.extend(new_seg.transform(odom));
+            self.segments.extend(new_seg.transform(ctx));
         }
     }
-    pub fn follow(&mut self, odom: &Odometry, angle_pid: &mut Pid) -> [f64; 2] {
+    pub fn follow(&mut self, ctx: &mut RobotCtx, angle_pid: &mut Pid) -> [f64; 2] {
+        let Some(constraints) = self.constraints else {
+            return self.follow_segments(ctx, angle_pid);
+        };
+        let outer = std::mem::replace(&mut ctx.constraints, constraints);
+        let pow = self.follow_segments(ctx, angle_pid);
+        ctx.constraints = outer;
+        pow
+    }
+    fn follow_segments(&mut self, ctx: &mut RobotCtx, angle_pid: &mut Pid) -> [f64; 2] {
+        if self.paused {
+            return [0.0; 2];
+        }
+
+        // restart the segment that was interrupted by a pause so
+        // its controllers don't see the time spent paused
+        if std::mem::take(&mut self.restart) {
+            if let Some(seg) = self.current_segment.as_mut() {
+                log::info!("restarting segment after resume: {seg:?}");
+                // the robot was stopped while paused
+                seg.set_entry_velocity(0.0);
+                seg.start(ctx, angle_pid);
+            }
+        }
+
         // get new segments if needed
-        self.transform_segments(odom, angle_pid);
+        self.transform_segments(ctx, angle_pid);
 
         // exit when no segments could be transformed
         let Some(seg) = self.current_segment.as_mut() else {
@@ -192,74 +499,353 @@ impl Path {
         };
 
         // end segment and start next
-        if let Some(new_segments) = seg.end_follow(odom) {
+        if let Some(new_segments) = seg.end_follow(ctx, angle_pid) {
             if new_segments.is_empty() {
                 log::info!("segment_ended: {seg:?} and added new segments: {new_segments:?}");
             } else {
                 log::info!("segment_ended: {seg:?}");
             }
+            self.exit_velocity = seg.exit_velocity();
+            self.history.push(SegmentRecord {
+                segment: format!("{seg:?}"),
+                duration: self.segment_start.elapsed(),
+            });
             self.segments.extend(new_segments);
             self.current_segment = None;
-            return self.follow(odom, angle_pid);
+            return self.follow_segments(ctx, angle_pid);
         }
 
-        seg.follow(odom, angle_pid)
+        seg.follow(ctx, angle_pid)
     }
-    fn abrupt_end(&mut self, odom: &Odometry) {
+    fn abrupt_end(&mut self, ctx: &mut RobotCtx, angle_pid: &mut Pid) {
         if let Some(seg) = self.current_segment.as_mut() {
-            seg.abrupt_end(odom);
+            seg.abrupt_end(ctx, angle_pid);
         }
     }
     pub fn ended(&self) -> bool {
         self.current_segment.is_none() && self.segments.is_empty()
     }
+    // Stops the drivetrain output while keeping the current segment
+    // so that following can be continued with resume. Note that
+    // segments which power other motors (PowerMotors) are not stopped.
+    pub fn pause(&mut self) {
+        if !self.paused {
+            log::info!("path paused during segment: {:?}", self.current_segment);
+        }
+        self.paused = true;
+    }
+    // Continues a paused path. The current segment is started again
+    // so any timers or controllers it has are reset.
+    pub fn resume(&mut self) {
+        if self.paused {
+            log::info!("path resumed");
+            self.paused = false;
+            self.restart = true;
+        }
+    }
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+    // Debugging aid where the path stops (with zero output) before
+    // each segment until step is called, logging the segment to run.
+    pub fn set_step_mode(&mut self, step_mode: bool) {
+        self.step_mode = step_mode;
+        self.step_ready = false;
+    }
+    // lets the next segment start when in step mode
+    pub fn step(&mut self) {
+        if self.step_mode {
+            self.step_ready = true;
+        }
+    }
+    // the segments finished since this was last called
+    pub fn take_history(&mut self) -> Vec<SegmentRecord> {
+        std::mem::take(&mut self.history)
+    }
+    // Ends the current segment and drops the rest of the path.
+    pub fn abort(&mut self, ctx: &mut RobotCtx, angle_pid: &mut Pid) {
+        log::info!("path aborted during segment: {:?}", self.current_segment);
+        self.abrupt_end(ctx, angle_pid);
+        self.current_segment = None;
+        self.segments.clear();
+        self.paused = false;
+        self.restart = false;
+        self.exit_velocity = 0.0;
+    }
+    fn clone_path(&self) -> Path {
+        Self {
+            segments: self
+                .segments
+                .iter()
+                .map(|v| v.as_ref().boxed_clone())
+                .collect(),
+            current_segment: self
+                .current_segment
+                .as_ref()
+                .map(|v| v.as_ref().boxed_clone()),
+            paused: self.paused,
+            restart: self.restart,
+            exit_velocity: self.exit_velocity,
+            constraints: self.constraints,
+            segment_start: self.segment_start,
+            history: self.history.clone(),
+            step_mode: self.step_mode,
+            step_ready: self.step_ready,
+            step_logged: self.step_logged,
+        }
+    }
 }
 
 pub trait PathSegment: std::fmt::Debug {
-    fn transform<'a>(self: Box<Self>, odom: &Odometry) -> Vec<Box<dyn PathSegment + 'a>>;
+    fn transform<'a>(self: Box<Self>, ctx: &RobotCtx) -> Vec<Box<dyn PathSegment + 'a>>;
     fn finished_transform(&self) -> bool;
-    fn start(&mut self, odom: &Odometry, angle_pid: &mut Pid);
-    fn follow(&mut self, odom: &Odometry, angle_pid: &mut Pid) -> [f64; 2];
-    fn end_follow<'a>(&mut self, odom: &Odometry) -> Option<Vec<Box<dyn PathSegment + 'a>>>;
-    fn abrupt_end(&mut self, odom: &Odometry) {}
+    fn start(&mut self, ctx: &mut RobotCtx, angle_pid: &mut Pid);
+    fn follow(&mut self, ctx: &mut RobotCtx, angle_pid: &mut Pid) -> [f64; 2];
+    fn end_follow<'a>(
+        &mut self,
+        ctx: &mut RobotCtx,
+        angle_pid: &mut Pid,
+    ) -> Option<Vec<Box<dyn PathSegment + 'a>>>;
+    fn abrupt_end(&mut self, _ctx: &mut RobotCtx, _angle_pid: &mut Pid) {}
+    // overrides the settle conditions for segments that use them
+    fn set_settle(&mut self, _turn: Option<SettleCondition>, _linear: Option<SettleCondition>) {}
+    // overrides the angle pid gains for segments that use it
+    fn set_gains(&mut self, _turn: Option<PidGains>, _linear: Option<PidGains>) {}
+    // velocity the segment finishes at which the next segment's
+    // profile starts from (see MinSegment::MoveThrough)
+    fn exit_velocity(&self) -> f64 {
+        0.0
+    }
+    fn set_entry_velocity(&mut self, _velocity: f64) {}
+    // sets what drive segments do when they stall (see StallAction)
+    fn set_stall_action(&mut self, _action: StallAction) {}
     fn boxed_clone<'a>(&self) -> Box<dyn PathSegment + 'a> {
         panic!("This type is designed to not be clonable: {self:?}");
     }
 }
 
 impl PathSegment for Path {
-    fn transform<'a>(self: Box<Self>, _: &Odometry) -> Vec<Box<dyn PathSegment + 'a>> {
+    fn transform<'a>(self: Box<Self>, _: &RobotCtx) -> Vec<Box<dyn PathSegment + 'a>> {
         unreachable!("transform should never get called since finished_transform is true");
     }
     fn finished_transform(&self) -> bool {
         true
     }
-    fn start(&mut self, _: &Odometry, _: &mut Pid) {}
-    fn follow(&mut self, odom: &Odometry, angle_pid: &mut Pid) -> [f64; 2] {
-        Path::follow(self, odom, angle_pid)
+    fn start(&mut self, _: &mut RobotCtx, _: &mut Pid) {}
+    fn follow(&mut self, ctx: &mut RobotCtx, angle_pid: &mut Pid) -> [f64; 2] {
+        Path::follow(self, ctx, angle_pid)
     }
-    fn end_follow<'a>(&mut self, _: &Odometry) -> Option<Vec<Box<dyn PathSegment + 'a>>> {
+    fn end_follow<'a>(
+        &mut self,
+        _: &mut RobotCtx,
+        _: &mut Pid,
+    ) -> Option<Vec<Box<dyn PathSegment + 'a>>> {
         if self.ended() {
             Some(Vec::new())
         } else {
             None
         }
     }
-    fn abrupt_end(&mut self, odom: &Odometry) {
-        Path::abrupt_end(self, odom);
+    fn abrupt_end(&mut self, ctx: &mut RobotCtx, angle_pid: &mut Pid) {
+        Path::abrupt_end(self, ctx, angle_pid);
     }
     fn boxed_clone<'a>(&self) -> Box<dyn PathSegment + 'a> {
-        Box::new(Self {
-            segments: self
-                .segments
-                .iter()
-                .map(|v| v.as_ref().boxed_clone())
-                .collect(),
-            current_segment: self
-                .current_segment
-                .as_ref()
-                .map(|v| v.as_ref().boxed_clone()),
-        })
+        Box::new(self.clone_path())
+    }
+}
+
+// Conditions for a segment to be considered finished. The error and
+// velocity tolerances are in the units of the segment (radians and
+// radians per second for turns, meters and meters per second for moves)
+// and both have to be met for the whole duration.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SettleCondition {
+    pub error: f64,
+    pub velocity: f64,
+    pub duration: Duration,
+}
+
+impl SettleCondition {
+    pub const fn new(error: f64, velocity: f64) -> Self {
+        Self {
+            error,
+            velocity,
+            duration: Duration::ZERO,
+        }
+    }
+    pub const fn with_error(mut self, error: f64) -> Self {
+        self.error = error;
+        self
+    }
+    pub const fn with_velocity(mut self, velocity: f64) -> Self {
+        self.velocity = velocity;
+        self
+    }
+    pub const fn with_duration(mut self, duration: Duration) -> Self {
+        self.duration = duration;
+        self
+    }
+}
+
+// Tracks whether the error and velocity have stayed within a
+// SettleCondition for its duration, so passing through the tolerance
+// (e.g. on a zero crossing while overshooting) doesn't count as settled.
+#[derive(Debug, Clone, Copy)]
+pub struct Settled {
+    condition: SettleCondition,
+    // when the tolerances were first met
+    since: Option<Instant>,
+}
+
+impl Settled {
+    pub const fn new(condition: SettleCondition) -> Self {
+        Self {
+            condition,
+            since: None,
+        }
+    }
+    // called every poll, true once settled for the whole duration
+    pub fn update(&mut self, error: f64, velocity: f64) -> bool {
+        if error.abs() > self.condition.error || velocity.abs() > self.condition.velocity {
+            self.since = None;
+            return false;
+        }
+        self.since.get_or_insert_with(Instant::now).elapsed() >= self.condition.duration
+    }
+    pub fn reset(&mut self) {
+        self.since = None;
+    }
+    pub fn condition(&self) -> SettleCondition {
+        self.condition
+    }
+    pub fn set_condition(&mut self, condition: SettleCondition) {
+        self.condition = condition;
+        self.since = None;
+    }
+}
+
+// the tolerances have to hold this long so a pass through doesn't finish a segment
+const SETTLE_DURATION: Duration = Duration::from_millis(100);
+// within 2deg and turning slower then 1deg/s
+pub const TURN_SETTLE: SettleCondition =
+    SettleCondition::new(2.0 * PI / 180.0, PI / 180.0).with_duration(SETTLE_DURATION);
+// within 3cm and moving slower then 1cm/s
+pub const MOVE_SETTLE: SettleCondition =
+    SettleCondition::new(0.03, 0.01).with_duration(SETTLE_DURATION);
+
+// Gains swapped into the angle pid for the duration of a segment, the
+// previous gains are kept so they can be restored when it ends.
+#[derive(Debug, Clone, Copy, Default)]
+struct GainOverride {
+    gains: Option<PidGains>,
+    saved: Option<PidGains>,
+}
+
+impl GainOverride {
+    fn apply(&mut self, angle_pid: &mut Pid) {
+        let Some(gains) = self.gains else {
+            return;
+        };
+        // keep the original gains if the segment is restarted
+        self.saved.get_or_insert(angle_pid.gains());
+        angle_pid.set_gains(gains);
+    }
+    fn restore(&mut self, angle_pid: &mut Pid) {
+        if let Some(saved) = self.saved.take() {
+            angle_pid.set_gains(saved);
+        }
+    }
+}
+
+// a drive segment is stalled when it has been commanded at least
+// STALL_POWER but moved slower then STALL_VELOCITY (m/s) for STALL_TIME,
+// kept under the profile floor so a robot blocked from the start of a
+// move is caught
+const STALL_POWER: f64 = 0.5 * MIN_PROFILE_VELOCITY;
+const STALL_VELOCITY: f64 = 0.02;
+const STALL_TIME: Duration = Duration::from_millis(500);
+
+// what a drive segment does once it detects it is stalled
+#[derive(Debug, Clone, Copy)]
+pub enum StallAction {
+    // end the segment and continue with the rest of the path
+    End,
+    // drive the other way at pow for dur then retry the segment, up to
+    // retries times after which the segment ends like End
+    BackOff {
+        pow: f64,
+        dur: Duration,
+        retries: u32,
+    },
+    // end the segment after calling the function
+    Callback(fn(&mut RobotCtx)),
+}
+
+impl StallAction {
+    // the action for a retry of the segment, None when out of retries
+    fn retried(self) -> Option<Self> {
+        match self {
+            StallAction::BackOff { pow, dur, retries } if retries > 0 => {
+                Some(StallAction::BackOff {
+                    pow,
+                    dur,
+                    retries: retries - 1,
+                })
+            }
+            StallAction::BackOff { .. } => None,
+            action => Some(action),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct StallDetector {
+    action: StallAction,
+    since: Option<Instant>,
+}
+
+impl StallDetector {
+    fn new(action: StallAction) -> Self {
+        Self {
+            action,
+            since: None,
+        }
+    }
+    // returns true once the robot has been stalled for STALL_TIME
+    fn stalled(&mut self, commanded: f64, velocity: f64) -> bool {
+        if commanded.abs() < STALL_POWER || velocity.abs() > STALL_VELOCITY {
+            self.since = None;
+            return false;
+        }
+        self.since.get_or_insert_with(Instant::now).elapsed() >= STALL_TIME
+    }
+    // the segments to continue with given the segment to retry and
+    // the direction it was driving in
+    fn handle<'a>(
+        &self,
+        ctx: &mut RobotCtx,
+        retry: Box<dyn PathSegment>,
+        commanded: f64,
+    ) -> Vec<Box<dyn PathSegment + 'a>> {
+        match self.action {
+            StallAction::End => Vec::new(),
+            StallAction::BackOff { pow, dur, .. } => {
+                let Some(action) = self.action.retried() else {
+                    log::warn!("Out of stall retries, continuing with the next segment.");
+                    return Vec::new();
+                };
+                // the retry carries the retries left, note order is
+                // reversed because of stack
+                vec![
+                    Box::new(OnStall::new(retry, action)),
+                    Box::new(Ram::back_off(-pow.abs() * commanded.signum(), dur)),
+                ]
+            }
+            StallAction::Callback(f) => {
+                f(ctx);
+                Vec::new()
+            }
+        }
     }
 }
 
@@ -267,98 +853,204 @@ impl PathSegment for Path {
 struct TurnTo {
     start_heading: f64,
     target_heading: f64,
+    settle: Settled,
+    gains: GainOverride,
+    direction: TurnDirection,
+    profile: Option<AngularProfile>,
+    // the turn before a move to a point, skipped when chained onto a
+    // MoveThrough and already facing close enough to the point
+    before_move: bool,
+    // velocity carried in from a MoveThrough, passed on to the move
+    // when the turn is skipped
+    entry: f64,
+}
+
+impl TurnTo {
+    fn new(start_heading: f64, target_heading: f64) -> Self {
+        Self {
+            start_heading,
+            target_heading,
+            settle: Settled::new(TURN_SETTLE),
+            gains: GainOverride::default(),
+            direction: TurnDirection::Shortest,
+            profile: None,
+            before_move: false,
+            entry: 0.0,
+        }
+    }
+    fn before_move(mut self) -> Self {
+        self.before_move = true;
+        self
+    }
+    // a chained move doesn't stop to turn, the heading hold of MoveRel
+    // corrects the small difference
+    fn skip_for_chain(&self, heading: f64) -> bool {
+        self.before_move
+            && self.entry > 0.0
+            && (self.target_heading - heading).abs() < CHAIN_HEADING_TOLERANCE
+    }
 }
 
 impl PathSegment for TurnTo {
-    fn transform<'a>(self: Box<Self>, _: &Odometry) -> Vec<Box<dyn PathSegment + 'a>> {
+    fn transform<'a>(self: Box<Self>, _: &RobotCtx) -> Vec<Box<dyn PathSegment + 'a>> {
         unreachable!("transform should never get called since finished_transform is true")
     }
     fn finished_transform(&self) -> bool {
         true
     }
-    fn start(&mut self, odom: &Odometry, angle_pid: &mut Pid) {
-        self.target_heading = optimise_target_heading(odom.heading(), self.target_heading);
+    fn start(&mut self, ctx: &mut RobotCtx, angle_pid: &mut Pid) {
+        // a forced direction is resolved once in transform as doing it
+        // again (e.g. on resume) could send the robot around a full turn
+        if self.direction == TurnDirection::Shortest {
+            self.target_heading = optimise_target_heading(ctx.odom.heading(), self.target_heading);
+        }
+        self.settle.reset();
+        self.gains.apply(angle_pid);
+        // the profile starts from the current heading so resuming
+        // after an interruption doesn't jump the setpoint
+        self.profile =
+            AngularProfile::new(ctx.odom.heading(), self.target_heading, &ctx.constraints);
         angle_pid.set_target(self.target_heading);
         angle_pid.reset();
     }
-    fn follow(&mut self, odom: &Odometry, angle_pid: &mut Pid) -> [f64; 2] {
-        let pow = angle_pid.poll(odom.heading());
+    fn follow(&mut self, ctx: &mut RobotCtx, angle_pid: &mut Pid) -> [f64; 2] {
+        if let Some(profile) = &self.profile {
+            angle_pid.set_target(profile.setpoint());
+        }
+        let max = ctx.constraints.max_ang_vel;
+        let pow = angle_pid.poll(ctx.odom.heading()).clamp(-max, max);
         [-pow, pow]
     }
-    fn end_follow<'a>(&mut self, odom: &Odometry) -> Option<Vec<Box<dyn PathSegment + 'a>>> {
-        if (odom.heading() - self.target_heading).abs() < 2f64.to_radians()
-            && odom.angular_velocity().abs() < 1f64.to_radians()
-        {
+    fn set_settle(&mut self, turn: Option<SettleCondition>, _: Option<SettleCondition>) {
+        if let Some(settle) = turn {
+            self.settle.set_condition(settle);
+        }
+    }
+    // only non zero when the turn was skipped
+    fn exit_velocity(&self) -> f64 {
+        self.entry
+    }
+    fn set_entry_velocity(&mut self, velocity: f64) {
+        self.entry = velocity;
+    }
+    fn set_gains(&mut self, turn: Option<PidGains>, _: Option<PidGains>) {
+        if turn.is_some() {
+            self.gains.gains = turn;
+        }
+    }
+    fn end_follow<'a>(
+        &mut self,
+        ctx: &mut RobotCtx,
+        angle_pid: &mut Pid,
+    ) -> Option<Vec<Box<dyn PathSegment + 'a>>> {
+        if self.skip_for_chain(ctx.odom.heading()) {
+            log::info!("Skipped TurnTo({}) of chained move.", self.target_heading);
+            self.gains.restore(angle_pid);
+            return Some(vec![]);
+        }
+        if self.settle.update(
+            ctx.odom.heading() - self.target_heading,
+            ctx.odom.angular_velocity(),
+        ) {
             log::info!(
                 "Finished segment - TurnTo({}) with heading ({}).",
                 self.target_heading,
-                odom.heading()
+                ctx.odom.heading()
             );
+            // the robot stopped to turn
+            self.entry = 0.0;
+            self.gains.restore(angle_pid);
             return Some(vec![]);
         }
         None
     }
+    fn abrupt_end(&mut self, _: &mut RobotCtx, angle_pid: &mut Pid) {
+        self.gains.restore(angle_pid);
+    }
 }
 
 impl PathSegment for MinSegment {
-    fn transform<'a>(self: Box<Self>, odom: &Odometry) -> Vec<Box<dyn PathSegment + 'a>> {
-        let heading = odom.heading();
+    fn transform<'a>(self: Box<Self>, ctx: &RobotCtx) -> Vec<Box<dyn PathSegment + 'a>> {
+        let heading = ctx.odom.heading();
         match *self {
             // note that this allows a suboptimal turn but
             // such a turn is likely to be intentional
             // unlike with TurnTo
             MinSegment::TurnRel(rel) => {
-                vec![Box::new(TurnTo {
-                    start_heading: heading,
-                    target_heading: heading + rel,
-                })]
+                vec![Box::new(TurnTo::new(heading, heading + rel))]
             }
             // ensure TurnTo takes most optimal turn
             // (don't turn more then half a turn)
             MinSegment::TurnTo(target) => {
-                vec![Box::new(TurnTo {
-                    start_heading: heading,
-                    target_heading: optimise_target_heading(heading, target),
-                })]
+                vec![Box::new(TurnTo::new(
+                    heading,
+                    optimise_target_heading(heading, target),
+                ))]
+            }
+            MinSegment::TurnToDirection(target, direction) => {
+                let mut turn =
+                    TurnTo::new(heading, directed_target_heading(heading, target, direction));
+                turn.direction = direction;
+                vec![Box::new(turn)]
             }
-            MinSegment::MoveTo(pos) => {
-                let opos = odom.position();
+            // the heading is found from the position when the segment
+            // is reached rather then when the path is created
+            MinSegment::TurnToPoint(point) => {
+                let opos = ctx.odom.position();
+                let target = (point[1] - opos[1]).atan2(point[0] - opos[0]);
+                vec![Box::new(TurnTo::new(
+                    heading,
+                    optimise_target_heading(heading, target),
+                ))]
+            }
+            MinSegment::MoveTo(pos)
+            | MinSegment::MoveToReverse(pos)
+            | MinSegment::MoveThrough(pos, _) => {
+                let reverse = matches!(*self, MinSegment::MoveToReverse(_));
+                let opos = ctx.odom.position();
                 let diff = [pos[0] - opos[0], pos[1] - opos[1]];
-                let target_heading = diff[1].atan2(diff[0]);
+                let mut target_heading = diff[1].atan2(diff[0]);
+                if reverse {
+                    // face away from the point
+                    target_heading += PI;
+                }
+                let target_heading = optimise_target_heading(heading, target_heading);
                 let len = (diff[0].powi(2) + diff[1].powi(2)).sqrt();
+                let mut move_rel = MoveRel::new(opos, pos, len, reverse);
+                if let MinSegment::MoveThrough(_, exit) = *self {
+                    move_rel.exit = exit;
+                }
                 // note order is reversed because of stack
                 vec![
-                    Box::new(MoveRel {
-                        start: opos,
-                        end: pos,
-                        dist: len,
-                    }),
-                    Box::new(TurnTo {
-                        start_heading: heading,
-                        target_heading: optimise_target_heading(heading, target_heading),
-                    }),
+                    Box::new(move_rel),
+                    Box::new(TurnTo::new(heading, target_heading).before_move()),
                 ]
             }
             MinSegment::MoveRel(rel) => {
-                let opos = odom.position();
-                vec![Box::new(MoveRel {
-                    start: opos,
-                    end: [opos[0] + heading.cos() * rel, opos[1] + heading.sin() * rel],
-                    dist: rel,
-                })]
+                let opos = ctx.odom.position();
+                vec![Box::new(MoveRel::new(
+                    opos,
+                    [opos[0] + heading.cos() * rel, opos[1] + heading.sin() * rel],
+                    rel,
+                    false,
+                ))]
             }
         }
     }
     fn finished_transform(&self) -> bool {
         false
     }
-    fn start(&mut self, _: &Odometry, _: &mut Pid) {
+    fn start(&mut self, _: &mut RobotCtx, _: &mut Pid) {
         unreachable!("segment should be always be transformed")
     }
-    fn follow(&mut self, _: &Odometry, _: &mut Pid) -> [f64; 2] {
+    fn follow(&mut self, _: &mut RobotCtx, _: &mut Pid) -> [f64; 2] {
         unreachable!("segment should be always be transformed")
     }
-    fn end_follow<'a>(&mut self, _: &Odometry) -> Option<Vec<Box<dyn PathSegment + 'a>>> {
+    fn end_follow<'a>(
+        &mut self,
+        _: &mut RobotCtx,
+        _: &mut Pid,
+    ) -> Option<Vec<Box<dyn PathSegment + 'a>>> {
         unreachable!("segment should be always be transformed")
     }
     fn boxed_clone<'a>(&self) -> Box<dyn PathSegment + 'a> {
@@ -371,32 +1063,188 @@ struct MoveRel {
     start: [f64; 2],
     end: [f64; 2],
     dist: f64,
+    // drive backwards along the line
+    reverse: bool,
+    // velocities the line is entered and left at (see chained_velocity_profile)
+    entry: f64,
+    exit: f64,
+    settle: Settled,
+    gains: GainOverride,
+    stall: StallDetector,
+    // power last commanded along the line
+    last_pow: f64,
+    // steers the heading target to bring the robot back onto the line
+    cross_track: Pid,
+}
+
+// heading error under which a move chained onto a MoveThrough skips turning first
+const CHAIN_HEADING_TOLERANCE: f64 = 3.0 * PI / 180.0;
+
+// gains of the cross track controller of MoveRel from the distance
+// off the line in meters to a change in heading target in radians
+const CROSS_TRACK_GAINS: PidGains = PidGains::new(1.0, 0.0, 0.0);
+// kept below the 8deg at which MoveRel replans
+const MAX_CROSS_TRACK_ANGLE: f64 = 5.0 * PI / 180.0;
+
+// maximum differential power the heading hold can add to
+// each side during a MoveRel so it can't stall a side
+const MAX_HEADING_CORRECTION: f64 = 0.3;
+
+impl MoveRel {
+    fn new(start: [f64; 2], end: [f64; 2], dist: f64, reverse: bool) -> Self {
+        Self {
+            start,
+            end,
+            dist,
+            reverse,
+            entry: 0.0,
+            exit: 0.0,
+            settle: Settled::new(MOVE_SETTLE),
+            gains: GainOverride::default(),
+            stall: StallDetector::new(StallAction::End),
+            last_pow: 0.0,
+            cross_track: Pid::new(
+                CROSS_TRACK_GAINS.kp,
+                CROSS_TRACK_GAINS.ki,
+                CROSS_TRACK_GAINS.kd,
+            ),
+        }
+    }
+    // heading the robot should face while following the line
+    fn ideal_heading(&self) -> f64 {
+        let heading = (self.end[1] - self.start[1]).atan2(self.end[0] - self.start[0]);
+        if self.reverse {
+            heading + PI
+        } else {
+            heading
+        }
+    }
+    // signed distance of pos from the line (positive to the left of it)
+    fn cross_track_error(&self, pos: Vec2) -> f64 {
+        let start: Vec2 = self.start.into();
+        let end: Vec2 = self.end.into();
+        if (end - start).mag() == 0.0 {
+            return 0.0;
+        }
+        let dir = (end - start).normalised();
+        let rel = pos - start;
+        dir.x() * rel.y() - dir.y() * rel.x()
+    }
+    // segment to replan with when the line can't be followed
+    fn replan(&self) -> Box<MinSegment> {
+        if self.exit > 0.0 && !self.reverse {
+            Box::new(MinSegment::MoveThrough(self.end, self.exit))
+        } else if self.reverse {
+            Box::new(MinSegment::MoveToReverse(self.end))
+        } else {
+            Box::new(MinSegment::MoveTo(self.end))
+        }
+    }
 }
 
 impl PathSegment for MoveRel {
-    fn transform<'a>(self: Box<Self>, _: &Odometry) -> Vec<Box<dyn PathSegment + 'a>> {
+    fn transform<'a>(self: Box<Self>, _: &RobotCtx) -> Vec<Box<dyn PathSegment + 'a>> {
         unreachable!("transform should never get called since finished_transform is true")
     }
     fn finished_transform(&self) -> bool {
         true
     }
-    fn start(&mut self, _: &Odometry, _: &mut Pid) {}
-    fn follow(&mut self, odom: &Odometry, _: &mut Pid) -> [f64; 2] {
-        let pow = velocity_profile(
+    // hold the heading of the line with the angle pid so small
+    // drift is corrected rather then replanned in end_follow
+    fn start(&mut self, ctx: &mut RobotCtx, angle_pid: &mut Pid) {
+        self.settle.reset();
+        self.stall.since = None;
+        self.cross_track.set_target(0.0);
+        self.cross_track.reset();
+        self.gains.apply(angle_pid);
+        angle_pid.set_target(optimise_target_heading(
+            ctx.odom.heading(),
+            self.ideal_heading(),
+        ));
+        angle_pid.reset();
+    }
+    fn follow(&mut self, ctx: &mut RobotCtx, angle_pid: &mut Pid) -> [f64; 2] {
+        let mut pow = chained_velocity_profile(
             self.start.into(),
             self.end.into(),
             self.dist,
-            odom.position().into(),
+            ctx.odom.position().into(),
+            self.entry,
+            self.exit,
+            &ctx.constraints,
         );
-        [pow; 2]
+        if self.reverse {
+            pow = -pow;
+        }
+        self.last_pow = pow;
+        // rather then only holding the heading of the line, aim back
+        // towards the line in proportion to how far the robot is off it
+        let offset = self
+            .cross_track
+            .poll(self.cross_track_error(ctx.odom.position().into()))
+            .clamp(-MAX_CROSS_TRACK_ANGLE, MAX_CROSS_TRACK_ANGLE);
+        angle_pid.set_target(optimise_target_heading(
+            ctx.odom.heading(),
+            self.ideal_heading() + offset,
+        ));
+        let correction = angle_pid
+            .poll(ctx.odom.heading())
+            .clamp(-MAX_HEADING_CORRECTION, MAX_HEADING_CORRECTION);
+        [pow - correction, pow + correction]
+    }
+    fn set_settle(&mut self, _: Option<SettleCondition>, linear: Option<SettleCondition>) {
+        if let Some(settle) = linear {
+            self.settle.set_condition(settle);
+        }
+    }
+    fn set_stall_action(&mut self, action: StallAction) {
+        self.stall.action = action;
+    }
+    fn exit_velocity(&self) -> f64 {
+        self.exit
+    }
+    fn set_entry_velocity(&mut self, velocity: f64) {
+        self.entry = velocity;
+    }
+    fn set_gains(&mut self, _: Option<PidGains>, linear: Option<PidGains>) {
+        if linear.is_some() {
+            self.gains.gains = linear;
+        }
+    }
+    fn end_follow<'a>(
+        &mut self,
+        ctx: &mut RobotCtx,
+        angle_pid: &mut Pid,
+    ) -> Option<Vec<Box<dyn PathSegment + 'a>>> {
+        let segs = self.check_end(ctx)?;
+        self.gains.restore(angle_pid);
+        Some(segs)
+    }
+    fn abrupt_end(&mut self, _: &mut RobotCtx, angle_pid: &mut Pid) {
+        self.gains.restore(angle_pid);
     }
-    fn end_follow<'a>(&mut self, odom: &Odometry) -> Option<Vec<Box<dyn PathSegment + 'a>>> {
-        let ideal_heading = (self.end[1] - self.start[1]).atan2(self.end[0] - self.start[0]);
-        let ideal_heading = optimise_target_heading(odom.heading(), ideal_heading);
+}
+
+impl MoveRel {
+    // returns the segments to continue with once the line is finished
+    // or can no longer be followed
+    fn check_end<'a>(&mut self, ctx: &mut RobotCtx) -> Option<Vec<Box<dyn PathSegment + 'a>>> {
+        if self
+            .stall
+            .stalled(self.last_pow, ctx.odom.settle_velocity())
+        {
+            log::warn!(
+                "MoveRel stalled, handling with {:?}. pos: {:?}",
+                self.stall.action,
+                ctx.odom.position()
+            );
+            return Some(self.stall.handle(ctx, self.replan(), self.last_pow));
+        }
+        let ideal_heading = optimise_target_heading(ctx.odom.heading(), self.ideal_heading());
         // check heading is within +-3 deg
-        if (odom.heading() - ideal_heading).abs() > 8f64.to_radians() {
-            let new_segs = Box::new(MinSegment::MoveTo(self.end));
-            log::warn!("MoveRel failed due to exceeding a +- 8deg heading ({} vs {}). Creating MoveTo segment.", odom.heading(), ideal_heading);
+        if (ctx.odom.heading() - ideal_heading).abs() > 8f64.to_radians() {
+            let new_segs = self.replan();
+            log::warn!("MoveRel failed due to exceeding a +- 8deg heading ({} vs {}). Creating MoveTo segment.", ctx.odom.heading(), ideal_heading);
             return Some(vec![new_segs]);
         }
 
@@ -407,7 +1255,7 @@ impl PathSegment for MoveRel {
         // solve for the height from the base length and area.
         let end: Vec2 = self.end.into();
         let start: Vec2 = self.start.into();
-        let pos: Vec2 = odom.position().into();
+        let pos: Vec2 = ctx.odom.position().into();
         let base = (end - start).mag();
         let end_dist = (end - pos).mag();
         let start_dist = (start - pos).mag();
@@ -415,17 +1263,17 @@ impl PathSegment for MoveRel {
         let area = (s * (s - end_dist) * (s - start_dist) * (s - base)).sqrt();
         let near_dist = 2.0 * area / base;
         if near_dist > 0.10 {
-            let new_segs = Box::new(MinSegment::MoveTo([end.x(), end.y()]));
+            let new_segs = self.replan();
             log::warn!("Distance from closest point exceeds 10cm ({near_dist}). Creating MoveTo segment. pos: ({}, {})", pos.x(), pos.y());
             return Some(vec![new_segs]);
         }
 
-        // finish the segment if distance to end point is less then
-        // 5cm and (average side) velocity is < 1cm/s
+        // finish the segment if the distance to end point and velocity
+        // (see Odometry::settle_velocity) have settled or we overshot
         use communication::plot;
         plot!("dists", [end_dist, 2.0 * area / base]);
         plot!("end", [end.x(), end.y()]);
-        if 0.5 * (odom.side_velocities()[0] + odom.side_velocities()[1]) < 0.01 && end_dist < 0.03
+        if self.settle.update(end_dist, ctx.odom.settle_velocity())
             || (end_dist < start_dist && start_dist > base)
         {
             log::info!(
@@ -439,86 +1287,573 @@ impl PathSegment for MoveRel {
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct Ram {
-    pow: f64,
-    dur: std::time::Duration,
-    start: std::time::Instant,
+// Overrides the settle conditions of a segment (and the segments it
+// transforms into) e.g. a looser turn tolerance for a quick adjustment:
+// WithSettle::new(Box::new(MinSegment::TurnTo(h))).turn(TURN_SETTLE.with_error(0.1))
+#[derive(Debug)]
+pub struct WithSettle {
+    seg: Box<dyn PathSegment>,
+    turn: Option<SettleCondition>,
+    linear: Option<SettleCondition>,
 }
 
-impl Ram {
-    pub fn new(pow: f64, dur: std::time::Duration) -> Self {
+impl WithSettle {
+    pub fn new(seg: Box<dyn PathSegment>) -> Self {
         Self {
-            pow,
-            dur,
-            start: std::time::Instant::now(),
+            seg,
+            turn: None,
+            linear: None,
         }
     }
+    pub fn turn(mut self, settle: SettleCondition) -> Self {
+        self.turn = Some(settle);
+        self
+    }
+    pub fn linear(mut self, settle: SettleCondition) -> Self {
+        self.linear = Some(settle);
+        self
+    }
+    fn wrap<'a>(
+        segs: Vec<Box<dyn PathSegment>>,
+        turn: Option<SettleCondition>,
+        linear: Option<SettleCondition>,
+    ) -> Vec<Box<dyn PathSegment + 'a>> {
+        segs.into_iter()
+            .map(|seg| -> Box<dyn PathSegment + 'a> { Box::new(WithSettle { seg, turn, linear }) })
+            .collect()
+    }
 }
 
-impl PathSegment for Ram {
-    fn transform<'a>(self: Box<Self>, _: &Odometry) -> Vec<Box<dyn PathSegment + 'a>> {
-        unreachable!("transform should never get called since finished_transform is true")
+impl PathSegment for WithSettle {
+    fn transform<'a>(self: Box<Self>, ctx: &RobotCtx) -> Vec<Box<dyn PathSegment + 'a>> {
+        Self::wrap(self.seg.transform(ctx), self.turn, self.linear)
     }
     fn finished_transform(&self) -> bool {
-        true
+        self.seg.finished_transform()
     }
-    fn start(&mut self, _: &Odometry, _: &mut Pid) {
-        self.start = std::time::Instant::now();
+    fn start(&mut self, ctx: &mut RobotCtx, angle_pid: &mut Pid) {
+        self.seg.set_settle(self.turn, self.linear);
+        self.seg.start(ctx, angle_pid);
     }
-    fn follow(&mut self, _: &Odometry, _: &mut Pid) -> [f64; 2] {
-        [self.pow; 2]
+    fn follow(&mut self, ctx: &mut RobotCtx, angle_pid: &mut Pid) -> [f64; 2] {
+        self.seg.follow(ctx, angle_pid)
     }
-    fn end_follow<'a>(&mut self, _: &Odometry) -> Option<Vec<Box<dyn PathSegment + 'a>>> {
-        if self.start.elapsed() > self.dur {
-            return Some(Vec::new());
-        }
-        None
+    // segments replanned by the wrapped segment keep the settle conditions
+    fn end_follow<'a>(
+        &mut self,
+        ctx: &mut RobotCtx,
+        angle_pid: &mut Pid,
+    ) -> Option<Vec<Box<dyn PathSegment + 'a>>> {
+        let segs = self.seg.end_follow(ctx, angle_pid)?;
+        Some(Self::wrap(segs, self.turn, self.linear))
+    }
+    fn abrupt_end(&mut self, ctx: &mut RobotCtx, angle_pid: &mut Pid) {
+        self.seg.abrupt_end(ctx, angle_pid);
+    }
+    // the innermost settle conditions win
+    fn set_settle(&mut self, turn: Option<SettleCondition>, linear: Option<SettleCondition>) {
+        self.turn = self.turn.or(turn);
+        self.linear = self.linear.or(linear);
+    }
+    fn set_gains(&mut self, turn: Option<PidGains>, linear: Option<PidGains>) {
+        self.seg.set_gains(turn, linear);
+    }
+    fn exit_velocity(&self) -> f64 {
+        self.seg.exit_velocity()
+    }
+    fn set_entry_velocity(&mut self, velocity: f64) {
+        self.seg.set_entry_velocity(velocity);
+    }
+    fn set_stall_action(&mut self, action: StallAction) {
+        self.seg.set_stall_action(action);
     }
     fn boxed_clone<'a>(&self) -> Box<dyn PathSegment + 'a> {
-        Box::new(self.clone())
+        Box::new(WithSettle {
+            seg: self.seg.boxed_clone(),
+            turn: self.turn,
+            linear: self.linear,
+        })
     }
 }
 
+// Overrides the angle pid gains of a segment (and the segments it
+// transforms into) while it runs, e.g. a long turn needing less kp:
+// WithGains::new(Box::new(MinSegment::TurnRel(PI))).turn(PidGains::new(0.25, 0.02, 0.002))
 #[derive(Debug)]
-pub struct TimedSegment {
+pub struct WithGains {
     seg: Box<dyn PathSegment>,
-    dur: std::time::Duration,
-    start: std::time::Instant,
+    turn: Option<PidGains>,
+    linear: Option<PidGains>,
 }
 
-impl TimedSegment {
-    pub fn new(seg: Box<dyn PathSegment>, dur: std::time::Duration) -> Self {
+impl WithGains {
+    pub fn new(seg: Box<dyn PathSegment>) -> Self {
         Self {
             seg,
-            dur,
-            start: std::time::Instant::now(),
+            turn: None,
+            linear: None,
         }
     }
+    pub fn turn(mut self, gains: PidGains) -> Self {
+        self.turn = Some(gains);
+        self
+    }
+    // gains for the heading hold of linear moves
+    pub fn linear(mut self, gains: PidGains) -> Self {
+        self.linear = Some(gains);
+        self
+    }
+    fn wrap<'a>(
+        segs: Vec<Box<dyn PathSegment>>,
+        turn: Option<PidGains>,
+        linear: Option<PidGains>,
+    ) -> Vec<Box<dyn PathSegment + 'a>> {
+        segs.into_iter()
+            .map(|seg| -> Box<dyn PathSegment + 'a> { Box::new(WithGains { seg, turn, linear }) })
+            .collect()
+    }
 }
 
-impl PathSegment for TimedSegment {
-    fn transform<'a>(self: Box<Self>, odom: &Odometry) -> Vec<Box<dyn PathSegment + 'a>> {
-        self.seg.transform(odom)
+impl PathSegment for WithGains {
+    fn transform<'a>(self: Box<Self>, ctx: &RobotCtx) -> Vec<Box<dyn PathSegment + 'a>> {
+        Self::wrap(self.seg.transform(ctx), self.turn, self.linear)
     }
     fn finished_transform(&self) -> bool {
         self.seg.finished_transform()
     }
-    fn start(&mut self, odom: &Odometry, angle_pid: &mut Pid) {
-        self.start = std::time::Instant::now();
-        self.seg.start(odom, angle_pid);
+    fn start(&mut self, ctx: &mut RobotCtx, angle_pid: &mut Pid) {
+        self.seg.set_gains(self.turn, self.linear);
+        self.seg.start(ctx, angle_pid);
     }
-    fn follow(&mut self, odom: &Odometry, angle_pid: &mut Pid) -> [f64; 2] {
-        self.seg.follow(odom, angle_pid)
+    fn follow(&mut self, ctx: &mut RobotCtx, angle_pid: &mut Pid) -> [f64; 2] {
+        self.seg.follow(ctx, angle_pid)
     }
-    fn end_follow<'a>(&mut self, odom: &Odometry) -> Option<Vec<Box<dyn PathSegment + 'a>>> {
-        if self.start.elapsed() > self.dur {
-            self.seg.abrupt_end(odom);
-            return Some(Vec::new());
-        }
-        self.seg.end_follow(odom)
+    // segments replanned by the wrapped segment keep the gains
+    fn end_follow<'a>(
+        &mut self,
+        ctx: &mut RobotCtx,
+        angle_pid: &mut Pid,
+    ) -> Option<Vec<Box<dyn PathSegment + 'a>>> {
+        let segs = self.seg.end_follow(ctx, angle_pid)?;
+        Some(Self::wrap(segs, self.turn, self.linear))
     }
-    fn boxed_clone<'a>(&self) -> Box<dyn PathSegment + 'a> {
+    fn abrupt_end(&mut self, ctx: &mut RobotCtx, angle_pid: &mut Pid) {
+        self.seg.abrupt_end(ctx, angle_pid);
+    }
+    fn set_settle(&mut self, turn: Option<SettleCondition>, linear: Option<SettleCondition>) {
+        self.seg.set_settle(turn, linear);
+    }
+    // the innermost gains win
+    fn set_gains(&mut self, turn: Option<PidGains>, linear: Option<PidGains>) {
+        self.turn = self.turn.or(turn);
+        self.linear = self.linear.or(linear);
+    }
+    fn exit_velocity(&self) -> f64 {
+        self.seg.exit_velocity()
+    }
+    fn set_entry_velocity(&mut self, velocity: f64) {
+        self.seg.set_entry_velocity(velocity);
+    }
+    fn set_stall_action(&mut self, action: StallAction) {
+        self.seg.set_stall_action(action);
+    }
+    fn boxed_clone<'a>(&self) -> Box<dyn PathSegment + 'a> {
+        Box::new(WithGains {
+            seg: self.seg.boxed_clone(),
+            turn: self.turn,
+            linear: self.linear,
+        })
+    }
+}
+
+// Sets what the drive segments of a segment (and the segments it
+// transforms into) do when they stall, e.g. backing off and retrying:
+// OnStall::new(Box::new(MinSegment::MoveTo(p)), StallAction::BackOff { pow: 0.3, dur, retries: 2 })
+#[derive(Debug)]
+pub struct OnStall {
+    seg: Box<dyn PathSegment>,
+    action: StallAction,
+}
+
+impl OnStall {
+    pub fn new(seg: Box<dyn PathSegment>, action: StallAction) -> Self {
+        Self { seg, action }
+    }
+    fn wrap<'a>(
+        segs: Vec<Box<dyn PathSegment>>,
+        action: StallAction,
+    ) -> Vec<Box<dyn PathSegment + 'a>> {
+        segs.into_iter()
+            .map(|seg| -> Box<dyn PathSegment + 'a> { Box::new(OnStall { seg, action }) })
+            .collect()
+    }
+}
+
+impl PathSegment for OnStall {
+    fn transform<'a>(self: Box<Self>, ctx: &RobotCtx) -> Vec<Box<dyn PathSegment + 'a>> {
+        Self::wrap(self.seg.transform(ctx), self.action)
+    }
+    fn finished_transform(&self) -> bool {
+        self.seg.finished_transform()
+    }
+    fn start(&mut self, ctx: &mut RobotCtx, angle_pid: &mut Pid) {
+        self.seg.set_stall_action(self.action);
+        self.seg.start(ctx, angle_pid);
+    }
+    fn follow(&mut self, ctx: &mut RobotCtx, angle_pid: &mut Pid) -> [f64; 2] {
+        self.seg.follow(ctx, angle_pid)
+    }
+    // retried and replanned segments keep the stall action
+    fn end_follow<'a>(
+        &mut self,
+        ctx: &mut RobotCtx,
+        angle_pid: &mut Pid,
+    ) -> Option<Vec<Box<dyn PathSegment + 'a>>> {
+        let segs = self.seg.end_follow(ctx, angle_pid)?;
+        Some(Self::wrap(segs, self.action))
+    }
+    fn abrupt_end(&mut self, ctx: &mut RobotCtx, angle_pid: &mut Pid) {
+        self.seg.abrupt_end(ctx, angle_pid);
+    }
+    fn set_settle(&mut self, turn: Option<SettleCondition>, linear: Option<SettleCondition>) {
+        self.seg.set_settle(turn, linear);
+    }
+    fn set_gains(&mut self, turn: Option<PidGains>, linear: Option<PidGains>) {
+        self.seg.set_gains(turn, linear);
+    }
+    fn exit_velocity(&self) -> f64 {
+        self.seg.exit_velocity()
+    }
+    fn set_entry_velocity(&mut self, velocity: f64) {
+        self.seg.set_entry_velocity(velocity);
+    }
+    fn boxed_clone<'a>(&self) -> Box<dyn PathSegment + 'a> {
+        Box::new(OnStall {
+            seg: self.seg.boxed_clone(),
+            action: self.action,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Left,
+    Right,
+}
+
+// Drives forward along a circular arc turning towards direction. The
+// ratio between the side velocities is fixed by the radius so unlike
+// MoveTo no turn is needed first. The velocity profile is based
+// on the distance along the arc (measured from the change in heading).
+#[derive(Debug, Clone)]
+pub struct ArcSegment {
+    // radius of the arc followed by the centre of the robot
+    radius: f64,
+    // angle to turn through in radians
+    angle: f64,
+    direction: Side,
+    start_heading: f64,
+}
+
+impl ArcSegment {
+    pub fn new(radius: f64, angle: f64, direction: Side) -> Self {
+        Self {
+            radius,
+            angle: angle.abs(),
+            direction,
+            start_heading: 0.0,
+        }
+    }
+    // angle turned so far along the arc
+    fn progress(&self, heading: f64) -> f64 {
+        match self.direction {
+            Side::Left => heading - self.start_heading,
+            Side::Right => self.start_heading - heading,
+        }
+    }
+}
+
+impl PathSegment for ArcSegment {
+    fn transform<'a>(self: Box<Self>, _: &RobotCtx) -> Vec<Box<dyn PathSegment + 'a>> {
+        unreachable!("transform should never get called since finished_transform is true")
+    }
+    fn finished_transform(&self) -> bool {
+        true
+    }
+    fn start(&mut self, ctx: &mut RobotCtx, _: &mut Pid) {
+        self.start_heading = ctx.odom.heading();
+        if self.radius < 0.5 * ctx.constraints.track_width {
+            log::warn!("ArcSegment has a radius ({}) smaller then half the track width. The inner side will drive backwards.", self.radius);
+        }
+    }
+    fn follow(&mut self, ctx: &mut RobotCtx, _: &mut Pid) -> [f64; 2] {
+        let arc_len = self.radius * self.angle;
+        let travelled = self.radius * self.progress(ctx.odom.heading());
+        let pow = velocity_profile(
+            [0.0, 0.0].into(),
+            [arc_len, 0.0].into(),
+            arc_len,
+            [travelled, 0.0].into(),
+            &ctx.constraints,
+        );
+        // the outer side drives at pow and the inner side is
+        // slowed by the ratio of the radii of the two sides
+        let half_track = 0.5 * ctx.constraints.track_width;
+        let inner = pow * (self.radius - half_track) / (self.radius + half_track);
+        match self.direction {
+            Side::Left => [inner, pow],
+            Side::Right => [pow, inner],
+        }
+    }
+    fn end_follow<'a>(
+        &mut self,
+        ctx: &mut RobotCtx,
+        _: &mut Pid,
+    ) -> Option<Vec<Box<dyn PathSegment + 'a>>> {
+        // the profile goes to zero at the end of the arc so
+        // finish within 2deg to avoid stalling just short of it
+        if self.progress(ctx.odom.heading()) >= self.angle - 2f64.to_radians() {
+            log::info!(
+                "Finished segment - ArcSegment(radius: {}, angle: {}) with heading ({}).",
+                self.radius,
+                self.angle,
+                ctx.odom.heading()
+            );
+            return Some(Vec::new());
+        }
+        None
+    }
+    fn boxed_clone<'a>(&self) -> Box<dyn PathSegment + 'a> {
+        Box::new(self.clone())
+    }
+}
+
+// Turns to a heading by only driving one side of the drivetrain,
+// pivoting around the other side which is held at the hold power.
+// Useful in tight spots where a point turn would hit a wall.
+#[derive(Debug, Clone)]
+pub struct SwingTurn {
+    target_heading: f64,
+    pivot: Side,
+    hold: f64,
+    settle: Settled,
+    gains: GainOverride,
+}
+
+impl SwingTurn {
+    pub fn new(target_heading: f64, pivot: Side, hold: f64) -> Self {
+        Self {
+            target_heading,
+            pivot,
+            hold,
+            settle: Settled::new(TURN_SETTLE),
+            gains: GainOverride::default(),
+        }
+    }
+}
+
+impl PathSegment for SwingTurn {
+    fn transform<'a>(self: Box<Self>, _: &RobotCtx) -> Vec<Box<dyn PathSegment + 'a>> {
+        unreachable!("transform should never get called since finished_transform is true")
+    }
+    fn finished_transform(&self) -> bool {
+        true
+    }
+    fn start(&mut self, ctx: &mut RobotCtx, angle_pid: &mut Pid) {
+        self.target_heading = optimise_target_heading(ctx.odom.heading(), self.target_heading);
+        self.settle.reset();
+        self.gains.apply(angle_pid);
+        angle_pid.set_target(self.target_heading);
+        angle_pid.reset();
+    }
+    fn set_settle(&mut self, turn: Option<SettleCondition>, _: Option<SettleCondition>) {
+        if let Some(settle) = turn {
+            self.settle.set_condition(settle);
+        }
+    }
+    fn set_gains(&mut self, turn: Option<PidGains>, _: Option<PidGains>) {
+        if turn.is_some() {
+            self.gains.gains = turn;
+        }
+    }
+    fn follow(&mut self, ctx: &mut RobotCtx, angle_pid: &mut Pid) -> [f64; 2] {
+        // the driven side has to do the work of both sides of a point turn,
+        // still within the limit once doubled
+        let max = ctx.constraints.max_ang_vel;
+        let pow = (2.0 * angle_pid.poll(ctx.odom.heading())).clamp(-max, max);
+        match self.pivot {
+            Side::Left => [self.hold, pow],
+            Side::Right => [-pow, self.hold],
+        }
+    }
+    fn end_follow<'a>(
+        &mut self,
+        ctx: &mut RobotCtx,
+        angle_pid: &mut Pid,
+    ) -> Option<Vec<Box<dyn PathSegment + 'a>>> {
+        if self.settle.update(
+            ctx.odom.heading() - self.target_heading,
+            ctx.odom.angular_velocity(),
+        ) {
+            log::info!(
+                "Finished segment - SwingTurn({}) with heading ({}).",
+                self.target_heading,
+                ctx.odom.heading()
+            );
+            self.gains.restore(angle_pid);
+            return Some(vec![]);
+        }
+        None
+    }
+    fn abrupt_end(&mut self, _: &mut RobotCtx, angle_pid: &mut Pid) {
+        self.gains.restore(angle_pid);
+    }
+    fn boxed_clone<'a>(&self) -> Box<dyn PathSegment + 'a> {
+        Box::new(self.clone())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Ram {
+    pow: f64,
+    dur: std::time::Duration,
+    start: std::time::Instant,
+    // off by default as ramming into something is often the point
+    stall: Option<StallDetector>,
+    // the back off of a stalled segment, which never has stall detection
+    // of its own so an OnStall around the stalled segment can't retry it
+    back_off: bool,
+}
+
+impl Ram {
+    pub fn new(pow: f64, dur: std::time::Duration) -> Self {
+        Self {
+            pow,
+            dur,
+            start: std::time::Instant::now(),
+            stall: None,
+            back_off: false,
+        }
+    }
+    fn back_off(pow: f64, dur: std::time::Duration) -> Self {
+        Self {
+            back_off: true,
+            ..Self::new(pow, dur)
+        }
+    }
+    pub fn on_stall(mut self, action: StallAction) -> Self {
+        self.stall = Some(StallDetector::new(action));
+        self
+    }
+}
+
+impl PathSegment for Ram {
+    fn transform<'a>(self: Box<Self>, _: &RobotCtx) -> Vec<Box<dyn PathSegment + 'a>> {
+        unreachable!("transform should never get called since finished_transform is true")
+    }
+    fn finished_transform(&self) -> bool {
+        true
+    }
+    fn start(&mut self, _: &mut RobotCtx, _: &mut Pid) {
+        self.start = std::time::Instant::now();
+        if let Some(stall) = self.stall.as_mut() {
+            stall.since = None;
+        }
+    }
+    fn follow(&mut self, _: &mut RobotCtx, _: &mut Pid) -> [f64; 2] {
+        [self.pow; 2]
+    }
+    fn set_stall_action(&mut self, action: StallAction) {
+        if !self.back_off {
+            self.stall = Some(StallDetector::new(action));
+        }
+    }
+    fn end_follow<'a>(
+        &mut self,
+        ctx: &mut RobotCtx,
+        _: &mut Pid,
+    ) -> Option<Vec<Box<dyn PathSegment + 'a>>> {
+        if self.start.elapsed() > self.dur {
+            return Some(Vec::new());
+        }
+        if let Some(mut stall) = self.stall {
+            let stalled = stall.stalled(self.pow, ctx.odom.settle_velocity());
+            self.stall = Some(stall);
+            if stalled {
+                log::warn!("Ram stalled, handling with {:?}.", stall.action);
+                // the retry only runs for the remaining time
+                let retry = Ram {
+                    dur: self.dur.saturating_sub(self.start.elapsed()),
+                    ..self.clone()
+                };
+                return Some(stall.handle(ctx, Box::new(retry), self.pow));
+            }
+        }
+        None
+    }
+    fn boxed_clone<'a>(&self) -> Box<dyn PathSegment + 'a> {
+        Box::new(self.clone())
+    }
+}
+
+#[derive(Debug)]
+pub struct TimedSegment {
+    seg: Box<dyn PathSegment>,
+    dur: std::time::Duration,
+    start: std::time::Instant,
+}
+
+impl TimedSegment {
+    pub fn new(seg: Box<dyn PathSegment>, dur: std::time::Duration) -> Self {
+        Self {
+            seg,
+            dur,
+            start: std::time::Instant::now(),
+        }
+    }
+}
+
+impl PathSegment for TimedSegment {
+    fn transform<'a>(self: Box<Self>, ctx: &RobotCtx) -> Vec<Box<dyn PathSegment + 'a>> {
+        self.seg.transform(ctx)
+    }
+    fn finished_transform(&self) -> bool {
+        self.seg.finished_transform()
+    }
+    fn start(&mut self, ctx: &mut RobotCtx, angle_pid: &mut Pid) {
+        self.start = std::time::Instant::now();
+        self.seg.start(ctx, angle_pid);
+    }
+    fn follow(&mut self, ctx: &mut RobotCtx, angle_pid: &mut Pid) -> [f64; 2] {
+        self.seg.follow(ctx, angle_pid)
+    }
+    fn set_settle(&mut self, turn: Option<SettleCondition>, linear: Option<SettleCondition>) {
+        self.seg.set_settle(turn, linear);
+    }
+    fn set_gains(&mut self, turn: Option<PidGains>, linear: Option<PidGains>) {
+        self.seg.set_gains(turn, linear);
+    }
+    fn exit_velocity(&self) -> f64 {
+        self.seg.exit_velocity()
+    }
+    fn set_entry_velocity(&mut self, velocity: f64) {
+        self.seg.set_entry_velocity(velocity);
+    }
+    fn set_stall_action(&mut self, action: StallAction) {
+        self.seg.set_stall_action(action);
+    }
+    fn end_follow<'a>(
+        &mut self,
+        ctx: &mut RobotCtx,
+        angle_pid: &mut Pid,
+    ) -> Option<Vec<Box<dyn PathSegment + 'a>>> {
+        if self.start.elapsed() > self.dur {
+            self.seg.abrupt_end(ctx, angle_pid);
+            return Some(Vec::new());
+        }
+        self.seg.end_follow(ctx, angle_pid)
+    }
+    fn boxed_clone<'a>(&self) -> Box<dyn PathSegment + 'a> {
         Box::new(Self {
             seg: self.seg.as_ref().boxed_clone(),
             dur: self.dur,
@@ -547,14 +1882,14 @@ impl<const N: usize> PowerMotors<N> {
 }
 
 impl<const N: usize> PathSegment for PowerMotors<N> {
-    fn transform<'a>(self: Box<Self>, _: &Odometry) -> Vec<Box<dyn PathSegment + 'a>> {
+    fn transform<'a>(self: Box<Self>, _: &RobotCtx) -> Vec<Box<dyn PathSegment + 'a>> {
         unreachable!("transform should never get called since finished_transform is true")
     }
     fn finished_transform(&self) -> bool {
         true
     }
-    fn start(&mut self, _: &Odometry, _: &mut Pid) {}
-    fn follow(&mut self, _: &Odometry, _: &mut Pid) -> [f64; 2] {
+    fn start(&mut self, _: &mut RobotCtx, _: &mut Pid) {}
+    fn follow(&mut self, _: &mut RobotCtx, _: &mut Pid) -> [f64; 2] {
         for (motor, rev) in &mut self.motors {
             if *rev {
                 motor.set_target(crate::motor::Target::PercentVoltage(-self.pow));
@@ -564,12 +1899,16 @@ impl<const N: usize> PathSegment for PowerMotors<N> {
         }
         [0.0, 0.0]
     }
-    fn abrupt_end(&mut self, _: &Odometry) {
+    fn abrupt_end(&mut self, _: &mut RobotCtx, _: &mut Pid) {
         for (motor, _) in &mut self.motors {
             motor.set_target(crate::motor::Target::PercentVoltage(0.0));
         }
     }
-    fn end_follow<'a>(&mut self, _: &Odometry) -> Option<Vec<Box<dyn PathSegment + 'a>>> {
+    fn end_follow<'a>(
+        &mut self,
+        _: &mut RobotCtx,
+        _: &mut Pid,
+    ) -> Option<Vec<Box<dyn PathSegment + 'a>>> {
         None
     }
     fn boxed_clone<'a>(&self) -> Box<dyn PathSegment + 'a> {
@@ -577,34 +1916,199 @@ impl<const N: usize> PathSegment for PowerMotors<N> {
     }
 }
 
+// Moves a mechanism to a position with a CascadedPosition controller
+// and finishes once it has settled there.
+#[derive(Debug, Clone)]
+pub struct MotorsTo {
+    controller: CascadedPosition,
+    target: f64,
+}
+
+impl MotorsTo {
+    pub fn new(controller: CascadedPosition, target: f64) -> Self {
+        Self { controller, target }
+    }
+}
+
+impl PathSegment for MotorsTo {
+    fn transform<'a>(self: Box<Self>, _: &RobotCtx) -> Vec<Box<dyn PathSegment + 'a>> {
+        unreachable!("transform should never get called since finished_transform is true")
+    }
+    fn finished_transform(&self) -> bool {
+        true
+    }
+    fn start(&mut self, _: &mut RobotCtx, _: &mut Pid) {
+        self.controller.set_target(self.target);
+    }
+    fn follow(&mut self, _: &mut RobotCtx, _: &mut Pid) -> [f64; 2] {
+        self.controller.poll();
+        [0.0, 0.0]
+    }
+    fn end_follow<'a>(
+        &mut self,
+        _: &mut RobotCtx,
+        _: &mut Pid,
+    ) -> Option<Vec<Box<dyn PathSegment + 'a>>> {
+        if self.controller.settled() {
+            log::info!("Finished segment - MotorsTo({}).", self.target);
+            self.controller.stop();
+            return Some(Vec::new());
+        }
+        None
+    }
+    fn abrupt_end(&mut self, _: &mut RobotCtx, _: &mut Pid) {
+        self.controller.stop();
+    }
+    fn boxed_clone<'a>(&self) -> Box<dyn PathSegment + 'a> {
+        Box::new(self.clone())
+    }
+}
+
+// Moves a lift to one of its presets and finishes once it has settled
+// there. The lift is moved by the Subsystems it is registered with so
+// the segment only sets the target.
+#[derive(Debug, Clone)]
+pub struct LiftTo {
+    lift: Rc<RefCell<Lift>>,
+    preset: &'static str,
+}
+
+impl LiftTo {
+    pub fn new(lift: Rc<RefCell<Lift>>, preset: &'static str) -> Self {
+        Self { lift, preset }
+    }
+}
+
+impl PathSegment for LiftTo {
+    fn transform<'a>(self: Box<Self>, _: &RobotCtx) -> Vec<Box<dyn PathSegment + 'a>> {
+        unreachable!("transform should never get called since finished_transform is true")
+    }
+    fn finished_transform(&self) -> bool {
+        true
+    }
+    fn start(&mut self, _: &mut RobotCtx, _: &mut Pid) {
+        self.lift.borrow_mut().go_to(self.preset);
+    }
+    fn follow(&mut self, _: &mut RobotCtx, _: &mut Pid) -> [f64; 2] {
+        [0.0, 0.0]
+    }
+    fn end_follow<'a>(
+        &mut self,
+        _: &mut RobotCtx,
+        _: &mut Pid,
+    ) -> Option<Vec<Box<dyn PathSegment + 'a>>> {
+        let lift = self.lift.borrow();
+        // an unknown preset has no target and is skipped
+        if lift.settled() || lift.target().is_none() {
+            log::info!("Finished segment - LiftTo({}).", self.preset);
+            return Some(Vec::new());
+        }
+        None
+    }
+    fn abrupt_end(&mut self, _: &mut RobotCtx, _: &mut Pid) {
+        self.lift.borrow_mut().stop();
+    }
+    fn boxed_clone<'a>(&self) -> Box<dyn PathSegment + 'a> {
+        Box::new(self.clone())
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct Nop {}
 
 impl PathSegment for Nop {
-    fn transform<'a>(self: Box<Self>, _: &Odometry) -> Vec<Box<dyn PathSegment + 'a>> {
+    fn transform<'a>(self: Box<Self>, _: &RobotCtx) -> Vec<Box<dyn PathSegment + 'a>> {
+        unreachable!("transform should never get called since finished_transform is true")
+    }
+    fn finished_transform(&self) -> bool {
+        true
+    }
+    fn start(&mut self, _: &mut RobotCtx, _: &mut Pid) {}
+    fn follow(&mut self, _: &mut RobotCtx, _: &mut Pid) -> [f64; 2] {
+        [0.0, 0.0]
+    }
+    fn end_follow<'a>(
+        &mut self,
+        _: &mut RobotCtx,
+        _: &mut Pid,
+    ) -> Option<Vec<Box<dyn PathSegment + 'a>>> {
+        None
+    }
+    fn boxed_clone<'a>(&self) -> Box<dyn PathSegment + 'a> {
+        Box::new(*self)
+    }
+}
+
+// Holds the drivetrain still until a condition becomes true
+// or the (optional) timeout has elapsed.
+pub struct WaitUntil<F: Fn(&RobotCtx) -> bool> {
+    cond: F,
+    timeout: Option<Duration>,
+    start: Instant,
+}
+
+impl<F: Fn(&RobotCtx) -> bool> WaitUntil<F> {
+    pub fn new(cond: F, timeout: Option<Duration>) -> Self {
+        Self {
+            cond,
+            timeout,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl<F: Fn(&RobotCtx) -> bool> std::fmt::Debug for WaitUntil<F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WaitUntil")
+            .field("timeout", &self.timeout)
+            .field("start", &self.start)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<F: Fn(&RobotCtx) -> bool + Clone + 'static> PathSegment for WaitUntil<F> {
+    fn transform<'a>(self: Box<Self>, _: &RobotCtx) -> Vec<Box<dyn PathSegment + 'a>> {
         unreachable!("transform should never get called since finished_transform is true")
     }
     fn finished_transform(&self) -> bool {
         true
     }
-    fn start(&mut self, _: &Odometry, _: &mut Pid) {}
-    fn follow(&mut self, _: &Odometry, _: &mut Pid) -> [f64; 2] {
+    fn start(&mut self, _: &mut RobotCtx, _: &mut Pid) {
+        self.start = Instant::now();
+    }
+    fn follow(&mut self, _: &mut RobotCtx, _: &mut Pid) -> [f64; 2] {
         [0.0, 0.0]
     }
-    fn end_follow<'a>(&mut self, _: &Odometry) -> Option<Vec<Box<dyn PathSegment + 'a>>> {
+    fn end_follow<'a>(
+        &mut self,
+        ctx: &mut RobotCtx,
+        _: &mut Pid,
+    ) -> Option<Vec<Box<dyn PathSegment + 'a>>> {
+        if (self.cond)(ctx) {
+            return Some(Vec::new());
+        }
+        if self.timeout.is_some_and(|t| self.start.elapsed() > t) {
+            log::warn!("WaitUntil timed out after {:?}.", self.start.elapsed());
+            return Some(Vec::new());
+        }
         None
     }
     fn boxed_clone<'a>(&self) -> Box<dyn PathSegment + 'a> {
-        Box::new(*self)
+        Box::new(Self {
+            cond: self.cond.clone(),
+            timeout: self.timeout,
+            start: self.start,
+        })
     }
 }
 
-#[derive(Debug)]
-pub struct RepeatSegment {
+pub struct RepeatSegment<F: Fn(&RobotCtx) -> bool = fn(&RobotCtx) -> bool> {
     max_count: usize,
     count: usize,
     ref_seg: Box<dyn PathSegment>,
     current_seg: Box<dyn PathSegment>,
+    // stops repeating early once true (checked between repetitions)
+    until: Option<F>,
 }
 
 impl RepeatSegment {
@@ -614,12 +2118,39 @@ impl RepeatSegment {
             count: 0,
             current_seg: path.boxed_clone(),
             ref_seg: path.boxed_clone(),
+            until: None,
+        }
+    }
+}
+
+impl<F: Fn(&RobotCtx) -> bool> RepeatSegment<F> {
+    // stop before max_count once the predicate is true, e.g. when the
+    // ball sensor sees nothing so a match load loop ends with the supply
+    pub fn until<G: Fn(&RobotCtx) -> bool>(self, predicate: G) -> RepeatSegment<G> {
+        RepeatSegment {
+            max_count: self.max_count,
+            count: self.count,
+            ref_seg: self.ref_seg,
+            current_seg: self.current_seg,
+            until: Some(predicate),
         }
     }
 }
 
-impl PathSegment for RepeatSegment {
-    fn transform<'a>(self: Box<Self>, _: &Odometry) -> Vec<Box<dyn PathSegment + 'a>> {
+impl<F: Fn(&RobotCtx) -> bool> std::fmt::Debug for RepeatSegment<F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RepeatSegment")
+            .field("max_count", &self.max_count)
+            .field("count", &self.count)
+            .field("ref_seg", &self.ref_seg)
+            .field("current_seg", &self.current_seg)
+            .field("until", &self.until.is_some())
+            .finish()
+    }
+}
+
+impl<F: Fn(&RobotCtx) -> bool + Clone + 'static> PathSegment for RepeatSegment<F> {
+    fn transform<'a>(self: Box<Self>, _: &RobotCtx) -> Vec<Box<dyn PathSegment + 'a>> {
         unreachable!("transform should never get called since finished_transform is true")
     }
     fn finished_transform(&self) -> bool {
@@ -627,18 +2158,29 @@ impl PathSegment for RepeatSegment {
     }
     // This assures self.ref_seg is of type "Path" (to avoid handling
     // segment start and transform code)
-    fn start(&mut self, _: &Odometry, _: &mut Pid) {
+    fn start(&mut self, _: &mut RobotCtx, _: &mut Pid) {
         let a: Box<Path> = Box::new(self.ref_seg.boxed_clone().into());
         self.ref_seg = a;
         self.current_seg = self.ref_seg.boxed_clone();
     }
-    fn follow(&mut self, odom: &Odometry, angle_pid: &mut Pid) -> [f64; 2] {
-        self.current_seg.follow(odom, angle_pid)
+    fn follow(&mut self, ctx: &mut RobotCtx, angle_pid: &mut Pid) -> [f64; 2] {
+        self.current_seg.follow(ctx, angle_pid)
     }
-    fn end_follow<'a>(&mut self, odom: &Odometry) -> Option<Vec<Box<dyn PathSegment + 'a>>> {
-        let ret = self.current_seg.end_follow(odom)?;
+    fn end_follow<'a>(
+        &mut self,
+        ctx: &mut RobotCtx,
+        angle_pid: &mut Pid,
+    ) -> Option<Vec<Box<dyn PathSegment + 'a>>> {
+        let ret = self.current_seg.end_follow(ctx, angle_pid)?;
 
         if ret.is_empty() && self.count != self.max_count {
+            if self.until.as_ref().is_some_and(|until| until(ctx)) {
+                log::info!(
+                    "RepeatSegment stopped early after {} repeats.",
+                    self.count + 1
+                );
+                return Some(ret);
+            }
             self.count += 1;
             self.current_seg = self.ref_seg.boxed_clone();
             return None;
@@ -652,6 +2194,7 @@ impl PathSegment for RepeatSegment {
             count: 0,
             current_seg: self.ref_seg.boxed_clone(),
             ref_seg: self.ref_seg.boxed_clone(),
+            until: self.until.clone(),
         })
     }
 }
@@ -664,36 +2207,222 @@ pub struct WhileSegment {
 }
 
 impl PathSegment for WhileSegment {
-    fn transform<'a>(self: Box<Self>, _: &Odometry) -> Vec<Box<dyn PathSegment + 'a>> {
+    fn transform<'a>(self: Box<Self>, _: &RobotCtx) -> Vec<Box<dyn PathSegment + 'a>> {
         unreachable!("transform should never get called since finished_transform is true")
     }
     fn finished_transform(&self) -> bool {
         true
     }
-    fn start(&mut self, _: &Odometry, _: &mut Pid) {}
-    fn follow(&mut self, odom: &Odometry, angle_pid: &mut Pid) -> [f64; 2] {
-        let _ = self.secondary.follow(odom, angle_pid);
-        self.main.follow(odom, angle_pid)
+    fn start(&mut self, _: &mut RobotCtx, _: &mut Pid) {}
+    fn follow(&mut self, ctx: &mut RobotCtx, angle_pid: &mut Pid) -> [f64; 2] {
+        let _ = self.secondary.follow(ctx, angle_pid);
+        self.main.follow(ctx, angle_pid)
     }
-    fn end_follow<'a>(&mut self, odom: &Odometry) -> Option<Vec<Box<dyn PathSegment + 'a>>> {
+    fn end_follow<'a>(
+        &mut self,
+        ctx: &mut RobotCtx,
+        angle_pid: &mut Pid,
+    ) -> Option<Vec<Box<dyn PathSegment + 'a>>> {
         if !self.secondary_ended && self.secondary.ended() {
             self.secondary_ended = true;
-            self.secondary.abrupt_end(odom);
+            self.secondary.abrupt_end(ctx, angle_pid);
         }
         if self.main.ended() {
             return Some(Vec::new());
         }
         None
     }
-    fn abrupt_end(&mut self, odom: &Odometry) {
-        self.main.abrupt_end(odom);
-        self.secondary.abrupt_end(odom);
+    fn abrupt_end(&mut self, ctx: &mut RobotCtx, angle_pid: &mut Pid) {
+        self.main.abrupt_end(ctx, angle_pid);
+        self.secondary.abrupt_end(ctx, angle_pid);
     }
     fn boxed_clone<'a>(&self) -> Box<dyn PathSegment + 'a> {
         todo!()
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Completion {
+    // finish once every child has finished
+    All,
+    // finish as soon as a single child has finished
+    // (the remaining children are ended abruptly)
+    Any,
+}
+
+// Runs each child path at the same time. Unlike WhileSegment
+// the drive output of every child is kept: the outputs are summed
+// and clamped so children that don't drive (PowerMotors, Nop, etc.)
+// can be freely mixed with a single driving child. Each child has its
+// own copy of the angle pid so children don't fight over its target.
+// With no children the segment finishes straight away.
+#[derive(Debug)]
+pub struct ParallelSegment {
+    children: Vec<Path>,
+    ended: Vec<bool>,
+    pids: Vec<Pid>,
+    completion: Completion,
+}
+
+impl ParallelSegment {
+    pub fn new(children: Vec<Box<dyn PathSegment>>, completion: Completion) -> Self {
+        Self {
+            ended: vec![false; children.len()],
+            children: children.into_iter().map(Path::from).collect(),
+            pids: Vec::new(),
+            completion,
+        }
+    }
+    fn finished(&self) -> bool {
+        match self.completion {
+            Completion::All => self.ended.iter().all(|v| *v),
+            // an empty Any has no child to wait on
+            Completion::Any => self.ended.is_empty() || self.ended.iter().any(|v| *v),
+        }
+    }
+}
+
+impl PathSegment for ParallelSegment {
+    fn transform<'a>(self: Box<Self>, _: &RobotCtx) -> Vec<Box<dyn PathSegment + 'a>> {
+        unreachable!("transform should never get called since finished_transform is true")
+    }
+    fn finished_transform(&self) -> bool {
+        true
+    }
+    fn start(&mut self, _: &mut RobotCtx, angle_pid: &mut Pid) {
+        self.pids = self
+            .children
+            .iter()
+            .map(|_| {
+                let mut pid = angle_pid.clone();
+                pid.reset();
+                pid
+            })
+            .collect();
+    }
+    fn follow(&mut self, ctx: &mut RobotCtx, _: &mut Pid) -> [f64; 2] {
+        let mut out = [0.0; 2];
+        for ((child, ended), pid) in self
+            .children
+            .iter_mut()
+            .zip(&self.ended)
+            .zip(&mut self.pids)
+        {
+            if *ended {
+                continue;
+            }
+            let [l, r] = child.follow(ctx, pid);
+            out = [out[0] + l, out[1] + r];
+        }
+        [out[0].clamp(-1.0, 1.0), out[1].clamp(-1.0, 1.0)]
+    }
+    fn end_follow<'a>(
+        &mut self,
+        ctx: &mut RobotCtx,
+        angle_pid: &mut Pid,
+    ) -> Option<Vec<Box<dyn PathSegment + 'a>>> {
+        for (child, ended) in self.children.iter_mut().zip(self.ended.iter_mut()) {
+            if !*ended && child.ended() {
+                *ended = true;
+            }
+        }
+        if self.finished() {
+            self.abrupt_end(ctx, angle_pid);
+            return Some(Vec::new());
+        }
+        None
+    }
+    fn abrupt_end(&mut self, ctx: &mut RobotCtx, angle_pid: &mut Pid) {
+        for (i, (child, ended)) in self.children.iter_mut().zip(&mut self.ended).enumerate() {
+            if !*ended {
+                *ended = true;
+                // the pids are only made when the segment starts
+                let pid = match self.pids.get_mut(i) {
+                    Some(pid) => pid,
+                    None => &mut *angle_pid,
+                };
+                child.abrupt_end(ctx, pid);
+            }
+        }
+    }
+    fn boxed_clone<'a>(&self) -> Box<dyn PathSegment + 'a> {
+        Box::new(Self {
+            ended: vec![false; self.children.len()],
+            children: self.children.iter().map(Path::clone_path).collect(),
+            pids: Vec::new(),
+            completion: self.completion,
+        })
+    }
+}
+
+// Picks one of two segments when it is reached (not when the path is
+// created) based on an arbitrary condition on the robot state.
+pub struct CondSegment<F: Fn(&RobotCtx) -> bool> {
+    cond: F,
+    on_true: Box<dyn PathSegment>,
+    on_false: Box<dyn PathSegment>,
+}
+
+impl<F: Fn(&RobotCtx) -> bool> CondSegment<F> {
+    pub fn new(cond: F, on_true: Box<dyn PathSegment>, on_false: Box<dyn PathSegment>) -> Self {
+        Self {
+            cond,
+            on_true,
+            on_false,
+        }
+    }
+}
+
+impl<F: Fn(&RobotCtx) -> bool> std::fmt::Debug for CondSegment<F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CondSegment")
+            .field("on_true", &self.on_true)
+            .field("on_false", &self.on_false)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<F: Fn(&RobotCtx) -> bool + Clone + 'static> PathSegment for CondSegment<F> {
+    fn transform<'a>(self: Box<Self>, ctx: &RobotCtx) -> Vec<Box<dyn PathSegment + 'a>> {
+        if (self.cond)(ctx) {
+            log::info!(
+                "CondSegment condition was true, following {:?}",
+                self.on_true
+            );
+            vec![self.on_true]
+        } else {
+            log::info!(
+                "CondSegment condition was false, following {:?}",
+                self.on_false
+            );
+            vec![self.on_false]
+        }
+    }
+    fn finished_transform(&self) -> bool {
+        false
+    }
+    fn start(&mut self, _: &mut RobotCtx, _: &mut Pid) {
+        unreachable!("segment should be always be transformed")
+    }
+    fn follow(&mut self, _: &mut RobotCtx, _: &mut Pid) -> [f64; 2] {
+        unreachable!("segment should be always be transformed")
+    }
+    fn end_follow<'a>(
+        &mut self,
+        _: &mut RobotCtx,
+        _: &mut Pid,
+    ) -> Option<Vec<Box<dyn PathSegment + 'a>>> {
+        unreachable!("segment should be always be transformed")
+    }
+    fn boxed_clone<'a>(&self) -> Box<dyn PathSegment + 'a> {
+        Box::new(Self {
+            cond: self.cond.clone(),
+            on_true: self.on_true.boxed_clone(),
+            on_false: self.on_false.boxed_clone(),
+        })
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ChangeTriports {
     triports: Vec<Triport>,
@@ -706,38 +2435,108 @@ impl ChangeTriports {
     }
 }
 
+// what the limit of a SpeedLimiter ramps over (see SpeedLimiter::ramp)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LimitRamp {
+    // the time since the sub-path started
+    Time(Duration),
+    // the distance in meters driven since the sub-path started
+    Distance(f64),
+}
+
 #[derive(Debug)]
 pub struct SpeedLimiter {
     main: Path,
     limit: f64,
+    // the limit at the start of the sub-path and what it ramps over
+    ramp: Option<(f64, LimitRamp)>,
+    started: Option<Instant>,
+    // distance driven and the position it was last updated at
+    driven: f64,
+    last_pos: Option<Vec2>,
 }
 
 impl SpeedLimiter {
     pub fn new(main: Path, limit: f64) -> Self {
-        Self { main, limit }
+        Self {
+            main,
+            limit,
+            ramp: None,
+            started: None,
+            driven: 0.0,
+            last_pos: None,
+        }
+    }
+    // ramps the limit linearly from `from` up to the limit, e.g. for a
+    // gentle start against a field element
+    // `SpeedLimiter::new(path, 1.0).ramp(0.3, LimitRamp::Distance(0.5))`
+    pub fn ramp(mut self, from: f64, over: LimitRamp) -> Self {
+        self.ramp = Some((from, over));
+        self
+    }
+    fn current_limit(&self) -> f64 {
+        let Some((from, over)) = self.ramp else {
+            return self.limit;
+        };
+        let progress = match over {
+            LimitRamp::Time(dur) => {
+                let elapsed = self.started.map_or(0.0, |t| t.elapsed().as_secs_f64());
+                elapsed / dur.as_secs_f64()
+            }
+            LimitRamp::Distance(dist) => self.driven / dist,
+        };
+        // a zero length ramp gives NaN or infinity which means the ramp is over
+        let progress = if progress.is_nan() {
+            1.0
+        } else {
+            progress.clamp(0.0, 1.0)
+        };
+        from + (self.limit - from) * progress
     }
 }
 
 impl PathSegment for SpeedLimiter {
-    fn transform<'a>(self: Box<Self>, _: &Odometry) -> Vec<Box<dyn PathSegment + 'a>> {
+    fn transform<'a>(self: Box<Self>, _: &RobotCtx) -> Vec<Box<dyn PathSegment + 'a>> {
         unreachable!("transform should never get called since finished_transform is true")
     }
     fn finished_transform(&self) -> bool {
         true
     }
-    fn start(&mut self, _: &Odometry, _: &mut Pid) {}
-    fn follow(&mut self, odom: &Odometry, angle_pid: &mut Pid) -> [f64; 2] {
-        let fol = self.main.follow(odom, angle_pid);
-        [self.limit.min(fol[0]), self.limit.min(fol[1])]
+    fn start(&mut self, ctx: &mut RobotCtx, _: &mut Pid) {
+        // the ramp only starts once so resuming doesn't restart it
+        if self.started.is_none() {
+            self.started = Some(Instant::now());
+            self.last_pos = Some(ctx.odom.position().into());
+        }
+    }
+    fn follow(&mut self, ctx: &mut RobotCtx, angle_pid: &mut Pid) -> [f64; 2] {
+        let pos: Vec2 = ctx.odom.position().into();
+        if let Some(last) = self.last_pos.replace(pos) {
+            self.driven += (pos - last).mag();
+        }
+        let fol = self.main.follow(ctx, angle_pid);
+        let limit = self.current_limit();
+        [limit.min(fol[0]), limit.min(fol[1])]
     }
-    fn end_follow<'a>(&mut self, odom: &Odometry) -> Option<Vec<Box<dyn PathSegment + 'a>>> {
-        self.main.end_follow(odom)
+    fn end_follow<'a>(
+        &mut self,
+        ctx: &mut RobotCtx,
+        angle_pid: &mut Pid,
+    ) -> Option<Vec<Box<dyn PathSegment + 'a>>> {
+        self.main.end_follow(ctx, angle_pid)
     }
-    fn abrupt_end(&mut self, odom: &Odometry) {
-        self.main.abrupt_end(odom);
+    fn abrupt_end(&mut self, ctx: &mut RobotCtx, angle_pid: &mut Pid) {
+        self.main.abrupt_end(ctx, angle_pid);
     }
     fn boxed_clone<'a>(&self) -> Box<dyn PathSegment + 'a> {
-        todo!()
+        Box::new(SpeedLimiter {
+            main: self.main.clone_path(),
+            limit: self.limit,
+            ramp: self.ramp,
+            started: None,
+            driven: 0.0,
+            last_pos: None,
+        })
     }
 }
 
@@ -754,22 +2553,26 @@ impl SpeedMultiplier {
 }
 
 impl PathSegment for SpeedMultiplier {
-    fn transform<'a>(self: Box<Self>, _: &Odometry) -> Vec<Box<dyn PathSegment + 'a>> {
+    fn transform<'a>(self: Box<Self>, _: &RobotCtx) -> Vec<Box<dyn PathSegment + 'a>> {
         unreachable!("transform should never get called since finished_transform is true")
     }
     fn finished_transform(&self) -> bool {
         true
     }
-    fn start(&mut self, _: &Odometry, _: &mut Pid) {}
-    fn follow(&mut self, odom: &Odometry, angle_pid: &mut Pid) -> [f64; 2] {
-        let fol = self.main.follow(odom, angle_pid);
+    fn start(&mut self, _: &mut RobotCtx, _: &mut Pid) {}
+    fn follow(&mut self, ctx: &mut RobotCtx, angle_pid: &mut Pid) -> [f64; 2] {
+        let fol = self.main.follow(ctx, angle_pid);
         [self.mul * fol[0], self.mul * fol[1]]
     }
-    fn end_follow<'a>(&mut self, odom: &Odometry) -> Option<Vec<Box<dyn PathSegment + 'a>>> {
-        self.main.end_follow(odom)
+    fn end_follow<'a>(
+        &mut self,
+        ctx: &mut RobotCtx,
+        angle_pid: &mut Pid,
+    ) -> Option<Vec<Box<dyn PathSegment + 'a>>> {
+        self.main.end_follow(ctx, angle_pid)
     }
-    fn abrupt_end(&mut self, odom: &Odometry) {
-        self.main.abrupt_end(odom);
+    fn abrupt_end(&mut self, ctx: &mut RobotCtx, angle_pid: &mut Pid) {
+        self.main.abrupt_end(ctx, angle_pid);
     }
     fn boxed_clone<'a>(&self) -> Box<dyn PathSegment + 'a> {
         todo!()
@@ -777,23 +2580,292 @@ impl PathSegment for SpeedMultiplier {
 }
 
 impl PathSegment for ChangeTriports {
-    fn transform<'a>(self: Box<Self>, _: &Odometry) -> Vec<Box<dyn PathSegment + 'a>> {
+    fn transform<'a>(self: Box<Self>, _: &RobotCtx) -> Vec<Box<dyn PathSegment + 'a>> {
         unreachable!("transform should never get called since finished_transform is true")
     }
     fn finished_transform(&self) -> bool {
         true
     }
-    fn start(&mut self, _: &Odometry, _: &mut Pid) {
+    fn start(&mut self, _: &mut RobotCtx, _: &mut Pid) {
         for triport in &self.triports {
             triport.change(self.change);
         }
     }
-    fn follow(&mut self, _: &Odometry, _: &mut Pid) -> [f64; 2] {
+    fn follow(&mut self, _: &mut RobotCtx, _: &mut Pid) -> [f64; 2] {
+        unreachable!(
+            "follow should never get called on since end_follow always returns Some(Vec::new())"
+        )
+    }
+    fn end_follow<'a>(
+        &mut self,
+        _: &mut RobotCtx,
+        _: &mut Pid,
+    ) -> Option<Vec<Box<dyn PathSegment + 'a>>> {
+        Some(Vec::new())
+    }
+    fn boxed_clone<'a>(&self) -> Box<dyn PathSegment + 'a> {
+        Box::new(self.clone())
+    }
+}
+
+// Sets or shifts the pose of the odometry when reached, for re-zeroing
+// mid route. Coordinates left as None are not changed.
+#[derive(Debug, Clone, Copy)]
+pub enum SetPose {
+    Set {
+        position: Option<[f64; 2]>,
+        heading: Option<f64>,
+    },
+    Offset {
+        position: [f64; 2],
+        heading: f64,
+    },
+}
+
+impl PathSegment for SetPose {
+    fn transform<'a>(self: Box<Self>, _: &RobotCtx) -> Vec<Box<dyn PathSegment + 'a>> {
+        unreachable!("transform should never get called since finished_transform is true")
+    }
+    fn finished_transform(&self) -> bool {
+        true
+    }
+    fn start(&mut self, ctx: &mut RobotCtx, _: &mut Pid) {
+        match *self {
+            SetPose::Set { position, heading } => {
+                if let Some(position) = position {
+                    ctx.odom.set_position(position);
+                }
+                if let Some(heading) = heading {
+                    ctx.odom.set_heading(heading);
+                }
+            }
+            SetPose::Offset { position, heading } => ctx.odom.offset_pose(position, heading),
+        }
+        log::info!(
+            "SetPose set pose to {:?} @ {}.",
+            ctx.odom.position(),
+            ctx.odom.heading()
+        );
+    }
+    fn follow(&mut self, _: &mut RobotCtx, _: &mut Pid) -> [f64; 2] {
         unreachable!(
             "follow should never get called on since end_follow always returns Some(Vec::new())"
         )
     }
-    fn end_follow<'a>(&mut self, _: &Odometry) -> Option<Vec<Box<dyn PathSegment + 'a>>> {
+    fn end_follow<'a>(
+        &mut self,
+        _: &mut RobotCtx,
+        _: &mut Pid,
+    ) -> Option<Vec<Box<dyn PathSegment + 'a>>> {
+        Some(Vec::new())
+    }
+    fn boxed_clone<'a>(&self) -> Box<dyn PathSegment + 'a> {
+        Box::new(*self)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Axis {
+    X,
+    Y,
+}
+
+// where the robot is when it is square against a wall
+#[derive(Debug, Clone, Copy)]
+pub struct Wall {
+    // heading of the robot when square against the wall
+    pub heading: f64,
+    // the position axis the wall constrains and the value of
+    // that coordinate for the robot when against the wall
+    pub axis: Option<(Axis, f64)>,
+}
+
+impl Wall {
+    // the pose the odometry would have against the wall
+    fn pose(&self, odom: &Odometry) -> ([f64; 2], f64) {
+        // keep the number of full turns the odometry has accumulated
+        let heading = optimise_target_heading(odom.heading(), self.heading);
+        let mut pos = odom.position();
+        if let Some((axis, coord)) = self.axis {
+            match axis {
+                Axis::X => pos[0] = coord,
+                Axis::Y => pos[1] = coord,
+            }
+        }
+        (pos, heading)
+    }
+    fn reseed(&self, odom: &mut Odometry) {
+        let (pos, heading) = self.pose(odom);
+        odom.set_pose(pos, heading);
+    }
+}
+
+// largest correction WallReset will make, anything larger means
+// the robot likely isn't against the wall
+const MAX_WALL_POSITION_CORRECTION: f64 = 0.3;
+const MAX_WALL_HEADING_CORRECTION: f64 = 20.0 * PI / 180.0;
+
+// Snaps the pose to a known wall once the wrapped segment ends, for a
+// segment that leaves the robot against the wall (e.g. a Ram into it).
+// Corrections larger then the limits above are skipped as the wall was
+// likely missed. See SquareUp for detecting the contact from current.
+#[derive(Debug)]
+pub struct WallReset {
+    seg: Box<dyn PathSegment>,
+    wall: Wall,
+}
+
+impl WallReset {
+    pub fn new(seg: Box<dyn PathSegment>, wall: Wall) -> Self {
+        Self { seg, wall }
+    }
+    // only the last of the segments to run (the first in the stack
+    // order) needs to reset the pose
+    fn wrap<'a>(mut segs: Vec<Box<dyn PathSegment>>, wall: Wall) -> Vec<Box<dyn PathSegment + 'a>> {
+        if !segs.is_empty() {
+            let seg = segs.remove(0);
+            segs.insert(0, Box::new(WallReset { seg, wall }));
+        }
+        segs.into_iter()
+            .map(|seg| -> Box<dyn PathSegment + 'a> { seg })
+            .collect()
+    }
+}
+
+impl PathSegment for WallReset {
+    fn transform<'a>(self: Box<Self>, ctx: &RobotCtx) -> Vec<Box<dyn PathSegment + 'a>> {
+        Self::wrap(self.seg.transform(ctx), self.wall)
+    }
+    fn finished_transform(&self) -> bool {
+        self.seg.finished_transform()
+    }
+    fn start(&mut self, ctx: &mut RobotCtx, angle_pid: &mut Pid) {
+        self.seg.start(ctx, angle_pid);
+    }
+    fn follow(&mut self, ctx: &mut RobotCtx, angle_pid: &mut Pid) -> [f64; 2] {
+        self.seg.follow(ctx, angle_pid)
+    }
+    fn end_follow<'a>(
+        &mut self,
+        ctx: &mut RobotCtx,
+        angle_pid: &mut Pid,
+    ) -> Option<Vec<Box<dyn PathSegment + 'a>>> {
+        let segs = self.seg.end_follow(ctx, angle_pid)?;
+        if !segs.is_empty() {
+            return Some(Self::wrap(segs, self.wall));
+        }
+
+        let (pos, heading) = self.wall.pose(ctx.odom);
+        let odom_pos: Vec2 = ctx.odom.position().into();
+        let pos_error = (odom_pos - pos.into()).mag();
+        let heading_error = (heading - ctx.odom.heading()).abs();
+        if pos_error > MAX_WALL_POSITION_CORRECTION || heading_error > MAX_WALL_HEADING_CORRECTION {
+            log::warn!("WallReset skipped, correction of {pos_error}m and {heading_error}rad is too large.");
+        } else {
+            ctx.odom.set_pose(pos, heading);
+            log::info!("WallReset reseeded pose to {pos:?} @ {heading}.");
+        }
+        Some(segs)
+    }
+    fn abrupt_end(&mut self, ctx: &mut RobotCtx, angle_pid: &mut Pid) {
+        self.seg.abrupt_end(ctx, angle_pid);
+    }
+    fn set_settle(&mut self, turn: Option<SettleCondition>, linear: Option<SettleCondition>) {
+        self.seg.set_settle(turn, linear);
+    }
+    fn set_gains(&mut self, turn: Option<PidGains>, linear: Option<PidGains>) {
+        self.seg.set_gains(turn, linear);
+    }
+    fn set_stall_action(&mut self, action: StallAction) {
+        self.seg.set_stall_action(action);
+    }
+    fn exit_velocity(&self) -> f64 {
+        self.seg.exit_velocity()
+    }
+    fn set_entry_velocity(&mut self, velocity: f64) {
+        self.seg.set_entry_velocity(velocity);
+    }
+    fn boxed_clone<'a>(&self) -> Box<dyn PathSegment + 'a> {
+        Box::new(WallReset {
+            seg: self.seg.boxed_clone(),
+            wall: self.wall,
+        })
+    }
+}
+
+// current (mA) each side must exceed to count as pushing against the wall
+const SQUARE_UP_CURRENT: i16 = 1200;
+// how long both sides need to be pushing before the robot is considered square
+const SQUARE_UP_TIME: Duration = Duration::from_millis(150);
+
+// Drives gently into a known wall until both sides of the drivetrain
+// are pushing against it (measured by motor current) and then reseeds
+// the heading and optionally one axis of the position from the wall.
+// The segment has no timeout so it should be wrapped in a TimedSegment
+// if there is any chance of missing the wall. When it times out
+// odometry is left untouched.
+#[derive(Debug, Clone)]
+pub struct SquareUp {
+    pow: f64,
+    motors: [Vec<Motor>; 2],
+    wall: Wall,
+    pushing_since: Option<Instant>,
+}
+
+impl SquareUp {
+    // pow is the signed drive power (negative to back into the wall)
+    // and motors are the left and right drive motors (Tankdrive::motors)
+    pub fn new(motors: [Vec<Motor>; 2], pow: f64, wall: Wall) -> Self {
+        Self {
+            pow,
+            motors,
+            wall,
+            pushing_since: None,
+        }
+    }
+    fn side_pushing(motors: &[Motor]) -> bool {
+        let currents: Vec<_> = motors.iter().filter_map(Motor::current).collect();
+        if currents.is_empty() {
+            return false;
+        }
+        let avg = currents.iter().map(|&v| v as f64).sum::<f64>() / currents.len() as f64;
+        avg.abs() > SQUARE_UP_CURRENT as f64
+    }
+}
+
+impl PathSegment for SquareUp {
+    fn transform<'a>(self: Box<Self>, _: &RobotCtx) -> Vec<Box<dyn PathSegment + 'a>> {
+        unreachable!("transform should never get called since finished_transform is true")
+    }
+    fn finished_transform(&self) -> bool {
+        true
+    }
+    fn start(&mut self, _: &mut RobotCtx, _: &mut Pid) {
+        self.pushing_since = None;
+    }
+    fn follow(&mut self, _: &mut RobotCtx, _: &mut Pid) -> [f64; 2] {
+        [self.pow; 2]
+    }
+    fn end_follow<'a>(
+        &mut self,
+        ctx: &mut RobotCtx,
+        _: &mut Pid,
+    ) -> Option<Vec<Box<dyn PathSegment + 'a>>> {
+        if !self.motors.iter().all(|side| Self::side_pushing(side)) {
+            self.pushing_since = None;
+            return None;
+        }
+        let since = *self.pushing_since.get_or_insert_with(Instant::now);
+        if since.elapsed() < SQUARE_UP_TIME {
+            return None;
+        }
+
+        self.wall.reseed(ctx.odom);
+        log::info!(
+            "Finished segment - SquareUp reseeded pose to {:?} @ {}.",
+            ctx.odom.position(),
+            ctx.odom.heading()
+        );
         Some(Vec::new())
     }
     fn boxed_clone<'a>(&self) -> Box<dyn PathSegment + 'a> {
@@ -801,6 +2873,19 @@ impl PathSegment for ChangeTriports {
     }
 }
 
+// like optimise_target_heading but turning in the given direction
+// (counter clockwise is a positive change in heading)
+fn directed_target_heading(heading: f64, target: f64, direction: TurnDirection) -> f64 {
+    // delta in [0, TAU)
+    let delta = (target - heading).rem_euclid(TAU);
+    match direction {
+        TurnDirection::Shortest => optimise_target_heading(heading, target),
+        TurnDirection::CounterClockwise => heading + delta,
+        TurnDirection::Clockwise if delta == 0.0 => heading,
+        TurnDirection::Clockwise => heading + delta - TAU,
+    }
+}
+
 fn optimise_target_heading(heading: f64, target: f64) -> f64 {
     let mut delta = target - heading;
     // map delta into [-TAU, TAU]
@@ -815,3 +2900,269 @@ fn optimise_target_heading(heading: f64, target: f64) -> f64 {
     }
     heading + delta
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, close};
+
+    // headings a few turns either side of zero, as after a long auton
+    const SWEEP_RANGE: f64 = 4.0 * TAU;
+    const SWEEPS: usize = 10_000;
+
+    #[test]
+    fn optimise_target_heading_is_the_nearest_equivalent_heading() {
+        let mut sweep = testing::Sweep::new(3286);
+        for _ in 0..SWEEPS {
+            let heading = sweep.range(-SWEEP_RANGE, SWEEP_RANGE);
+            let target = sweep.range(-SWEEP_RANGE, SWEEP_RANGE);
+            let got = optimise_target_heading(heading, target);
+            assert!(
+                (got - heading).abs() <= PI + 1e-9,
+                "turning from {heading} to {target} goes the long way to {got}"
+            );
+            assert!(
+                testing::same_direction(got, target),
+                "turning from {heading} to {target} ends facing {got}"
+            );
+        }
+    }
+
+    #[test]
+    fn directed_target_heading_turns_less_then_a_turn_the_given_way() {
+        let mut sweep = testing::Sweep::new(3286);
+        for _ in 0..SWEEPS {
+            let heading = sweep.range(-SWEEP_RANGE, SWEEP_RANGE);
+            let target = sweep.range(-SWEEP_RANGE, SWEEP_RANGE);
+            let shortest = directed_target_heading(heading, target, TurnDirection::Shortest);
+            assert_eq!(shortest, optimise_target_heading(heading, target));
+
+            let ccw = directed_target_heading(heading, target, TurnDirection::CounterClockwise);
+            let cw = directed_target_heading(heading, target, TurnDirection::Clockwise);
+            assert!(
+                (0.0..TAU).contains(&(ccw - heading)),
+                "counter clockwise from {heading} to {target} turns {}",
+                ccw - heading
+            );
+            assert!(
+                (0.0..TAU).contains(&(heading - cw)),
+                "clockwise from {heading} to {target} turns {}",
+                cw - heading
+            );
+            assert!(testing::same_direction(ccw, target));
+            assert!(testing::same_direction(cw, target));
+        }
+        // already facing the target doesn't turn a full circle either way
+        for direction in [TurnDirection::CounterClockwise, TurnDirection::Clockwise] {
+            assert_eq!(directed_target_heading(TAU, 0.0, direction), TAU);
+        }
+    }
+
+    #[test]
+    fn turn_before_move_is_only_skipped_when_chained() {
+        let small = CHAIN_HEADING_TOLERANCE / 2.0;
+        // (before a move, entry velocity, heading, skipped)
+        let cases = [
+            (true, 0.5, small, true),
+            (true, 0.0, small, false),
+            (false, 0.5, small, false),
+            (true, 0.5, 2.0 * CHAIN_HEADING_TOLERANCE, false),
+        ];
+        for (before_move, entry, heading, expected) in cases {
+            let mut turn = TurnTo::new(0.0, 0.0);
+            turn.before_move = before_move;
+            turn.set_entry_velocity(entry);
+            assert_eq!(
+                turn.skip_for_chain(heading),
+                expected,
+                "before_move {before_move}, entry {entry}, heading {heading}"
+            );
+        }
+    }
+
+    #[test]
+    fn nested_settle_conditions_use_the_innermost() {
+        let mut odom = testing::still_odometry();
+        let brain = testing::idle_brain();
+        let mut ctx = testing::ctx(&mut odom, &brain);
+        let mut angle_pid = Pid::new(1.0, 0.0, 0.0);
+        // the pinned robot stays 0.1 rad off the target
+        let tight = SettleCondition::new(0.01, 1.0);
+        let loose = SettleCondition::new(0.5, 1.0);
+        let turn = WithSettle::new(Box::new(MinSegment::TurnTo(0.1))).turn(tight);
+        let mut path = Path::new(vec![Box::new(WithSettle::new(Box::new(turn)).turn(loose))]);
+        for _ in 0..5 {
+            path.follow(&mut ctx, &mut angle_pid);
+        }
+        assert!(!path.ended());
+
+        let turn = WithSettle::new(Box::new(MinSegment::TurnTo(0.1)));
+        let mut path = Path::new(vec![Box::new(WithSettle::new(Box::new(turn)).turn(loose))]);
+        path.follow(&mut ctx, &mut angle_pid);
+        path.follow(&mut ctx, &mut angle_pid);
+        assert!(path.ended());
+    }
+
+    #[test]
+    fn nested_gains_use_the_innermost() {
+        let mut odom = testing::still_odometry();
+        let brain = testing::idle_brain();
+        let mut ctx = testing::ctx(&mut odom, &brain);
+        ctx.constraints.turn_vel = 0.0;
+        let mut angle_pid = Pid::new(1.0, 0.0, 0.0);
+        let turn =
+            WithGains::new(Box::new(MinSegment::TurnTo(0.1))).turn(PidGains::new(0.5, 0.0, 0.0));
+        let mut path = Path::new(vec![Box::new(
+            WithGains::new(Box::new(turn)).turn(PidGains::new(2.0, 0.0, 0.0)),
+        )]);
+        let [_, right] = path.follow(&mut ctx, &mut angle_pid);
+        assert!(close(right, 0.05));
+        assert_eq!(angle_pid.gains().kp, 0.5);
+    }
+
+    #[test]
+    fn back_off_runs_out_of_retries() {
+        let mut odom = testing::still_odometry();
+        let brain = testing::idle_brain();
+        let mut ctx = testing::ctx(&mut odom, &brain);
+        let mut angle_pid = Pid::new(0.0, 0.0, 0.0);
+        // backs off for longer then it takes to stall so a back off that
+        // had stall detection of its own would reverse again
+        let back_off = STALL_TIME * 2;
+        let ram = Ram::new(0.5, Duration::from_secs(30));
+        let mut path = Path::new(vec![Box::new(OnStall::new(
+            Box::new(ram),
+            StallAction::BackOff {
+                pow: 0.3,
+                dur: back_off,
+                retries: 1,
+            },
+        ))]);
+
+        // the pinned robot stalls, backs off once, stalls on the retry
+        // and then gives up rather then ramming for the whole 30s
+        let start = Instant::now();
+        let mut outputs = Vec::new();
+        while !path.ended() && start.elapsed() < Duration::from_secs(10) {
+            let [l, _] = path.follow(&mut ctx, &mut angle_pid);
+            if outputs.last() != Some(&l) {
+                outputs.push(l);
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert!(path.ended(), "still ramming after {:?}", start.elapsed());
+        assert_eq!(outputs, vec![0.5, -0.3, 0.5, 0.0]);
+        assert!(start.elapsed() >= STALL_TIME * 2 + back_off);
+    }
+
+    #[test]
+    fn speed_limit_ramps_over_distance() {
+        let mut odom = testing::still_odometry();
+        let brain = testing::idle_brain();
+        let mut ctx = testing::ctx(&mut odom, &brain);
+        let mut angle_pid = Pid::new(0.0, 0.0, 0.0);
+        let full = Path::new(vec![Box::new(Ram::new(1.0, Duration::from_secs(30)))]);
+        let mut limiter = SpeedLimiter::new(full, 1.0).ramp(0.3, LimitRamp::Distance(0.5));
+        limiter.start(&mut ctx, &mut angle_pid);
+        // (position driven to, limit)
+        for (x, expected) in [(0.0, 0.3), (0.25, 0.65), (0.5, 1.0), (2.0, 1.0)] {
+            ctx.odom.set_position([x, 0.0]);
+            let [left, _] = limiter.follow(&mut ctx, &mut angle_pid);
+            assert!(close(limiter.current_limit(), expected));
+            assert!(close(left, expected));
+        }
+        // a clone starts the ramp again
+        let mut clone = Path::new(vec![limiter.boxed_clone()]);
+        let [left, _] = clone.follow(&mut ctx, &mut angle_pid);
+        assert!(close(left, 0.3));
+    }
+
+    // follows the path until it ends, returning the left outputs in
+    // the order they changed and how long it took
+    fn run(path: &mut Path, ctx: &mut RobotCtx, angle_pid: &mut Pid) -> (Vec<f64>, Duration) {
+        let start = Instant::now();
+        let mut outputs = Vec::new();
+        while !path.ended() && start.elapsed() < Duration::from_secs(10) {
+            let [left, _] = path.follow(ctx, angle_pid);
+            if outputs.last() != Some(&left) {
+                outputs.push(left);
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert!(path.ended(), "still following after {:?}", start.elapsed());
+        (outputs, start.elapsed())
+    }
+
+    #[test]
+    fn parallel_sums_children_until_complete() {
+        let mut odom = testing::still_odometry();
+        let brain = testing::idle_brain();
+        let mut ctx = testing::ctx(&mut odom, &brain);
+        let mut angle_pid = Pid::new(1.0, 0.0, 0.0);
+        let short = Duration::from_millis(100);
+        let children = || -> Vec<Box<dyn PathSegment>> {
+            vec![
+                Box::new(Ram::new(0.25, short)),
+                Box::new(Ram::new(0.5, 3 * short)),
+            ]
+        };
+
+        let any = ParallelSegment::new(children(), Completion::Any);
+        let (outputs, took) = run(
+            &mut Path::new(vec![Box::new(any)]),
+            &mut ctx,
+            &mut angle_pid,
+        );
+        // the tick the short child ends the long one is still followed
+        assert_eq!(outputs, vec![0.75, 0.5, 0.0]);
+        assert!(took < 3 * short, "took {took:?}");
+
+        let all = ParallelSegment::new(children(), Completion::All);
+        let (outputs, took) = run(
+            &mut Path::new(vec![Box::new(all)]),
+            &mut ctx,
+            &mut angle_pid,
+        );
+        assert_eq!(outputs, vec![0.75, 0.5, 0.0]);
+        assert!(took >= 3 * short, "took {took:?}");
+
+        // nothing to wait on
+        let empty = ParallelSegment::new(Vec::new(), Completion::Any);
+        let (outputs, _) = run(
+            &mut Path::new(vec![Box::new(empty)]),
+            &mut ctx,
+            &mut angle_pid,
+        );
+        assert_eq!(outputs, vec![0.0]);
+    }
+
+    #[test]
+    fn parallel_children_have_their_own_angle_pid() {
+        let mut odom = testing::still_odometry();
+        let brain = testing::idle_brain();
+        let mut ctx = testing::ctx(&mut odom, &brain);
+        // turns straight to the target rather then along a profile
+        ctx.constraints.turn_vel = 0.0;
+        let mut angle_pid = Pid::new(1.0, 0.0, 0.0);
+        angle_pid.set_target(0.25);
+
+        // turning both ways at once cancels out, with a shared pid the
+        // second turn's target would be used by both
+        let mut parallel = ParallelSegment::new(
+            vec![
+                Box::new(MinSegment::TurnTo(1.0)),
+                Box::new(MinSegment::TurnTo(-1.0)),
+            ],
+            Completion::All,
+        );
+        parallel.start(&mut ctx, &mut angle_pid);
+        assert_eq!(parallel.follow(&mut ctx, &mut angle_pid), [0.0, 0.0]);
+        assert_eq!(angle_pid.target(), 0.25);
+
+        // ending it before it started still ends every child
+        let mut parallel =
+            ParallelSegment::new(vec![Box::new(Nop {}), Box::new(Nop {})], Completion::All);
+        parallel.abrupt_end(&mut ctx, &mut angle_pid);
+        assert!(parallel.end_follow(&mut ctx, &mut angle_pid).is_some());
+    }
+}
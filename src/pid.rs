@@ -1,12 +1,112 @@
-use std::time::Instant;
+use std::{
+    f64::consts::{PI, TAU},
+    time::Instant,
+};
 
+use crate::{config::RobotConfig, filters::SlewLimiter};
+
+// the output is considered saturated outside of [-OUTPUT_LIMIT, OUTPUT_LIMIT]
+// as that is the range consumers clamp the output to
+const OUTPUT_LIMIT: f64 = 1.0;
+
+// bounds on the time between polls, anything shorter is clamped to avoid
+// a huge derivative and anything longer is treated as a gap in the loop
+// (the first poll, a hiccup or the controller not being polled for a while)
+const MIN_DT: f64 = 0.0005;
+const MAX_DT: f64 = 0.05;
+
+// strategies to stop the integral from winding up
+// see (wikipedia.org/wiki/Integral_windup)
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum AntiWindup {
+    // clegg integration, reset the integral when the error changes sign
+    #[default]
+    Clegg,
+    // bleed off the integral by kb times the amount the output is saturated,
+    // this keeps the integral for controllers with a steady state load (lifts)
+    BackCalculation {
+        kb: f64,
+    },
+    // only integrate when the output isn't saturated or when
+    // integrating would bring the output out of saturation
+    ConditionalIntegration,
+    // nothing beyond the integral limit
+    Clamp,
+}
+
+// what the D term is the derivative of
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Derivative {
+    #[default]
+    Error,
+    // the negated derivative of the process variable, the same as the
+    // error's except there is no kick when the target changes
+    Measurement,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PidGains {
+    pub kp: f64,
+    pub ki: f64,
+    pub kd: f64,
+}
+
+impl PidGains {
+    pub const fn new(kp: f64, ki: f64, kd: f64) -> Self {
+        Self { kp, ki, kd }
+    }
+    // the gains under `<prefix>.kp`, `<prefix>.ki` and `<prefix>.kd`,
+    // None unless all three are set
+    pub fn load(config: &RobotConfig, prefix: &str) -> Option<Self> {
+        let get = |k: &str| config.get(&format!("{prefix}.{k}"));
+        Some(Self::new(get("kp")?, get("ki")?, get("kd")?))
+    }
+    pub fn save(&self, config: &mut RobotConfig, prefix: &str) {
+        config.set(&format!("{prefix}.kp"), self.kp);
+        config.set(&format!("{prefix}.ki"), self.ki);
+        config.set(&format!("{prefix}.kd"), self.kd);
+        if let Err(e) = config.save() {
+            log::error!("Failed to save the gains under {prefix}: {e}");
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Pid {
     pub kp: f64,
     pub ki: f64,
     pub kd: f64,
+    // feedforward of the target, for targets that need a steady output
+    pub kf: f64,
+    // output added in the direction of the error to overcome static
+    // friction, not applied within ks_deadband of the target so the
+    // output doesn't chatter when settled
+    pub ks: f64,
+    pub ks_deadband: f64,
+    pub anti_windup: AntiWindup,
+    // the integral is cleared while |error| is outside the zone so it
+    // only builds up close to the target, None to always integrate
+    pub integral_zone: Option<f64>,
+    // max magnitude of the integral contribution to the output
+    pub integral_limit: f64,
+    // treat the target and pv as angles in radians, the error is wrapped
+    // to [-π, π) so the controller takes the short way round
+    pub angle_wrap: bool,
+    // limits how fast the output can change
+    pub slew: Option<SlewLimiter>,
+    pub derivative: Derivative,
+    // time constant (s) of the low-pass filter on the derivative,
+    // 0 for no filtering. Around a few loop periods tames sensor noise
+    pub derivative_filter: f64,
+    // when set the P, I, D and feedforward contributions, the error and
+    // the output are plotted (and logged at debug) under this name every poll
+    pub telemetry: Option<&'static str>,
     target: f64,
     ki_integral: f64,
     last_error: f64,
+    last_pv: f64,
+    // the filtered derivative before kd is applied
+    filtered_rate: f64,
     last_update: Instant,
     first_update: bool,
 }
@@ -17,43 +117,318 @@ impl Pid {
             kp,
             ki,
             kd,
+            kf: 0.0,
+            ks: 0.0,
+            ks_deadband: 0.0,
+            anti_windup: AntiWindup::default(),
+            integral_zone: None,
+            integral_limit: OUTPUT_LIMIT,
+            angle_wrap: false,
+            slew: None,
+            derivative: Derivative::default(),
+            derivative_filter: 0.0,
+            telemetry: None,
             target: 0.0,
             ki_integral: 0.0,
             last_error: 0.0,
+            last_pv: 0.0,
+            filtered_rate: 0.0,
             last_update: Instant::now(),
             first_update: true,
         }
     }
+    pub fn with_gains(gains: PidGains) -> Self {
+        Self::new(gains.kp, gains.ki, gains.kd)
+    }
+    pub fn gains(&self) -> PidGains {
+        PidGains::new(self.kp, self.ki, self.kd)
+    }
+    // the integral is kept as it is already scaled by ki
+    pub fn set_gains(&mut self, gains: PidGains) {
+        self.kp = gains.kp;
+        self.ki = gains.ki;
+        self.kd = gains.kd;
+    }
     pub fn set_target(&mut self, target: f64) {
         self.target = target;
     }
     pub fn poll(&mut self, pv: f64) -> f64 {
         let now = Instant::now();
         let diff_t = now.duration_since(self.last_update).as_secs_f64();
+        // during a gap neither the derivative or the integral can be
+        // trusted so the derivative is skipped and the integral frozen
+        let gap = self.first_update || diff_t > MAX_DT;
+        let diff_t = diff_t.max(MIN_DT);
 
-        let error = self.target - pv;
-        // clegg integration (avoid integral windup)
-        // see (wikipedia.org/wiki/Integral_windup)
-        if self.last_error.signum() != error.signum() {
-            self.ki_integral = 0.0;
-        }
+        let error = self.error(pv);
+        let p = self.kp * error;
+        let d = if gap {
+            self.filtered_rate = 0.0;
+            0.0
+        } else {
+            let rate = match self.derivative {
+                Derivative::Error => self.wrap(error - self.last_error),
+                Derivative::Measurement => -self.wrap(pv - self.last_pv),
+            } / diff_t;
+            // first order low-pass
+            let alpha = diff_t / (self.derivative_filter.max(0.0) + diff_t);
+            self.filtered_rate += alpha * (rate - self.filtered_rate);
+            self.kd * self.filtered_rate
+        };
+        let ff = self.kf * self.target
+            + if error.abs() > self.ks_deadband {
+                self.ks * error.signum()
+            } else {
+                0.0
+            };
+        // output if the integral were left unchanged
+        let unsaturated = p + self.ki_integral + d + ff;
 
         // bumpless operation see (wikipedia.org/wiki/Proportional-integral-derivative_controller#Bumpless_operation)
-        self.ki_integral += self.ki * error * diff_t;
-        self.ki_integral = self.ki_integral.clamp(-1.0, 1.0);
+        let in_zone = self.integral_zone.is_none_or(|zone| error.abs() < zone);
+        match self.anti_windup {
+            _ if !in_zone => self.ki_integral = 0.0,
+            _ if gap => {}
+            AntiWindup::Clegg => {
+                if self.last_error.signum() != error.signum() {
+                    self.ki_integral = 0.0;
+                }
+                self.ki_integral += self.ki * error * diff_t;
+            }
+            AntiWindup::BackCalculation { kb } => {
+                let saturated = unsaturated.clamp(-OUTPUT_LIMIT, OUTPUT_LIMIT);
+                self.ki_integral += (self.ki * error + kb * (saturated - unsaturated)) * diff_t;
+            }
+            AntiWindup::ConditionalIntegration => {
+                if unsaturated.abs() < OUTPUT_LIMIT || unsaturated.signum() != error.signum() {
+                    self.ki_integral += self.ki * error * diff_t;
+                }
+            }
+            AntiWindup::Clamp => self.ki_integral += self.ki * error * diff_t,
+        }
+        self.ki_integral = self
+            .ki_integral
+            .clamp(-self.integral_limit, self.integral_limit);
+
+        let mut output = p + self.ki_integral + d + ff;
+        if let Some(slew) = self.slew.as_mut() {
+            output = slew.limit(output);
+        }
 
-        let output = self.kp * error + self.ki_integral + self.kd * (error - self.last_error);
+        if let Some(name) = self.telemetry {
+            use communication::plot;
+            plot!(name, [p, self.ki_integral, d, ff, error, output]);
+            log::debug!(
+                "{name}: p {p} i {} d {d} ff {ff} error {error} output {output}",
+                self.ki_integral
+            );
+        }
 
         self.last_error = error;
+        self.last_pv = pv;
         self.last_update = now;
+        self.first_update = false;
 
         output
     }
+    pub fn target(&self) -> f64 {
+        self.target
+    }
+    // the error poll would see for pv
+    pub fn error(&self, pv: f64) -> f64 {
+        self.wrap(self.target - pv)
+    }
+    fn wrap(&self, angle: f64) -> f64 {
+        if self.angle_wrap {
+            (angle + PI).rem_euclid(TAU) - PI
+        } else {
+            angle
+        }
+    }
     pub fn reset(&mut self) {
         log::info!("reset called");
         self.first_update = true;
         self.ki_integral = 0.0;
         self.last_error = 0.0;
+        self.filtered_rate = 0.0;
         self.last_update = Instant::now();
+        if let Some(slew) = self.slew.as_mut() {
+            slew.reset(0.0);
+        }
+    }
+}
+
+// what a GainSchedule picks the gains with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScheduleBy {
+    // magnitude of the error, e.g. softer gains close to the target
+    Error,
+    // a value given with set_variable, e.g. 1.0 while carrying a game object
+    External,
+}
+
+// Switches a Pid between gains depending on a variable. The integral
+// is kept already scaled by ki so switching doesn't bump the output.
+#[derive(Debug, Clone)]
+pub struct GainSchedule {
+    pid: Pid,
+    by: ScheduleBy,
+    // sorted by the variable the gains start from
+    entries: Vec<(f64, PidGains)>,
+    variable: f64,
+    active: usize,
+}
+
+impl GainSchedule {
+    // the pid's own gains are used below every added entry
+    pub fn new(pid: Pid, by: ScheduleBy) -> Self {
+        Self {
+            entries: vec![(f64::NEG_INFINITY, pid.gains())],
+            pid,
+            by,
+            variable: 0.0,
+            active: 0,
+        }
+    }
+    // use gains once the variable reaches from
+    pub fn gains(mut self, from: f64, gains: PidGains) -> Self {
+        let i = self.entries.partition_point(|(v, _)| *v <= from);
+        self.entries.insert(i, (from, gains));
+        self
+    }
+    pub fn set_variable(&mut self, variable: f64) {
+        self.variable = variable;
+    }
+    pub fn set_target(&mut self, target: f64) {
+        self.pid.set_target(target);
+    }
+    pub fn poll(&mut self, pv: f64) -> f64 {
+        let variable = match self.by {
+            ScheduleBy::Error => self.pid.error(pv).abs(),
+            ScheduleBy::External => self.variable,
+        };
+        let active = self
+            .entries
+            .partition_point(|(v, _)| *v <= variable)
+            .saturating_sub(1);
+        if active != self.active {
+            self.active = active;
+            self.pid.set_gains(self.entries[active].1);
+        }
+        self.pid.poll(pv)
+    }
+    pub fn reset(&mut self) {
+        self.pid.reset();
+    }
+    pub fn pid(&self) -> &Pid {
+        &self.pid
+    }
+}
+
+// Take-back-half velocity controller for flywheels. The output is the
+// integral of the error and each time the error crosses zero it is
+// set halfway between its value and the value at the last crossing,
+// which converges on the output holding the target without the
+// overshoot PID gets from the inertia of the flywheel.
+#[derive(Debug, Clone)]
+pub struct Tbh {
+    // output gained per unit of error per second
+    pub gain: f64,
+    target: f64,
+    output: f64,
+    // output at the last zero crossing of the error
+    tbh: f64,
+    last_error: f64,
+    last_update: Instant,
+    first_update: bool,
+}
+
+impl Tbh {
+    pub fn new(gain: f64) -> Self {
+        Self {
+            gain,
+            target: 0.0,
+            output: 0.0,
+            tbh: 0.0,
+            last_error: 0.0,
+            last_update: Instant::now(),
+            first_update: true,
+        }
+    }
+    // a guess of the output holding the target (e.g. from a
+    // feedforward model) skips most of the spin up oscillation
+    pub fn set_target(&mut self, target: f64, guess: Option<f64>) {
+        self.target = target;
+        if let Some(guess) = guess {
+            self.output = guess.clamp(-OUTPUT_LIMIT, OUTPUT_LIMIT);
+            self.tbh = self.output;
+        }
+    }
+    pub fn poll(&mut self, velocity: f64) -> f64 {
+        let now = Instant::now();
+        let diff_t = now.duration_since(self.last_update).as_secs_f64();
+        let error = self.target - velocity;
+        // the output is held through gaps like Pid's integral
+        if !(self.first_update || diff_t > MAX_DT) {
+            self.output += self.gain * error * diff_t.max(MIN_DT);
+            self.output = self.output.clamp(-OUTPUT_LIMIT, OUTPUT_LIMIT);
+            if error.signum() != self.last_error.signum() {
+                self.output = 0.5 * (self.output + self.tbh);
+                self.tbh = self.output;
+            }
+        }
+        self.last_error = error;
+        self.last_update = now;
+        self.first_update = false;
+        self.output
+    }
+    pub fn reset(&mut self) {
+        self.first_update = true;
+        self.output = 0.0;
+        self.tbh = 0.0;
+        self.last_error = 0.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn angle_wrap_error_is_the_short_way_round() {
+        let mut pid = Pid::new(1.0, 0.0, 0.0);
+        pid.angle_wrap = true;
+        let mut sweep = testing::Sweep::new(3286);
+        for _ in 0..10_000 {
+            let target = sweep.range(-4.0 * TAU, 4.0 * TAU);
+            let pv = sweep.range(-4.0 * TAU, 4.0 * TAU);
+            pid.set_target(target);
+            let error = pid.error(pv);
+            assert!(
+                (-PI..PI).contains(&error),
+                "error from {pv} to {target} is {error}"
+            );
+            assert!(
+                testing::same_direction(pv + error, target),
+                "error from {pv} to {target} is {error}"
+            );
+        }
+    }
+
+    #[test]
+    fn angle_wrap_turns_the_short_way_across_the_seam() {
+        let mut pid = Pid::new(1.0, 0.0, 0.01);
+        pid.angle_wrap = true;
+        pid.set_target(PI - 0.1);
+        pid.poll(PI - 0.05);
+        std::thread::sleep(Duration::from_millis(10));
+        // the heading crosses from π to -π, 0.1 rad past the target
+        let out = pid.poll(-PI + 0.05);
+        // the P term (-0.15) and at most -0.1 from the derivative, rather
+        // then a full turn the other way
+        assert!((-0.25..=-0.15).contains(&out), "output {out}");
     }
 }
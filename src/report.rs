@@ -0,0 +1,136 @@
+use std::{
+    fmt::Write,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    brain::Brain,
+    odom::Odometry,
+    path::{Path, SegmentRecord},
+    robot::RobotState,
+};
+
+// directory the match reports are written to
+const REPORT_DIR: &str = "match_reports";
+
+// A record of one enabled period of the robot (an auton or driver run)
+// written as markdown when the robot is disabled.
+pub struct MatchReport {
+    state: RobotState,
+    start: Instant,
+    auton_program: u8,
+    route: Option<&'static str>,
+    distance: f64,
+    last_pos: [f64; 2],
+    // odometry fell back from the tracking wheels during the run
+    odom_degraded: bool,
+    // max current draw of each motor in mA
+    max_currents: [i16; 20],
+    // max temperature of each motor in C
+    max_temperatures: [Option<f64>; 20],
+    segments: Vec<SegmentRecord>,
+}
+
+impl MatchReport {
+    pub fn begin(
+        state: RobotState,
+        route: Option<&'static str>,
+        brain: &mut Brain,
+        odom: &Odometry,
+    ) -> Self {
+        // drop stats from while the robot was disabled
+        brain.take_link_stats();
+        Self {
+            state,
+            start: Instant::now(),
+            auton_program: brain.auton_program(),
+            route,
+            distance: 0.0,
+            last_pos: odom.position(),
+            odom_degraded: odom.degraded(),
+            max_currents: [0; 20],
+            max_temperatures: [None; 20],
+            segments: Vec::new(),
+        }
+    }
+    // called every loop while the robot is enabled
+    pub fn update(&mut self, brain: &Brain, odom: &Odometry, path: &mut Path) {
+        let pos = odom.position();
+        self.distance +=
+            ((pos[0] - self.last_pos[0]).powi(2) + (pos[1] - self.last_pos[1]).powi(2)).sqrt();
+        self.last_pos = pos;
+        self.odom_degraded |= odom.degraded();
+        for (max, current) in self.max_currents.iter_mut().zip(brain.motor_currents()) {
+            *max = (*max).max(current.unwrap_or(0).abs());
+        }
+        for (max, temp) in self
+            .max_temperatures
+            .iter_mut()
+            .zip(brain.motor_temperatures())
+        {
+            *max = match (*max, temp) {
+                (Some(max), Some(temp)) => Some(max.max(temp)),
+                (max, temp) => max.or(temp),
+            };
+        }
+        self.segments.extend(path.take_history());
+    }
+    pub fn finish(self, brain: &mut Brain) {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let file = format!("{REPORT_DIR}/match-{time}.md");
+        let result = std::fs::create_dir_all(REPORT_DIR)
+            .and_then(|_| std::fs::write(&file, self.markdown(brain)));
+        match result {
+            Ok(()) => log::info!("Match report written to {file}"),
+            Err(e) => log::warn!("Failed to write match report to {file}: {e}"),
+        }
+    }
+    fn markdown(&self, brain: &mut Brain) -> String {
+        let link = brain.take_link_stats();
+        let mut md = String::new();
+        let _ = writeln!(md, "# Match report\n");
+        let _ = writeln!(md, "- mode: {:?}", self.state);
+        let _ = writeln!(md, "- auton program: {}", self.auton_program);
+        let _ = writeln!(md, "- route: {}", self.route.unwrap_or("none"));
+        let _ = writeln!(md, "- duration: {:.2}s", self.start.elapsed().as_secs_f64());
+        let _ = writeln!(md, "- distance driven: {:.2}m", self.distance);
+        if self.odom_degraded {
+            let _ = writeln!(md, "- odometry: tracking wheels failed, used the fallback");
+        }
+        let _ = writeln!(md, "- battery sag: not measured by the brain link");
+
+        let _ = writeln!(md, "\n## Link\n");
+        let _ = writeln!(md, "- status packets: {}", link.packets);
+        let _ = writeln!(md, "- longest gap: {}ms", link.max_gap.as_millis());
+        let _ = writeln!(md, "- disconnects: {}", link.disconnects);
+
+        if !self.segments.is_empty() {
+            let _ = writeln!(md, "\n## Segments\n");
+            let _ = writeln!(md, "| time (s) | segment |\n| --- | --- |");
+            for seg in &self.segments {
+                let _ = writeln!(
+                    md,
+                    "| {:.2} | `{}` |",
+                    seg.duration.as_secs_f64(),
+                    seg.segment
+                );
+            }
+        }
+
+        let _ = writeln!(md, "\n## Motors\n");
+        let _ = writeln!(
+            md,
+            "| port | max current (mA) | max temperature (C) |\n| --- | --- | --- |"
+        );
+        let motors = self.max_currents.iter().zip(&self.max_temperatures);
+        for (i, (current, temp)) in motors.enumerate() {
+            if *current > 0 || temp.is_some() {
+                let temp = temp.map_or("-".to_string(), |t| format!("{t:.0}"));
+                let _ = writeln!(md, "| {} | {current} | {temp} |", i + 1);
+            }
+        }
+        md
+    }
+}
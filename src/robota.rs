@@ -1,34 +1,100 @@
+mod auton;
+mod autotune;
 mod bmi088;
 mod brain;
+mod budget;
+mod calibration;
+mod capture;
+mod cascade;
+mod command;
+mod config;
 mod controller;
 mod drivebase;
+mod ekf;
+mod estimate;
+mod feedforward;
+mod field;
+mod filters;
+mod imu;
+mod lift;
+mod link;
+mod mirror;
 mod motor;
 mod odom;
 mod path;
 mod pid;
+mod report;
 mod robot;
+mod subsystem;
+mod teach;
+#[cfg(test)]
+mod testing;
 mod triports;
 mod vec;
 
 use crate::path::*;
-use brain::Brain;
+use auton::AutonSelector;
+use autotune::RelayTune;
+use bmi088::Bmi088;
+use brain::{Brain, PortError, StartupMode};
+use budget::LoopBudget;
+use calibration::{BackgroundCalibration, GyroBias, GyroScale, GyroTemperature};
+use command::{InstantCommand, Scheduler};
 use communication::{
     packet::{FromMediator, ToMediator},
     Mediator,
 };
+use config::{RobotConfig, Watched};
 use controller::Controller;
-use drivebase::Tankdrive;
-use odom::Odometry;
-use pid::Pid;
+use drivebase::{AntiTip, DriveMode, Tankdrive};
+use estimate::Differentiator;
+use feedforward::Feedforward;
+use lift::{Gravity, Lift};
+use motor::{AngularVelocity, MotorModel};
+use odom::{OdomConfig, OdomThread, OdomTrim, Odometry};
+use pid::{Pid, PidGains};
 use protocol::device::ControllerButtons;
+use report::MatchReport;
 use robot::RobotState;
+use subsystem::Subsystems;
+use teach::Teach;
 
-use std::time::Duration;
-
-use crate::bmi088::ROBOT_A_IMU_BIAS;
+use std::{cell::RefCell, rc::Rc, time::Duration};
 
 const IS_SKILLS: bool = true;
+// max drive velocity when following auton paths
+const AUTON_MAX_VELOCITY: AngularVelocity = AngularVelocity::from_rpm(200.0);
 pub const BRAIN_TIMEOUT: Duration = Duration::from_millis(500);
+const CONFIG_PATH: &str = "lemon.cfg";
+const WIRING_REPORT_PATH: &str = "wiring.txt";
+const TEACH_PATH: &str = "taught_route.txt";
+// turn controller gains unless tuned ones are in the config under "pid.angle"
+const ANGLE_GAINS: PidGains = PidGains::new(0.35, 0.035, 0.0022);
+// relay output and hysteresis (rad) used when auto-tuning the turn controller
+const TUNE_AMPLITUDE: f64 = 0.4;
+const TUNE_HYSTERESIS: f64 = 0.01;
+// turns done by hand during gyro scale calibration unless set in the config
+const GYRO_SCALE_TURNS: f64 = 5.0;
+// max change in forward output per second while the robot is tipping
+const ANTI_TIP_RATE: f64 = 1.0;
+// change in drive trim per button press
+const TRIM_STEP: f64 = 0.01;
+// lift presets and limits (motor degrees), the lift is only fitted when
+// "lift.port" is set in the config
+const LIFT_PRESETS: [(&str, f64); 3] = [("down", 0.0), ("middle", 300.0), ("up", 600.0)];
+const LIFT_GAINS: PidGains = PidGains::new(0.005, 0.0, 0.0);
+const LIFT_MAX_VELOCITY: f64 = 720.0;
+const LIFT_MAX_ACCELERATION: f64 = 1440.0;
+// within 10deg and moving slower than 30deg/s
+const LIFT_SETTLE: SettleCondition =
+    SettleCondition::new(10.0, 30.0).with_duration(Duration::from_millis(100));
+const CALIBRATION_BUDGET: Duration = Duration::from_micros(200);
+// odometry runs on its own thread at 200Hz
+const ODOM_PERIOD: Duration = Duration::from_millis(5);
+// time each part of the main loop may take, the whole loop has 2ms
+const LOOP_BUDGET: Duration = Duration::from_millis(2);
+const REPORT_BUDGET: Duration = Duration::from_micros(200);
+const CONTROL_BUDGET: Duration = Duration::from_micros(800);
 
 fn main() -> ! {
     Robot::run();
@@ -40,8 +106,24 @@ struct Robot {
     controller: Controller,
     drivebase: Tankdrive<3>,
     mediator: Mediator,
-    odom: Odometry,
+    odom: OdomThread,
     pid_angle: Pid,
+    auton_start: std::time::Instant,
+    config: RobotConfig,
+    turn_multiplier: Watched,
+    drive_mode: Watched,
+    drive_trim: Watched,
+    // heading the driver faces for field centric drive
+    driver_forward: f64,
+    // recording the driven route when in teach mode
+    teach: Option<Teach>,
+    gyro_scale: Option<GyroScale>,
+    autotune: Option<RelayTune>,
+    calibration: BackgroundCalibration,
+    // parts run every loop in every state alongside the drivebase
+    subsystems: Subsystems,
+    // commands bound to the controller in driver control
+    commands: Scheduler,
 }
 
 // merge or move these functions?
@@ -53,29 +135,118 @@ impl Robot {
     pub fn new() -> Self {
         let mediator = communication::Logger::init(true).expect("This only panics when another logger is set. This should never be the case and indicates a problem with the code.");
 
-        // block until connection is establish with brain
+        let mut config = RobotConfig::load(CONFIG_PATH);
+
+        // block until connection is establish with brain (unless in bench mode)
         log::info!("Connecting to the brain.");
-        let (mut brain, controller) = Brain::init();
+        let (mut brain, mut controller) = Brain::init(StartupMode::load(&config));
+        controller.load_curves(&config);
         log::info!("Connected to the brain.");
 
         // this is the drivetrain configuration for the nationals hang robot
-        let drivebase = Tankdrive::new(
+        let mut drivebase = Tankdrive::new(
             [(11, false), (12, true), (17, true)],
             [(14, false), (15, true), (16, false)],
             protocol::device::Gearbox::Blue,
             &mut brain,
+        )
+        .expect("the drivebase ports are only claimed by the drivebase");
+        drivebase.set_feedforward(Feedforward::load(&config, "drive.ff"));
+        drivebase.set_velocity_pid(PidGains::load(&config, "drive.velocity_pid"));
+        drivebase.set_model(MotorModel::load(&config, "drive"));
+        // max change in output per second so steps don't lift the wheels
+        drivebase.set_slew(config.get("drive.slew"));
+        // limits forward acceleration while the robot pitches faster than
+        // "drive.anti_tip.pitch_rate" (rad/s)
+        if let Some(pitch_rate) = config.get("drive.anti_tip.pitch_rate") {
+            let rate = config.get("drive.anti_tip.rate").unwrap_or(ANTI_TIP_RATE);
+            drivebase.set_anti_tip(Some(AntiTip::new(pitch_rate, rate)));
+        }
+        drivebase.set_current_limit(config.get("drive.current_limit").map(|limit| limit as i16));
+        drivebase.set_brake_mode(motor::BrakeMode::from_config(
+            config.get("drive.brake_mode").unwrap_or(0.0),
+        ));
+
+        // the drive motor encoders stand in for the tracking wheels when
+        // "odom.motor_tracking_scale" (meters per unit of motor position) is set
+        let geometry = OdomConfig::load(&config);
+        // the only IMU fitted is a BMI088, the config can move its i2c address
+        let imu_addr = config
+            .get("imu.address")
+            .map_or(0x69u16, |addr| addr as u16);
+        let imu = Box::new(Bmi088::new(imu_addr).expect("the IMU needs the i2c bus"));
+        let mut odom = match config.get("odom.motor_tracking_scale") {
+            Some(scale) => {
+                Odometry::with_tracking(imu, geometry, Box::new(drivebase.tracking_source(scale)))
+            }
+            None => {
+                let odom = Odometry::new(imu, geometry);
+                // the drive motor encoders take over if a tracking wheel fails
+                match config.get("odom.fallback_scale") {
+                    Some(scale) => odom.fallback(Box::new(drivebase.tracking_source(scale))),
+                    None => odom,
+                }
+            }
+        };
+
+        odom.set_trim(OdomTrim::load(&config));
+        // used until the bias is measured while disabled
+        if let Some(bias) = config.get("imu.gyro_bias") {
+            odom.set_gyro_bias(bias);
+        }
+        if let Some(slope) = config.get("imu.gyro_temperature_slope") {
+            odom.set_gyro_temperature_slope(slope);
+        }
+        if let Some(scale) = config.get("imu.gyro_scale") {
+            odom.set_gyro_scale(scale);
+        }
+        odom.set_rate_estimation(
+            Differentiator::load(&config, "odom.side_rates", odom::VELOCITY_WINDOW),
+            Differentiator::load(&config, "odom.pose_rates", odom::VELOCITY_WINDOW),
         );
+        motor::set_arbitration_log(config.get("debug.arbitration_log").unwrap_or(0.0) != 0.0);
 
-        let odom = Odometry::new(0.004167368000717639 - 0.007987093436054596, 0x69u16); //ROBOT_A_IMU_BIAS, 0x69u16);
+        // runs while the robot is disabled e.g. waiting on the field
+        let mut calibration = BackgroundCalibration::new(CALIBRATION_BUDGET);
+        // about 4s of samples
+        if config.get("calibration.gyro_bias").unwrap_or(1.0) != 0.0 {
+            calibration.add(Box::new(GyroBias::new(2000)));
+        }
+        // needs the robot to warm up so is only run when asked for
+        if config.get("calibration.gyro_temperature").unwrap_or(0.0) != 0.0 {
+            calibration.add(Box::new(GyroTemperature::new()));
+        }
+        let turn_multiplier = config.watch("drive.turn_multiplier", TURN_MULTIPLIER);
+        let drive_mode = config.watch("drive.mode", 0.0);
+        let drive_trim = config.watch("drive.trim", 0.0);
+        drivebase.set_trim(drive_trim.get());
 
+        let lift = lift(&config, &brain);
+        let commands = driver_commands(&brain, lift.clone());
+        let subsystems = match lift {
+            Some(lift) => Subsystems::new().register(Box::new(lift)),
+            None => Subsystems::new(),
+        };
         Self {
             state: RobotState::default(),
             brain,
             controller,
             drivebase,
             mediator,
-            odom,
-            pid_angle: Pid::new(0.35, 0.035, 2.2),
+            odom: OdomThread::spawn(odom, ODOM_PERIOD),
+            pid_angle: Pid::with_gains(PidGains::load(&config, "pid.angle").unwrap_or(ANGLE_GAINS)),
+            auton_start: std::time::Instant::now(),
+            teach: None,
+            gyro_scale: None,
+            autotune: None,
+            calibration,
+            subsystems,
+            commands,
+            config,
+            turn_multiplier,
+            drive_mode,
+            drive_trim,
+            driver_forward: 0.0,
         }
     }
     pub fn handle_events(&mut self) {
@@ -102,10 +273,24 @@ impl Robot {
     pub fn main_loop(&mut self) -> ! {
         let mut tuning_start = std::time::Instant::now();
         let mut start_heading = 0.0;
-        let mut angle_pid = Pid::new(0.35, 0.035, 2.2);
-        let mut auton_path = auton_path(&mut self.brain);
+        let mut angle_pid = Pid::with_gains(self.pid_angle.gains());
+        let mut autons = AutonSelector::new()
+            .register(0, "load and push", auton_path)
+            .register_file(1, "taught route", TEACH_PATH);
+        let mut auton_path = autons
+            .select(&mut self.brain)
+            .unwrap_or_else(|| Path::new(Vec::new()));
+        // pauses before each segment until A is pressed (see Path::step)
+        let step_path = self.config.get("debug.step_path").unwrap_or(0.0) != 0.0;
+        auton_path.set_step_mode(step_path);
+        // after the parts have claimed their ports
+        self.brain.save_wiring_report(WIRING_REPORT_PATH);
+        let mut report: Option<MatchReport> = None;
+        let mut budget = LoopBudget::new(LOOP_BUDGET);
         loop {
+            budget.start_loop();
             self.handle_events();
+            self.config.poll_file();
 
             // updates controller, robot state & motors
             let new_state = self.brain.update_state(&mut self.controller, &self.state);
@@ -114,14 +299,52 @@ impl Robot {
 
                 // reset odom at start of auton
                 if new_state == RobotState::AutonSkills || new_state == RobotState::DriverAuton {
-                    self.odom.reset();
+                    self.odom.lock().reset();
+                    self.auton_start = std::time::Instant::now();
+                }
+
+                // values changed while driving (e.g. the drive trim) are
+                // saved once rather than on every change
+                if matches!(new_state, RobotState::Off | RobotState::Disabled) {
+                    if let Err(e) = self.config.save() {
+                        log::error!("Failed to save the config: {e}");
+                    }
+                }
+
+                // report on the run that just ended and start one for the next
+                if let Some(report) = report.take() {
+                    report.finish(&mut self.brain);
+                }
+                if !matches!(new_state, RobotState::Off | RobotState::Disabled) {
+                    report = Some(MatchReport::begin(
+                        new_state,
+                        autons.selected_name(),
+                        &mut self.brain,
+                        &self.odom.lock(),
+                    ));
                 }
             }
             self.state = new_state;
 
-            self.odom.calc_position();
+            // the route can be changed on the brain until the robot is enabled
+            if self.state == RobotState::Disabled {
+                if let Some(path) = autons.select(&mut self.brain) {
+                    auton_path = path;
+                    auton_path.set_step_mode(step_path);
+                }
+            }
+
+            // commands only run in driver control
+            if !matches!(
+                self.state,
+                RobotState::DriverDriver | RobotState::DriverSkills
+            ) {
+                self.commands.cancel_all();
+            }
+            // stops every part when the robot is disabled
+            self.subsystems.run(self.state, &mut [&mut self.drivebase]);
 
-            match self.state {
+            budget.time("control", CONTROL_BUDGET, || match self.state {
                 RobotState::Off | RobotState::Disabled => {}
                 RobotState::AutonSkills => self.auton_skills(&mut auton_path, &mut angle_pid),
                 RobotState::DriverAuton => self.auton(&mut auton_path, &mut angle_pid),
@@ -131,28 +354,103 @@ impl Robot {
                 RobotState::DriverDriver => {
                     self.driver(&mut tuning_start, &mut start_heading);
                 }
-            }
+            });
             self.brain.write_changes();
+
+            // calibration and the report can wait for a later loop when
+            // there isn't time left, calibration limits itself so only the
+            // overhead is allowed on top
+            budget.time_deferrable("calibration", CALIBRATION_BUDGET * 2, || {
+                self.calibration.poll(
+                    self.state == RobotState::Disabled,
+                    &mut self.odom.lock(),
+                    &mut self.config,
+                )
+            });
+            if let Some(report) = report.as_mut() {
+                budget.time_deferrable("match report", REPORT_BUDGET, || {
+                    report.update(&self.brain, &self.odom.lock(), &mut auton_path)
+                });
+            }
+            budget.report();
             std::thread::sleep(std::time::Duration::from_millis(1));
         }
     }
     fn driver(&mut self, tuning_start: &mut std::time::Instant, start_heading: &mut f64) {
         communication::odom(self.odom.position(), self.odom.heading());
-        let forward_rate = self.controller.ly();
-        let turning_rate = self.controller.rx();
-        let (mut l, mut r) = (
-            (forward_rate + turning_rate * TURN_MULTIPLIER).clamp(-1.0, 1.0),
-            (forward_rate - turning_rate * TURN_MULTIPLIER).clamp(-1.0, 1.0),
+        let pitch_rate = self.odom.lock().pitch_rate();
+        self.drivebase.update_pitch_rate(pitch_rate);
+        // LEFT makes the way the robot is facing forward for field centric drive
+        if self.controller.pressed(ControllerButtons::LEFT) {
+            self.driver_forward = self.odom.heading();
+            log::info!(
+                "Driver forward set to {}deg.",
+                self.driver_forward.to_degrees()
+            );
+        }
+        // L1 and R1 trim the drive left and right, saved to "drive.trim"
+        // when the robot is disabled
+        for (button, step) in [
+            (ControllerButtons::L1, -TRIM_STEP),
+            (ControllerButtons::R1, TRIM_STEP),
+        ] {
+            if self.controller.pressed(button) {
+                let trim = self.drivebase.trim() + step;
+                self.config.set("drive.trim", trim);
+            }
+        }
+        if self.drive_trim.changed() {
+            self.drivebase.set_trim(self.drive_trim.get());
+            log::info!("Drive trim set to {}.", self.drivebase.trim());
+        }
+        let drive_mode = DriveMode::from_config(self.drive_mode.get());
+        let (l, r) = drive_mode.sides(
+            &self.controller,
+            self.turn_multiplier.get(),
+            self.odom.heading() - self.driver_forward,
         );
+        let (mut l, mut r) = self.drivebase.apply_trim(l, r);
         log::info!("{:?} @ {:?}", self.odom.position(), self.odom.heading());
 
-        if self.controller.pressed(ControllerButtons::Y) {
-            log::info!("TOGGLED");
-            let triport = self.brain.get_triport(1);
-            let triport_two = self.brain.get_triport(2);
-            triport.toggle();
-            triport_two.toggle();
+        self.commands.run(&self.controller);
+        // teach mode, X starts recording the driven route (relative to
+        // where the robot is, taken as the start of auton) and X again saves it
+        if self.controller.pressed(ControllerButtons::X) {
+            match self.teach.take() {
+                None => {
+                    self.teach = Some(Teach::new(self.odom.position(), self.odom.heading()));
+                    log::info!("Teach mode started.");
+                }
+                Some(teach) => match teach.save(TEACH_PATH) {
+                    Ok(()) => log::info!("Teach mode finished, route saved to {TEACH_PATH}."),
+                    Err(e) => log::error!("Failed to save taught route to {TEACH_PATH}: {e}"),
+                },
+            }
         }
+        // gyro scale calibration, UP starts it and UP again once the robot
+        // has been turned "calibration.gyro_scale_turns" times by hand
+        if self.controller.pressed(ControllerButtons::UP) {
+            match self.gyro_scale.take() {
+                None => {
+                    let turns = self
+                        .config
+                        .get("calibration.gyro_scale_turns")
+                        .unwrap_or(GYRO_SCALE_TURNS);
+                    self.gyro_scale = Some(GyroScale::start(&self.odom.lock(), turns));
+                }
+                Some(calibration) => {
+                    let mut odom = self.odom.lock();
+                    if let Some(scale) = calibration.finish(&odom) {
+                        odom.set_gyro_scale(scale);
+                        GyroScale::save(scale, &mut self.config);
+                    }
+                }
+            }
+        }
+        if let Some(teach) = self.teach.as_mut() {
+            teach.sample(self.odom.position(), self.odom.heading());
+        }
+
         use communication::plot;
         plot!("heading (degrees)", self.odom.heading().to_degrees());
         if self.controller.pressed(ControllerButtons::A) {
@@ -167,6 +465,36 @@ impl Robot {
             r = pw;
         }
 
+        // relay auto-tune of the turn controller, DOWN starts (or aborts)
+        // it and the gains found replace the current ones
+        if self.controller.pressed(ControllerButtons::DOWN) {
+            self.autotune = match self.autotune.take() {
+                None => Some(RelayTune::new(
+                    self.odom.heading(),
+                    TUNE_AMPLITUDE,
+                    TUNE_HYSTERESIS,
+                )),
+                Some(_) => {
+                    log::info!("Relay tuning aborted.");
+                    None
+                }
+            };
+        }
+        if let Some(tune) = self.autotune.as_mut() {
+            let pw = tune.poll(self.odom.heading());
+            if pw.is_none() {
+                if let Some(gains) = tune.gains() {
+                    self.pid_angle.set_gains(gains);
+                    self.pid_angle.reset();
+                    gains.save(&mut self.config, "pid.angle");
+                }
+                self.autotune = None;
+            }
+            let pw = pw.unwrap_or(0.0);
+            l = -pw;
+            r = pw;
+        }
+
         if self.controller.pressed(ControllerButtons::B) {
             *start_heading = self.odom.heading();
             *tuning_start = std::time::Instant::now();
@@ -182,6 +510,11 @@ impl Robot {
                 "PID tuning finished with drift of {grad} ({}deg).",
                 grad.to_degrees()
             );
+            // the measured drift is on top of the current trim
+            let mut trim = self.odom.lock().trim();
+            trim.heading_per_second += grad;
+            self.odom.lock().set_trim(trim);
+            trim.save(&mut self.config);
         }
 
         // prevent the robot from moving when "tuning" the IMU
@@ -191,9 +524,21 @@ impl Robot {
         }
     }
     fn auton(&mut self, route: &mut crate::path::Path, angle_pid: &mut Pid) {
-        let [l, r] = route.follow(&self.odom, angle_pid);
+        if self.controller.pressed(ControllerButtons::A) {
+            route.step();
+        }
+        let [l, r] = route.follow(
+            &mut RobotCtx {
+                odom: &mut self.odom.lock(),
+                brain: &self.brain,
+                auton_start: self.auton_start,
+                constraints: PathConstraints::default(),
+            },
+            angle_pid,
+        );
         //plot!("lr", [l, r]);
-        self.drivebase.set_side_percent_max_rpm(l, r, 200.0);
+        self.drivebase
+            .set_side_percent_max_velocity(l, r, AUTON_MAX_VELOCITY);
         log::info!("auton program: {}", self.brain.auton_program());
     }
 
@@ -203,13 +548,28 @@ impl Robot {
         plot!("heading", self.odom.heading().to_degrees());
         communication::odom(self.odom.position(), self.odom.heading());
 
-        let [l, r] = route.follow(&self.odom, angle_pid);
+        if self.controller.pressed(ControllerButtons::A) {
+            route.step();
+        }
+        let [l, r] = route.follow(
+            &mut RobotCtx {
+                odom: &mut self.odom.lock(),
+                brain: &self.brain,
+                auton_start: self.auton_start,
+                constraints: PathConstraints::default(),
+            },
+            angle_pid,
+        );
         //plot!("lr", [l, r]);
-        self.drivebase.set_side_percent_max_rpm(l, r, 200.0);
+        self.drivebase
+            .set_side_percent_max_velocity(l, r, AUTON_MAX_VELOCITY);
     }
 }
-fn load_balls(brain: &mut Brain, n: usize) -> Path {
-    let kicker = [(brain.get_motor(13), false), (brain.get_motor(1), true)];
+fn load_balls(brain: &mut Brain, n: usize) -> Result<Path, PortError> {
+    let kicker = [
+        (brain.claim_motor(13, false, "kicker")?, false),
+        (brain.claim_motor(1, true, "kicker")?, true),
+    ];
     let kick_ball = Path::new(vec![
         Box::new(TimedSegment::new(
             Box::new(PowerMotors::new(kicker.clone(), -1.0)),
@@ -224,7 +584,7 @@ fn load_balls(brain: &mut Brain, n: usize) -> Path {
             Duration::from_millis(800),
         )),
     ]);
-    Path::new(vec![
+    Ok(Path::new(vec![
         Box::new(TimedSegment::new(
             Box::new(PowerMotors::new(kicker.clone(), -0.8)),
             Duration::from_millis(80),
@@ -242,29 +602,84 @@ fn load_balls(brain: &mut Brain, n: usize) -> Path {
             Box::new(PowerMotors::new(kicker.clone(), 1.0)),
             Duration::from_millis(250),
         )),
-    ])
+    ]))
 }
 
 const TURN_MULTIPLIER: f64 = 0.5;
-fn blocker_up(brain: &mut Brain) -> Box<TimedSegment> {
-    let blocker = [(brain.get_motor(18), false)];
-    Box::new(TimedSegment::new(
+fn lift(config: &RobotConfig, brain: &Brain) -> Option<Rc<RefCell<Lift>>> {
+    let port = config.get("lift.port")? as u8;
+    let reversed = config.get("lift.reversed").unwrap_or(0.0) != 0.0;
+    let pid = Pid::with_gains(PidGains::load(config, "lift.pid").unwrap_or(LIFT_GAINS));
+    let mut lift = Lift::new(
+        &[(port, reversed)],
+        pid,
+        LIFT_MAX_VELOCITY,
+        LIFT_MAX_ACCELERATION,
+        LIFT_SETTLE,
+        brain,
+    )
+    // the conflict is logged and the robot runs without the lift
+    .ok()?
+    .gravity(Gravity::Elevator(config.get("lift.kg").unwrap_or(0.0)))
+    .soft_limits(LIFT_PRESETS[0].1, LIFT_PRESETS[2].1);
+    for (name, position) in LIFT_PRESETS {
+        lift = lift.preset(name, position);
+    }
+    Some(Rc::new(RefCell::new(lift)))
+}
+fn driver_commands(brain: &Brain, lift: Option<Rc<RefCell<Lift>>>) -> Scheduler {
+    let wings = [brain.get_triport(1), brain.get_triport(2)];
+    let commands = Scheduler::new().on_press(ControllerButtons::Y, move || {
+        let wings = wings.clone();
+        Box::new(InstantCommand::new(
+            "toggle wings",
+            vec!["wings"],
+            move || {
+                log::info!("TOGGLED");
+                for wing in &wings {
+                    wing.toggle();
+                }
+            },
+        ))
+    });
+    let Some(lift) = lift else {
+        return commands;
+    };
+    // L2 and R2 step the lift down and up through its presets
+    [
+        (ControllerButtons::L2, false),
+        (ControllerButtons::R2, true),
+    ]
+    .into_iter()
+    .fold(commands, |commands, (button, up)| {
+        let lift = lift.clone();
+        commands.on_press(button, move || {
+            let lift = lift.clone();
+            Box::new(InstantCommand::new("step lift", vec!["lift"], move || {
+                lift.borrow_mut().step_preset(up);
+            }))
+        })
+    })
+}
+fn blocker_up(brain: &mut Brain) -> Result<Box<TimedSegment>, PortError> {
+    let blocker = [(brain.claim_motor(18, false, "blocker")?, false)];
+    Ok(Box::new(TimedSegment::new(
         Box::new(PowerMotors::new(blocker, 1.0)),
         Duration::from_millis(500),
-    ))
+    )))
 }
-fn blocker_down(brain: &mut Brain) -> Box<TimedSegment> {
-    let blocker = [(brain.get_motor(18), false)];
-    Box::new(TimedSegment::new(
+fn blocker_down(brain: &mut Brain) -> Result<Box<TimedSegment>, PortError> {
+    let blocker = [(brain.claim_motor(18, false, "blocker")?, false)];
+    Ok(Box::new(TimedSegment::new(
         Box::new(PowerMotors::new(blocker, -1.0)),
         Duration::from_millis(500),
-    ))
+    )))
 }
 
-fn auton_path(brain: &mut Brain) -> Path {
+fn auton_path(brain: &mut Brain) -> Result<Path, PortError> {
     let out_wing = brain.get_triport(2);
-    Path::new(vec![
-        Box::new(load_balls(brain, 11)),
+    Ok(Path::new(vec![
+        Box::new(load_balls(brain, 11)?),
         Box::new(ChangeTriports::new(
             vec![out_wing.clone()],
             crate::triports::TriportChange::Active,
@@ -295,7 +710,7 @@ fn auton_path(brain: &mut Brain) -> Path {
         Box::new(MinSegment::TurnTo(135f64.to_radians())),
         Box::new(Ram::new(0.4, Duration::from_millis(1700))),
         Box::new(MinSegment::TurnTo(-45f64.to_radians())),
-        blocker_up(brain),
+        blocker_up(brain)?,
         Box::new(Ram::new(0.2, Duration::from_millis(1000))),
-    ])
+    ]))
 }
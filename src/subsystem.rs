@@ -0,0 +1,93 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::robot::RobotState;
+
+// A part of the robot (drive, catapult, lift) called by the robot every
+// loop in every state through Subsystems, so a part can't be forgotten
+// in one of the state branches of the main loop.
+pub trait Subsystem {
+    fn name(&self) -> &'static str;
+    // called every loop while the robot is enabled
+    fn periodic(&mut self, _state: RobotState) {}
+    // called every loop while the robot is disabled (or off)
+    fn disabled(&mut self) {}
+    // puts the part in a safe state, called when the robot is disabled
+    fn stop(&mut self);
+    // values plotted under "<name>.<key>" every loop
+    fn telemetry(&self) -> Vec<(&'static str, f64)> {
+        Vec::new()
+    }
+}
+
+// a part also used by commands or path segments, e.g. a lift moved to
+// its presets by LiftTo
+impl<S: Subsystem> Subsystem for Rc<RefCell<S>> {
+    fn name(&self) -> &'static str {
+        self.borrow().name()
+    }
+    fn periodic(&mut self, state: RobotState) {
+        self.borrow_mut().periodic(state);
+    }
+    fn disabled(&mut self) {
+        self.borrow_mut().disabled();
+    }
+    fn stop(&mut self) {
+        self.borrow_mut().stop();
+    }
+    fn telemetry(&self) -> Vec<(&'static str, f64)> {
+        self.borrow().telemetry()
+    }
+}
+
+fn is_enabled(state: RobotState) -> bool {
+    !matches!(state, RobotState::Off | RobotState::Disabled)
+}
+
+// The subsystems of a robot, run in the order they were registered
+#[derive(Default)]
+pub struct Subsystems {
+    subsystems: Vec<Box<dyn Subsystem>>,
+    last_state: RobotState,
+}
+
+impl Subsystems {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn register(mut self, subsystem: Box<dyn Subsystem>) -> Self {
+        self.subsystems.push(subsystem);
+        self
+    }
+    pub fn names(&self) -> Vec<&'static str> {
+        self.subsystems.iter().map(|s| s.name()).collect()
+    }
+    // Runs every registered subsystem and the ones owned by the robot
+    // (e.g. the drivebase which the driver loop also uses directly),
+    // called once every loop.
+    pub fn run(&mut self, state: RobotState, owned: &mut [&mut dyn Subsystem]) {
+        let stopping = is_enabled(self.last_state) && !is_enabled(state);
+        self.last_state = state;
+        for subsystem in &mut self.subsystems {
+            run_one(subsystem.as_mut(), state, stopping);
+        }
+        for subsystem in owned {
+            run_one(*subsystem, state, stopping);
+        }
+    }
+}
+
+fn run_one(subsystem: &mut dyn Subsystem, state: RobotState, stopping: bool) {
+    if stopping {
+        log::info!("Stopping {}.", subsystem.name());
+        subsystem.stop();
+    }
+    if is_enabled(state) {
+        subsystem.periodic(state);
+    } else {
+        subsystem.disabled();
+    }
+    for (key, value) in subsystem.telemetry() {
+        let name = format!("{}.{key}", subsystem.name());
+        communication::plot!(name.as_str(), value);
+    }
+}
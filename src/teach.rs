@@ -0,0 +1,215 @@
+use std::io::Write;
+
+use crate::path::MinSegment;
+use crate::vec::Vec2;
+
+// minimum distance between recorded samples in meters
+const MIN_SPACING: f64 = 0.05;
+// max distance the simplified path may be from the driven one in meters
+const TOLERANCE: f64 = 0.03;
+
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    pos: [f64; 2],
+    // the robot was driving backwards to get to this sample
+    reverse: bool,
+}
+
+// Records the robot's position while it is driven so an autonomous
+// route can be authored by driving it once. The trace is simplified
+// into MoveTo/MoveToReverse segments with Ramer-Douglas-Peucker.
+// Positions are recorded relative to the pose teaching started from,
+// which is the origin facing 0 like at the start of auton.
+#[derive(Debug, Default)]
+pub struct Teach {
+    start: [f64; 2],
+    start_heading: f64,
+    samples: Vec<Sample>,
+}
+
+impl Teach {
+    pub fn new(start: [f64; 2], start_heading: f64) -> Self {
+        Self {
+            start,
+            start_heading,
+            samples: vec![Sample {
+                pos: [0.0, 0.0],
+                reverse: false,
+            }],
+        }
+    }
+    // records the position if the robot has moved far enough since the last sample
+    pub fn sample(&mut self, pos: [f64; 2], heading: f64) {
+        let (pos, heading) = self.relative(pos, heading);
+        let last = self.samples.last().map_or(pos, |s| s.pos);
+        let diff = [pos[0] - last[0], pos[1] - last[1]];
+        if (diff[0].powi(2) + diff[1].powi(2)).sqrt() < MIN_SPACING {
+            return;
+        }
+        let reverse = diff[0] * heading.cos() + diff[1] * heading.sin() < 0.0;
+        self.samples.push(Sample { pos, reverse });
+    }
+    // the recorded route (the first sample is the start of the route)
+    pub fn segments(&self) -> Vec<MinSegment> {
+        let mut keep = vec![false; self.samples.len()];
+        if let Some(last) = keep.last_mut() {
+            *last = true;
+        }
+        self.simplify(0, self.samples.len().saturating_sub(1), &mut keep);
+
+        // split at changes in direction so a reversing section isn't
+        // simplified into a forwards move
+        for i in 1..self.samples.len() {
+            if self.samples[i].reverse != self.samples[i - 1].reverse {
+                keep[i - 1] = true;
+            }
+        }
+
+        self.samples
+            .iter()
+            .zip(keep)
+            .skip(1)
+            .filter(|(_, keep)| *keep)
+            .map(|(s, _)| {
+                if s.reverse {
+                    MinSegment::MoveToReverse(s.pos)
+                } else {
+                    MinSegment::MoveTo(s.pos)
+                }
+            })
+            .collect()
+    }
+    // writes the route to a file with one segment per line
+    pub fn save(&self, path: &str) -> std::io::Result<()> {
+        let mut file = std::fs::File::create(path)?;
+        for seg in self.segments() {
+            writeln!(file, "{seg:?}")?;
+        }
+        Ok(())
+    }
+    // the pose in the frame of the start pose
+    fn relative(&self, pos: [f64; 2], heading: f64) -> ([f64; 2], f64) {
+        let diff = [pos[0] - self.start[0], pos[1] - self.start[1]];
+        let (sin, cos) = (-self.start_heading).sin_cos();
+        (
+            [diff[0] * cos - diff[1] * sin, diff[0] * sin + diff[1] * cos],
+            heading - self.start_heading,
+        )
+    }
+    // marks the samples between first and last to keep
+    fn simplify(&self, first: usize, last: usize, keep: &mut [bool]) {
+        if last <= first + 1 {
+            return;
+        }
+        let start: Vec2 = self.samples[first].pos.into();
+        let end: Vec2 = self.samples[last].pos.into();
+        let (furthest, dist) = (first + 1..last)
+            .map(|i| (i, line_dist(start, end, self.samples[i].pos.into())))
+            .fold((first, 0.0), |max, v| if v.1 > max.1 { v } else { max });
+        if dist > TOLERANCE {
+            keep[furthest] = true;
+            self.simplify(first, furthest, keep);
+            self.simplify(furthest, last, keep);
+        }
+    }
+}
+
+// distance from pos to the line segment between start and end
+fn line_dist(start: Vec2, end: Vec2, pos: Vec2) -> f64 {
+    let base = end - start;
+    let len = base.mag();
+    if len == 0.0 {
+        return (pos - start).mag();
+    }
+    let t = ((pos - start).dot(base / len) / len).clamp(0.0, 1.0);
+    (pos - (start + base * t)).mag()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, close};
+    use std::f64::consts::FRAC_PI_2;
+
+    fn positions(segments: &[MinSegment]) -> Vec<(bool, [f64; 2])> {
+        segments
+            .iter()
+            .map(|seg| match *seg {
+                MinSegment::MoveTo(pos) => (false, pos),
+                MinSegment::MoveToReverse(pos) => (true, pos),
+                _ => panic!("unexpected segment {seg:?}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn route_stays_within_tolerance_of_the_driven_trace() {
+        // a wobbly quarter circle forwards then a straight reverse
+        let mut sweep = testing::Sweep::new(3277);
+        let mut teach = Teach::new([0.0, 0.0], 0.0);
+        let mut driven = vec![[0.0, 0.0]];
+        for i in 1..=100 {
+            let angle = i as f64 / 100.0 * FRAC_PI_2;
+            let wobble = sweep.range(-0.005, 0.005);
+            let pos = [angle.sin() + wobble, 1.0 - angle.cos() + wobble];
+            teach.sample(pos, angle);
+            driven.push(pos);
+        }
+        for i in 1..=16 {
+            let pos = [1.0, 1.0 - i as f64 * 0.0625];
+            teach.sample(pos, FRAC_PI_2);
+            driven.push(pos);
+        }
+
+        let segments = positions(&teach.segments());
+        // far fewer segments then samples, ending where the robot stopped
+        assert!(segments.len() < 15, "{segments:?}");
+        let end = segments.last().unwrap().1;
+        assert!(close(end[0], 1.0) && close(end[1], 0.0), "ends at {end:?}");
+        assert!(segments.last().unwrap().0, "the last move isn't reversed");
+
+        let route: Vec<Vec2> = std::iter::once([0.0, 0.0])
+            .chain(segments.iter().map(|s| s.1))
+            .map(Vec2::from)
+            .collect();
+        for pos in driven {
+            let off = route
+                .windows(2)
+                .map(|line| line_dist(line[0], line[1], pos.into()))
+                .fold(f64::INFINITY, f64::min);
+            // samples closer then MIN_SPACING together may be dropped
+            assert!(
+                off <= TOLERANCE + MIN_SPACING,
+                "{pos:?} is {off}m off the route"
+            );
+        }
+    }
+
+    #[test]
+    fn saved_routes_parse_back() {
+        let mut teach = Teach::new([0.0, 0.0], 0.0);
+        for i in 1..=10 {
+            teach.sample([i as f64 * 0.1, 0.0], 0.0);
+        }
+        for i in 1..=10 {
+            teach.sample([1.0 - i as f64 * 0.1, i as f64 * 0.1], 0.0);
+        }
+        for seg in teach.segments() {
+            let parsed: MinSegment = format!("{seg:?}").parse().unwrap();
+            assert_eq!(format!("{parsed:?}"), format!("{seg:?}"));
+        }
+    }
+
+    #[test]
+    fn relative_to_the_start_pose() {
+        // starts at (1, 1) facing +y so driving along +y is forwards from the start
+        let mut teach = Teach::new([1.0, 1.0], FRAC_PI_2);
+        for i in 1..=10 {
+            teach.sample([1.0, 1.0 + i as f64 * 0.1], FRAC_PI_2);
+        }
+        let segments = positions(&teach.segments());
+        assert_eq!(segments.len(), 1, "{segments:?}");
+        assert!(!segments[0].0);
+        assert!(close(segments[0].1[0], 1.0) && close(segments[0].1[1], 0.0));
+    }
+}
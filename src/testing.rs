@@ -0,0 +1,93 @@
+// Helpers shared by the unit tests, mostly for running path segments
+// against a robot that doesn't move.
+use std::{f64::consts::TAU, time::Instant};
+
+use protocol::StatusPkt;
+
+use crate::{
+    brain::Brain,
+    imu::HeadingSensor,
+    link::MockLink,
+    odom::{OdomConfig, Odometry, TrackingSource},
+    path::{PathConstraints, RobotCtx},
+};
+
+pub fn close(a: f64, b: f64) -> bool {
+    (a - b).abs() < 1e-9
+}
+
+// true if the angles point the same way (differ by whole turns)
+pub fn same_direction(a: f64, b: f64) -> bool {
+    let diff = (a - b).rem_euclid(TAU);
+    diff < 1e-9 || TAU - diff < 1e-9
+}
+
+// Deterministic pseudo random inputs (xorshift) for sweeping a function
+// over its inputs in property tests, seeded so a failure can be rerun.
+pub struct Sweep(u64);
+
+impl Sweep {
+    pub fn new(seed: u64) -> Self {
+        Self(seed.max(1))
+    }
+    // uniform in [min, max)
+    pub fn range(&mut self, min: f64, max: f64) -> f64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        min + (max - min) * (self.0 >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+// a gyro and tracking wheels that never move
+struct Still;
+
+impl HeadingSensor for Still {
+    fn calc_heading(&mut self) -> f64 {
+        0.0
+    }
+    fn heading(&self) -> f64 {
+        0.0
+    }
+    fn set_heading(&mut self, _heading: f64) {}
+    fn angular_velocity(&self) -> f64 {
+        0.0
+    }
+    fn reset(&mut self) {}
+    fn bias(&self) -> f64 {
+        0.0
+    }
+    fn set_bias(&mut self, _bias: f64) {}
+    fn scale(&self) -> f64 {
+        1.0
+    }
+    fn set_scale(&mut self, _scale: f64) {}
+}
+
+impl TrackingSource for Still {
+    fn calc_distances(&mut self, _dt: f64) {}
+    fn distances(&self) -> [f64; 2] {
+        [0.0; 2]
+    }
+}
+
+// odometry of a robot pinned at the origin
+pub fn still_odometry() -> Odometry {
+    Odometry::with_tracking(Box::new(Still), OdomConfig::default(), Box::new(Still))
+}
+
+// a brain with nothing plugged in and a disabled competition state
+pub fn idle_brain() -> Brain {
+    let status = [(), ()].map(|_| (Instant::now(), StatusPkt::default()));
+    let (link, _) = MockLink::new(status);
+    Brain::with_link(Box::new(link)).0
+}
+
+pub fn ctx<'a>(odom: &'a mut Odometry, brain: &'a Brain) -> RobotCtx<'a> {
+    RobotCtx {
+        odom,
+        brain,
+        auton_start: Instant::now(),
+        constraints: PathConstraints::default(),
+    }
+}